@@ -4,8 +4,11 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use loterra_v2::msg::{
-    ConfigResponse, ExecuteMsg, GameResponse, GameStatsResponse, InstantiateMsg, LotteryResponse,
-    LotteryStatsResponse, MigrateMsg, QueryMsg, StateResponse,
+    ConfigResponse, CurrentRoundResponse, ExecuteMsg, FeeBalanceResponse, GameResponse,
+    GameStatsResponse, GamesResponse, GamesStatsResponse, GiftsResponse, InstantiateMsg,
+    LotteriesResponse, LotteryResponse, LotteryStatsResponse, MigrateMsg, PlayerTicketResponse,
+    PlayerTicketsResponse, QueryMsg, ReferralRewardsResponse, ReferredPlayersResponse,
+    RoundPlayersResponse, StateResponse, SubscriptionsResponse,
 };
 use loterra_v2::state::State;
 
@@ -26,4 +29,16 @@ fn main() {
     export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(GameStatsResponse), &out_dir);
     export_schema(&schema_for!(LotteryStatsResponse), &out_dir);
+    export_schema(&schema_for!(PlayerTicketResponse), &out_dir);
+    export_schema(&schema_for!(GamesResponse), &out_dir);
+    export_schema(&schema_for!(LotteriesResponse), &out_dir);
+    export_schema(&schema_for!(GamesStatsResponse), &out_dir);
+    export_schema(&schema_for!(PlayerTicketsResponse), &out_dir);
+    export_schema(&schema_for!(RoundPlayersResponse), &out_dir);
+    export_schema(&schema_for!(FeeBalanceResponse), &out_dir);
+    export_schema(&schema_for!(GiftsResponse), &out_dir);
+    export_schema(&schema_for!(ReferralRewardsResponse), &out_dir);
+    export_schema(&schema_for!(ReferredPlayersResponse), &out_dir);
+    export_schema(&schema_for!(SubscriptionsResponse), &out_dir);
+    export_schema(&schema_for!(CurrentRoundResponse), &out_dir);
 }
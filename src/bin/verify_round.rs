@@ -0,0 +1,159 @@
+//! Recompute the winning numbers of a round and the prizes of its tickets from the drand beacon,
+//! with the game engine the contract draws and collects with.
+//!
+//! verify_round (--randomness <hex> | --signature <hex>) <player> <state.json> <lottery.json>
+//!     <games.json>
+//!
+//! state.json is the `State` of the contract or the response of the state query, lottery.json
+//! the response of the lottery state query of the round and games.json a page of the games query
+//! of the player, the games of every page can be verified one page after the other. The rules are
+//! the ones of the round, the quick-pick numbers are drawn again from the beacon.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use bech32::{FromBase32, Variant};
+use cosmwasm_std::from_slice;
+use cw_storage_plus::PrimaryKey;
+use loterra_v2::engine::{draw, evaluate, quick_pick_numbers, Outcome, Rules, Ticket};
+use loterra_v2::msg::{GamesResponse, LotteryResponse};
+use loterra_v2::state::LotteryState;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const USAGE: &str = "usage: verify_round (--randomness <hex> | --signature <hex>) <player> \
+                     <state.json> <lottery.json> <games.json>";
+
+/// The part of the state the rounds don't keep
+#[derive(Deserialize)]
+struct State {
+    set_of_balls: u8,
+}
+
+fn main() {
+    if let Err(err) = run(env::args().skip(1).collect()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let (beacon, player, state_path, lottery_path, games_path) = match args.as_slice() {
+        [flag, beacon, player, state, lottery, games] => (
+            beacon_randomness(flag, beacon)?,
+            player.as_str(),
+            state.as_str(),
+            lottery.as_str(),
+            games.as_str(),
+        ),
+        _ => return Err(USAGE.to_string()),
+    };
+    let state: State = read_json(state_path)?;
+    let lottery: LotteryResponse = read_json(lottery_path)?;
+    let games: GamesResponse = read_json(games_path)?;
+    let player_raw = canonical_address(player)?;
+
+    let round = lottery.lottery_id;
+    let rules = Rules::new(state.set_of_balls, &lottery_state(lottery.clone()));
+    let draw = draw(&beacon, &rules).map_err(|err| err.to_string())?;
+    println!(
+        "round {} winning numbers {:?} bonus {}",
+        round, draw.winning_number, draw.bonus_number
+    );
+    if let Some(winning_number) = &lottery.winning_number {
+        if winning_number != &draw.winning_number || lottery.bonus_number != Some(draw.bonus_number)
+        {
+            return Err(format!(
+                "the contract drew {:?} bonus {}",
+                winning_number,
+                lottery.bonus_number.unwrap_or_default()
+            ));
+        }
+    }
+
+    for game in games.games {
+        let end_round = game.end_round.unwrap_or(game.lottery_id);
+        if round < game.lottery_id || round > end_round {
+            println!("game {} doesn't play round {}", game.game_id, round);
+            continue;
+        }
+        // Quick-picks draw their numbers from the round randomness and the game key
+        let (number, bonus) = if game.quick_pick {
+            let game_key = (player_raw.as_slice(), &game.game_id.to_be_bytes()[..]).joined_key();
+            quick_pick_numbers(&beacon, &game_key, state.set_of_balls)
+        } else {
+            (game.number, game.bonus)
+        };
+        let ticket = Ticket {
+            number,
+            bonus,
+            multiplier: game.multiplier,
+        };
+        let outcome = match evaluate(&ticket, &draw, &rules) {
+            Outcome::Lost => "lost".to_string(),
+            Outcome::Won {
+                tier,
+                prize: Some(prize),
+            } => format!("tier {} prize {}", tier, prize),
+            // The pari-mutuel and progressive tiers share a pool between the round winners
+            Outcome::Won { tier, prize: None } => format!("tier {} pool share", tier),
+        };
+        println!(
+            "game {} {:?} bonus {} x{} {}",
+            game.game_id, ticket.number, ticket.bonus, ticket.multiplier, outcome
+        );
+    }
+
+    Ok(())
+}
+
+/// Randomness of the beacon, the drand randomness being the sha256 of its signature
+fn beacon_randomness(flag: &str, beacon: &str) -> Result<Vec<u8>, String> {
+    let bytes = hex::decode(beacon).map_err(|err| format!("invalid beacon hex: {}", err))?;
+    match flag {
+        "--randomness" => Ok(bytes),
+        "--signature" => Ok(Sha256::digest(&bytes).to_vec()),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Lottery of the round as the contract stores it, the parts only kept in storage left empty
+fn lottery_state(lottery: LotteryResponse) -> LotteryState {
+    LotteryState {
+        draw_time: lottery.draw_time,
+        terrand_round: lottery.terrand_round,
+        terrand_worker: None,
+        prize_rank: lottery.prize_rank,
+        ticket_price: lottery.ticket_price,
+        multiplier: lottery.multiplier,
+        winning_number: lottery.winning_number,
+        bonus_number: lottery.bonus_number,
+        randomness: lottery.randomness,
+        prize_mode: lottery.prize_mode,
+        prize_share: lottery.prize_share,
+        jackpot: lottery.jackpot,
+        jackpot_share: lottery.jackpot_share,
+        winners: lottery.winners,
+        resolution_cursor: None,
+        games_counted: false,
+        resolved: lottery.resolved,
+        cancelled: lottery.cancelled,
+        subscriptions_cursor: None,
+        subscriptions_played: false,
+    }
+}
+
+/// Canonical address of a Terra address, the data of its bech32 encoding
+fn canonical_address(address: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid address: {}", address);
+    match bech32::decode(address) {
+        Ok((_, data, Variant::Bech32)) => Vec::<u8>::from_base32(&data).map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    from_slice(&data).map_err(|err| format!("{}: {}", path, err))
+}
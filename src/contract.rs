@@ -1,24 +1,44 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Addr, Attribute, BankMsg, Binary, Coin, CosmosMsg, Decimal, Deps, DepsMut, Env,
-    MessageInfo, Order, Response, StdResult, SubMsg, Uint128, WasmQuery,
+    to_binary, Addr, Attribute, BankMsg, Binary, CanonicalAddr, Coin, CosmosMsg, Decimal, Deps,
+    DepsMut, Empty, Env, Event, MessageInfo, Order, Response, StdResult, Storage, SubMsg, Uint128,
+    WasmMsg, WasmQuery,
 };
-use cw2::set_contract_version;
-use cw_storage_plus::Bound;
+use cw2::{get_contract_version, set_contract_version};
+use cw721::{Cw721QueryMsg, OwnerOfResponse};
+use cw721_base::msg::{ExecuteMsg as Cw721BaseExecuteMsg, MintMsg};
+use cw_storage_plus::{Bound, PrimaryKey};
+use semver::Version;
 use std::convert::TryInto;
 use std::ops::Mul;
-use std::str::FromStr;
 
+use crate::engine::{draw, evaluate, Draw, Outcome, Rules, Ticket};
 use crate::error::ContractError;
-use crate::helpers::{bonus_number, count_match, save_game, winning_number};
+use crate::events::{
+    FeeKind, FeePaid, PrizeCollected, ReferralRewardsClaimed, RefundClaimed, RoundAborted,
+    RoundDrawn, SubscriptionCancelled, TicketCancelled, TicketRegistered,
+};
+use crate::helpers::{
+    add_collector_fee, add_fee, add_pending_referral_rewards, drawn_game, drawn_referral_rewards,
+    game_key, game_price, new_game, open_round_stats, register_game,
+    remove_pending_referral_rewards, remove_round_ticket, remove_sales, remove_ticket_range,
+    round_jackpot, split_game_key, ticket_multiplier, ticket_prize,
+};
+use crate::migrations::migrate_storage;
 use crate::msg::{
-    ConfigResponse, ExecuteMsg, GameResponse, GameStatsResponse, InstantiateMsg, LotteryResponse,
-    LotteryStatsResponse, MigrateMsg, QueryMsg, StateResponse,
+    ConfigResponse, CurrentRoundResponse, ExecuteMsg, FeeBalanceResponse, FeeRecipientMsg,
+    GameResponse, GameStatsResponse, GamesResponse, GamesStatsResponse, GiftResponse,
+    GiftsResponse, InstantiateMsg, LotteriesResponse, LotteryResponse, LotteryStatsResponse,
+    MigrateMsg, OrderBy, PlayerTicketResponse, PlayerTicketsResponse, QueryMsg,
+    ReferralRewardsResponse, ReferredPlayersResponse, RoundPlayersResponse, StakingExecuteMsg,
+    StateResponse, SubscriptionResponse, SubscriptionsResponse, TicketStatus,
 };
 use crate::state::{
-    BallsRange, Config, GameStats, LotteryState, LotteryStats, State, CONFIG, GAMES, GAMES_STATS,
-    LOTTERY_STATE, LOTTERY_STATS, STATE,
+    BallsRange, Config, FeeRecipient, Game, LotteryState, PrizeMode, State, Subscription, CONFIG,
+    FEE_BALANCES, GAMES, GAMES_STATS, GIFTS, LOTTERY_STATE, LOTTERY_STATS, MULTI_ROUND_GAMES,
+    PENDING_REFERRAL_REWARDS, REFERRAL_REWARDS, REFERRED_PLAYERS, REFUND_CLAIMS, ROUND_PLAYERS,
+    STATE, SUBSCRIPTIONS, SUBSCRIPTION_COUNT,
 };
 use crate::taxation::deduct_tax;
 // version info for migration info
@@ -27,6 +47,9 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 const DRAND_GENESIS_TIME: u64 = 1595431050;
 const DRAND_PERIOD: u64 = 30;
 const DRAND_NEXT_ROUND_SECURITY: u64 = 3;
+const MAX_MEMO_LENGTH: usize = 64;
+/// Delay after the draw time from which anyone can abort a round terrand didn't deliver
+const ABORT_TIMEOUT: u64 = 86_400;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -43,6 +66,15 @@ pub fn instantiate(
         fee_collector_terrand: msg.fee_collector_terrand,
         terrand_address: deps.api.addr_canonicalize(&msg.terrand_address)?,
         live_round_max: msg.live_round_max,
+        jackpot_share: msg.jackpot_share,
+        cancel_fee: msg.cancel_fee,
+        referral_share: msg.referral_share,
+        fee_model: msg.fee_model,
+        fee_recipients: fee_recipients(deps.as_ref(), msg.fee_recipients)?,
+        staking_address: optional_addr(deps.as_ref(), msg.staking_address)?,
+        tax_mode: msg.tax_mode,
+        ticket_nft: optional_addr(deps.as_ref(), msg.ticket_nft)?,
+        owner: Some(deps.api.addr_canonicalize(info.sender.as_str())?),
     };
 
     let state = State {
@@ -60,7 +92,10 @@ pub fn instantiate(
         prize_rank: msg.prize_rank.clone(),
         ticket_price: msg.ticket_price.clone(),
         multiplier: msg.multiplier.clone(),
+        prize_mode: msg.prize_mode.clone(),
+        prize_share: msg.prize_share.clone(),
     };
+    validate_config(&config, &state)?;
 
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     STATE.save(deps.storage, &state)?;
@@ -87,6 +122,18 @@ pub fn instantiate(
             multiplier: msg.multiplier,
             winning_number: None,
             bonus_number: None,
+            randomness: None,
+            prize_mode: msg.prize_mode,
+            prize_share: msg.prize_share,
+            jackpot: Uint128::zero(),
+            jackpot_share: config.jackpot_share,
+            winners: vec![],
+            resolution_cursor: None,
+            games_counted: false,
+            resolved: false,
+            cancelled: false,
+            subscriptions_cursor: None,
+            subscriptions_played: false,
         },
     )?;
 
@@ -95,6 +142,91 @@ pub fn instantiate(
         .add_attribute("owner", info.sender))
 }
 
+fn optional_addr(deps: Deps, address: Option<String>) -> StdResult<Option<CanonicalAddr>> {
+    address
+        .map(|address| {
+            let address = deps.api.addr_validate(&address)?;
+            deps.api.addr_canonicalize(address.as_str())
+        })
+        .transpose()
+}
+
+fn fee_recipients(deps: Deps, recipients: Vec<FeeRecipientMsg>) -> StdResult<Vec<FeeRecipient>> {
+    recipients
+        .into_iter()
+        .map(|recipient| {
+            let address = deps.api.addr_validate(&recipient.address)?;
+            Ok(FeeRecipient {
+                address: deps.api.addr_canonicalize(address.as_str())?,
+                share: recipient.share,
+            })
+        })
+        .collect()
+}
+
+fn validate_config(config: &Config, state: &State) -> Result<(), ContractError> {
+    // Tickets play at least their round, the refunds look that many rounds back
+    if config.live_round_max == 0 {
+        return Err(ContractError::LiveRoundMaxZero {});
+    }
+    if config.jackpot_share > Decimal::one() {
+        return Err(ContractError::JackpotShareTooHigh {});
+    }
+    if config.cancel_fee > Decimal::one() {
+        return Err(ContractError::CancelFeeTooHigh {});
+    }
+    // The recipients split the whole fee collector share by their part of it, so their parts
+    // add up to 1 and the share can't be zero
+    if !config.fee_recipients.is_empty()
+        && (config.fee_collector.is_zero()
+            || config
+                .fee_recipients
+                .iter()
+                .fold(Decimal::zero(), |total, recipient| total + recipient.share)
+                != config.fee_collector)
+    {
+        return Err(ContractError::InvalidFeeRecipients {});
+    }
+    // DistributeFees sends the fee balance of the staking contract, it must earn the fees
+    if let Some(staking_address) = &config.staking_address {
+        let receives_fees = if config.fee_recipients.is_empty() {
+            staking_address == &config.fee_collector_address
+        } else {
+            config
+                .fee_recipients
+                .iter()
+                .any(|recipient| &recipient.address == staking_address)
+        };
+        if !receives_fees {
+            return Err(ContractError::StakingNotFeeRecipient {});
+        }
+    }
+    if config.referral_share > config.fee_collector {
+        return Err(ContractError::ReferralShareTooHigh {});
+    }
+    // The rewards are paid out of the fees on sales, the fees on winnings may never cover them
+    if !config.referral_share.is_zero() && !config.fee_model.on_sales() {
+        return Err(ContractError::ReferralWithoutFeeOnSales {});
+    }
+    if state.prize_mode == PrizeMode::PariMutuel {
+        if state.prize_share.len() != state.prize_rank.len() {
+            return Err(ContractError::InvalidPrizeShare {});
+        }
+        let mut total_share = state
+            .prize_share
+            .iter()
+            .fold(config.jackpot_share, |total, share| total + *share);
+        if config.fee_model.on_sales() {
+            total_share = total_share + config.fee_collector + config.fee_collector_terrand;
+        }
+        if total_share > Decimal::one() {
+            return Err(ContractError::InvalidPrizeShare {});
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -108,24 +240,96 @@ pub fn execute(
             multiplier,
             live_round,
             address,
-        } => try_register(deps, env, info, numbers, multiplier, live_round, address),
+            memo,
+            referrer,
+        } => try_register(
+            deps, env, info, numbers, multiplier, live_round, address, memo, referrer,
+        ),
+        ExecuteMsg::WithdrawFees {} => try_withdraw_fees(deps, env, info),
+        ExecuteMsg::DistributeFees {} => try_distribute_fees(deps, env, info),
+        ExecuteMsg::ClaimReferralRewards { limit } => {
+            try_claim_referral_rewards(deps, env, info, limit)
+        }
         ExecuteMsg::Draw {} => try_draw(deps, env, info),
         ExecuteMsg::Collect {
             round,
             player,
             game_id,
         } => try_collect(deps, env, info, round, player, game_id),
+        ExecuteMsg::Resolve { round, limit } => try_resolve(deps, env, info, round, limit),
+        ExecuteMsg::PlaySubscriptions { limit } => try_play_subscriptions(deps, env, info, limit),
+        ExecuteMsg::Subscribe {
+            numbers,
+            multiplier,
+            rounds,
+            address,
+        } => try_subscribe(deps, env, info, numbers, multiplier, rounds, address),
+        ExecuteMsg::CancelTicket {
+            start_round,
+            game_id,
+        } => try_cancel_ticket(deps, env, info, start_round, game_id),
+        ExecuteMsg::AbortRound { round } => try_abort_round(deps, env, info, round),
+        ExecuteMsg::ClaimRefund { round, player } => {
+            try_claim_refund(deps, env, info, round, player)
+        }
+        ExecuteMsg::CancelSubscription { subscription_id } => {
+            try_cancel_subscription(deps, env, info, subscription_id)
+        }
     }
 }
 
+fn sent_funds(config: &Config, info: &MessageInfo) -> Result<Uint128, ContractError> {
+    match info.funds.len() {
+        0 => Err(ContractError::EmptyFunds {}),
+        1 => {
+            if info.funds[0].denom != config.denom {
+                return Err(ContractError::WrongDenom {});
+            }
+            Ok(info.funds[0].amount)
+        }
+        _ => Err(ContractError::MultipleDenoms {}),
+    }
+}
+
+/// Check there are as many balls as the set of balls followed by the bonus in the bonus range
+fn validate_numbers(state: &State, numbers: &[u8]) -> Result<(), ContractError> {
+    let balls = usize::from(state.set_of_balls);
+    if numbers.len() != balls + usize::from(state.bonus_set_of_balls) {
+        return Err(ContractError::InvalidNumbers(
+            state.set_of_balls,
+            state.bonus_set_of_balls,
+        ));
+    }
+    let (balls, bonus) = numbers.split_at(balls);
+    if balls
+        .iter()
+        .any(|ball| *ball < state.range.min || *ball > state.range.max)
+    {
+        return Err(ContractError::InvalidNumbers(
+            state.set_of_balls,
+            state.bonus_set_of_balls,
+        ));
+    }
+    if bonus
+        .iter()
+        .any(|ball| *ball < state.bonus_range.min || *ball > state.bonus_range.max)
+    {
+        return Err(ContractError::BonusOutOfRange {});
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn try_register(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    numbers: Vec<u8>,
+    numbers: Option<Vec<u8>>,
     multiplier: Uint128,
     live_round: u16,
     address: Option<String>,
+    memo: Option<String>,
+    referrer: Option<String>,
 ) -> Result<Response, ContractError> {
     let state = STATE.load(deps.storage)?;
     let config = CONFIG.load(deps.storage)?;
@@ -135,25 +339,31 @@ pub fn try_register(
         return Err(ContractError::RegisterClosed {});
     }
 
-    let sent = match info.funds.len() {
-        0 => Err(ContractError::EmptyFunds {}),
-        1 => {
-            if info.funds[0].denom != config.denom {
-                return Err(ContractError::WrongDenom {});
-            }
-            Ok(info.funds[0].amount)
-        }
-        _ => Err(ContractError::MultipleDenoms {}),
-    }?;
+    let sent = sent_funds(&config, &info)?;
 
     if live_round == 0 || live_round > config.live_round_max {
         return Err(ContractError::LiveRoundMaxLifeExceeded {});
     }
 
+    if memo.as_deref().map_or(0, |memo| memo.chars().count()) > MAX_MEMO_LENGTH {
+        return Err(ContractError::MemoTooLong(MAX_MEMO_LENGTH));
+    }
+
+    let sender_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
     let address_raw = match address {
-        None => deps.api.addr_canonicalize(&info.sender.as_str())?,
+        None => sender_raw.clone(),
         Some(address) => deps.api.addr_canonicalize(&address)?,
     };
+    let referrer_raw = match referrer {
+        None => None,
+        Some(referrer) => {
+            let referrer_addr = deps.api.addr_validate(&referrer)?;
+            Some(deps.api.addr_canonicalize(referrer_addr.as_str())?)
+        }
+    };
+    if referrer_raw.as_ref() == Some(&sender_raw) || referrer_raw.as_ref() == Some(&address_raw) {
+        return Err(ContractError::InvalidReferrer {});
+    }
 
     //let price_per_ticket = sent.div(Uint128::from(numbers.len() as u128));
     //let price_per_round = sent.div(Uint128::from(live_round as u128));
@@ -172,1596 +382,6757 @@ pub fn try_register(
     //let multiplier = Decimal::from_ratio(tiers[0], Uint128::from(1_000_000u128));
 
     // Get the multiplier
-    let multiplier_decimal = match multiplier.u128() {
-        1_000_000 => lottery.multiplier[0],
-        2_000_000 => lottery.multiplier[1],
-        5_000_000 => lottery.multiplier[2],
-        _ => {
-            return Err(ContractError::ErrorTierDetermination {});
-        }
-    };
+    let multiplier_decimal = ticket_multiplier(&lottery, multiplier)?;
 
-    let expected_amount = state.ticket_price[0]
-        .mul(multiplier_decimal)
-        .mul(Decimal::from_str(&live_round.to_string()).unwrap());
+    let round_price = state.ticket_price[0].mul(multiplier_decimal);
+    let expected_amount = round_price.checked_mul(Uint128::from(live_round)).unwrap();
     if sent != expected_amount {
         return Err(ContractError::AmountSentError(sent, expected_amount));
     };
 
-    let mut new_number = vec![];
-    // Check if duplicate numbers
-    //for mut number in numbers.clone() {
-    let mut new_arr = numbers.clone();
-    let bonus_number = numbers.last().unwrap();
-    // Handle the bonus number is in the range
-    if bonus_number > &state.bonus_range.max || bonus_number < &state.bonus_range.min {
-        return Err(ContractError::BonusOutOfRange {});
+    if let Some(numbers) = &numbers {
+        validate_numbers(&state, numbers)?;
     }
-    new_arr.retain(|&x| &x != bonus_number);
-    // new_arr.sort();
-    // new_arr.dedup();
-    //
-    // if new_arr.len() as u8 != state.set_of_balls {
-    //     return Err(ContractError::WrongSetOfBallsOrDuplicateNotAllowed {});
-    // }
-
-    new_number.push(new_arr);
-    //}
-    let mut rounds_info = vec![];
-    for round in state.round..state.round.checked_add(u64::from(live_round)).unwrap() {
-        rounds_info.push(round.to_string());
-        match GAMES_STATS.may_load(
-            deps.storage,
-            (&address_raw.as_slice(), &round.to_be_bytes()),
-        )? {
-            None => {
-                save_game(
-                    deps.storage,
-                    round,
-                    &address_raw,
-                    numbers.clone(),
-                    multiplier_decimal,
-                    None,
-                )?;
-                GAMES_STATS.save(
-                    deps.storage,
-                    (&address_raw.as_slice(), &round.to_be_bytes()),
-                    &GameStats {
-                        total_ticket: 1,
-                        total_spent: multiplier,
-                    },
-                )?;
-
-                match LOTTERY_STATS.may_load(deps.storage, &round.to_be_bytes())? {
-                    None => {
-                        LOTTERY_STATS.save(
-                            deps.storage,
-                            &round.to_be_bytes(),
-                            &LotteryStats {
-                                counter_player: Some(1),
-                                total_ticket_sold: Some(1),
-                                total_collected: Some(multiplier),
-                            },
-                        )?;
-                    }
-                    Some(_) => {
-                        LOTTERY_STATS.update(
-                            deps.storage,
-                            &round.to_be_bytes(),
-                            |lottery_stats| -> Result<_, ContractError> {
-                                let mut update_lottery_stats = lottery_stats.unwrap();
-
-                                if update_lottery_stats.counter_player.is_none() {
-                                    update_lottery_stats.counter_player = Some(1);
-                                } else {
-                                    update_lottery_stats.counter_player =
-                                        update_lottery_stats.counter_player.unwrap().checked_add(1);
-                                }
-
-                                if update_lottery_stats.total_ticket_sold.is_none() {
-                                    update_lottery_stats.total_ticket_sold = Some(1);
-                                } else {
-                                    update_lottery_stats.total_ticket_sold = update_lottery_stats
-                                        .total_ticket_sold
-                                        .unwrap()
-                                        .checked_add(1);
-                                }
-                                if update_lottery_stats.total_collected.is_none() {
-                                    update_lottery_stats.total_collected = Some(multiplier);
-                                } else {
-                                    update_lottery_stats.total_collected = Some(
-                                        update_lottery_stats
-                                            .total_collected
-                                            .unwrap()
-                                            .checked_add(multiplier)
-                                            .unwrap(),
-                                    );
-                                }
-
-                                Ok(update_lottery_stats)
-                            },
-                        )?;
-                    }
-                }
-            }
-            Some(game_stats) => {
-                save_game(
-                    deps.storage,
-                    round,
-                    &address_raw,
-                    numbers.clone(),
-                    multiplier_decimal,
-                    Some(game_stats),
-                )?;
-
-                GAMES_STATS.update(
-                    deps.storage,
-                    (&address_raw.as_slice(), &round.to_be_bytes()),
-                    |game_stats| -> Result<_, ContractError> {
-                        let mut update_game_stats = game_stats.unwrap();
-                        update_game_stats.total_spent = update_game_stats
-                            .total_spent
-                            .checked_add(multiplier)
-                            .unwrap();
-                        update_game_stats.total_ticket += 1;
-                        Ok(update_game_stats)
-                    },
-                )?;
-
-                match LOTTERY_STATS.may_load(deps.storage, &round.to_be_bytes())? {
-                    None => {
-                        LOTTERY_STATS.save(
-                            deps.storage,
-                            &round.to_be_bytes(),
-                            &LotteryStats {
-                                counter_player: Some(1),
-                                total_ticket_sold: Some(1),
-                                total_collected: Some(multiplier),
-                            },
-                        )?;
-                    }
-                    Some(_) => {
-                        LOTTERY_STATS.update(
-                            deps.storage,
-                            &round.to_be_bytes(),
-                            |lottery_stats| -> Result<_, ContractError> {
-                                let mut update_lottery_stats = lottery_stats.unwrap();
-
-                                if update_lottery_stats.total_ticket_sold.is_none() {
-                                    update_lottery_stats.total_ticket_sold = Some(1);
-                                } else {
-                                    update_lottery_stats.total_ticket_sold = update_lottery_stats
-                                        .total_ticket_sold
-                                        .unwrap()
-                                        .checked_add(1);
-                                }
-                                if update_lottery_stats.total_collected.is_none() {
-                                    update_lottery_stats.total_collected = Some(multiplier);
-                                } else {
-                                    update_lottery_stats.total_collected = Some(
-                                        update_lottery_stats
-                                            .total_collected
-                                            .unwrap()
-                                            .checked_add(multiplier)
-                                            .unwrap(),
-                                    );
-                                }
-
-                                Ok(update_lottery_stats)
-                            },
-                        )?;
-                    }
-                }
-            }
+    // A single game plays every live round, the next rounds are accounted through the deltas
+    let end_round = state.round.checked_add(u64::from(live_round) - 1).unwrap();
+    let mut game = new_game(
+        numbers.clone(),
+        state.set_of_balls,
+        multiplier_decimal,
+        if live_round > 1 {
+            Some(end_round)
+        } else {
+            None
+        },
+    );
+    // Gifts are indexed by buyer
+    game.buyer = if address_raw != sender_raw {
+        Some(sender_raw)
+    } else {
+        None
+    };
+    game.memo = memo;
+    // The reward is earned round by round once drawn, it is not paid for the cancelled rounds
+    let referral_reward = match &referrer_raw {
+        None => Uint128::zero(),
+        Some(_) => round_price.mul(config.referral_share),
+    };
+    game.referrer = referrer_raw.clone();
+    game.referral_reward = referral_reward;
+    let game_id = register_game(
+        deps.storage,
+        state.round,
+        &address_raw,
+        game,
+        round_price,
+        &config,
+    )?;
+    let recipient = deps.api.addr_humanize(&address_raw)?.to_string();
+    let mut res = Response::new().add_event(
+        TicketRegistered {
+            round: state.round,
+            end_round,
+            player: &recipient,
+            buyer: info.sender.as_str(),
+            game_id,
+            numbers: numbers.as_deref(),
+            multiplier: multiplier_decimal,
+            amount: sent,
         }
+        .into(),
+    );
+    if let Some(ticket_nft) = &config.ticket_nft {
+        res = res.add_message(mint_ticket(
+            deps.as_ref(),
+            ticket_nft,
+            state.round,
+            &recipient,
+            game_id,
+        )?);
+    }
+    if let Some(referrer_raw) = referrer_raw {
+        add_pending_referral_rewards(
+            deps.storage,
+            &referrer_raw,
+            state.round,
+            end_round,
+            &game_key(address_raw.as_slice(), game_id),
+            referral_reward,
+        )?;
+        let reward = referral_reward
+            .checked_mul(Uint128::from(live_round))
+            .unwrap();
+        REFERRED_PLAYERS.save(
+            deps.storage,
+            (referrer_raw.as_slice(), address_raw.as_slice()),
+            &Empty {},
+        )?;
+        res = res
+            .add_attribute("referrer", deps.api.addr_humanize(&referrer_raw)?)
+            .add_attribute("referral_reward", reward.to_string());
     }
+    let rounds_info = (state.round..=end_round)
+        .map(|round| round.to_string())
+        .collect::<Vec<String>>();
 
-    Ok(Response::new()
+    Ok(res
         .add_attribute("method", "try_register")
         .add_attribute("round", format!("[{}]", rounds_info.join(", ")))
         .add_attribute("live_round", live_round.to_string())
         .add_attribute("ticket_amount", "1".to_string())
         .add_attribute("sender", info.sender)
-        .add_attribute(
-            "recipient",
-            deps.api.addr_humanize(&address_raw)?.to_string(),
-        ))
+        .add_attribute("recipient", recipient))
 }
 
-pub fn try_draw(deps: DepsMut, env: Env, _info: MessageInfo) -> Result<Response, ContractError> {
-    let mut state = STATE.load(deps.storage)?;
-    let config = CONFIG.load(deps.storage)?;
-    let lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
-    let lottery_stats = LOTTERY_STATS.may_load(deps.storage, &state.round.to_be_bytes())?;
+/// Token id of the ticket NFT of a game, made of its `GAMES` key
+pub fn ticket_token_id(round: u64, player: &str, game_id: u64) -> String {
+    format!("{}/{}/{}", round, player, game_id)
+}
 
-    if lottery.draw_time > env.block.time.seconds() {
-        return Err(ContractError::LotteryInProgress {});
-    }
+fn mint_ticket(
+    deps: Deps,
+    ticket_nft: &CanonicalAddr,
+    round: u64,
+    player: &str,
+    game_id: u64,
+) -> StdResult<CosmosMsg> {
+    let token_id = ticket_token_id(round, player, game_id);
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: deps.api.addr_humanize(ticket_nft)?.to_string(),
+        msg: to_binary(&Cw721BaseExecuteMsg::Mint(MintMsg {
+            name: format!("LoTerra ticket {}", token_id),
+            token_id,
+            owner: player.to_string(),
+            description: None,
+            image: None,
+        }))?,
+        funds: vec![],
+    }))
+}
 
-    let mut msgs = vec![];
-    if lottery_stats.is_some() {
-        // Query terrand for the randomness
-        let msg = terrand::msg::QueryMsg::GetRandomness {
-            round: lottery.terrand_round,
-        };
-        let terrand_human = deps.api.addr_humanize(&config.terrand_address)?;
-        let query = WasmQuery::Smart {
-            contract_addr: terrand_human.to_string(),
-            msg: to_binary(&msg)?,
+/// Owner of the ticket NFTs of the games, which must all be the same. The games bought before
+/// the NFTs were enabled have no token, they stay with the player
+fn ticket_owner(
+    deps: Deps,
+    ticket_nft: &CanonicalAddr,
+    round: u64,
+    player: &str,
+    games: &[(u64, bool)],
+) -> Result<String, ContractError> {
+    let contract_addr = deps.api.addr_humanize(ticket_nft)?.to_string();
+    let mut owner: Option<String> = None;
+    for &(game_id, minted) in games {
+        let game_owner = if minted {
+            let query = WasmQuery::Smart {
+                contract_addr: contract_addr.clone(),
+                msg: to_binary(&Cw721QueryMsg::OwnerOf {
+                    token_id: ticket_token_id(round, player, game_id),
+                    include_expired: None,
+                })?,
+            };
+            deps.querier.query::<OwnerOfResponse>(&query.into())?.owner
+        } else {
+            player.to_string()
         };
-        let terrand_randomness: terrand::msg::GetRandomResponse =
-            deps.querier.query(&query.into())?;
-        let randomness_hash: String = hex::encode(terrand_randomness.randomness.as_slice());
-        // let x = random_number(randomness_hash.clone(), state.set_of_balls, state.range.max);
+        match &owner {
+            Some(owner) if owner != &game_owner => {
+                return Err(ContractError::TicketOwnersDiffer {})
+            }
+            _ => owner = Some(game_owner),
+        }
+    }
+    Ok(owner.unwrap_or_else(|| player.to_string()))
+}
 
-        let numbers: Vec<_> = randomness_hash.chars().collect();
-        let winning_number = winning_number(numbers.clone())?;
-        let bonus_number = bonus_number(numbers.last().unwrap())?;
+pub fn try_withdraw_fees(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let recipient_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let amount = FEE_BALANCES
+        .may_load(deps.storage, recipient_raw.as_slice())?
+        .unwrap_or_default();
+    if amount.is_zero() {
+        return Err(ContractError::NoFeesToWithdraw {});
+    }
+    FEE_BALANCES.remove(deps.storage, recipient_raw.as_slice());
 
-        let worker_raw = deps.api.addr_canonicalize(&terrand_randomness.worker)?;
-        // Update lottery winning and bonus number
-        LOTTERY_STATE.update(
-            deps.storage,
-            &state.round.to_be_bytes(),
-            |lottery_state| -> Result<_, ContractError> {
-                let mut update_lottery_state = lottery_state.unwrap();
-                update_lottery_state.winning_number = Some(winning_number);
-                update_lottery_state.bonus_number = Some(bonus_number);
-                update_lottery_state.terrand_worker = Some(worker_raw);
-                Ok(update_lottery_state)
+    let msg_fees = CosmosMsg::Bank(BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![deduct_tax(
+            &deps.querier,
+            &config.tax_mode,
+            Coin {
+                denom: config.denom,
+                amount,
             },
-        )?;
+        )?],
+    });
 
-        let randomness_fee = CosmosMsg::Bank(BankMsg::Send {
-            to_address: terrand_randomness.worker,
-            amount: vec![deduct_tax(
-                &deps.querier,
-                Coin {
-                    denom: config.denom.clone(),
-                    amount: Uint128::from(1_000_000u128),
-                },
-            )?],
-        });
+    Ok(Response::new()
+        .add_message(msg_fees)
+        .add_event(
+            FeePaid {
+                recipient: info.sender.as_str(),
+                amount,
+                kind: FeeKind::Withdraw,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_withdraw_fees")
+        .add_attribute("amount", amount.to_string()))
+}
 
-        msgs.push(randomness_fee)
+pub fn try_distribute_fees(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let staking_raw = match config.staking_address {
+        None => return Err(ContractError::StakingNotConfigured {}),
+        Some(staking_raw) => staking_raw,
+    };
+    let amount = FEE_BALANCES
+        .may_load(deps.storage, staking_raw.as_slice())?
+        .unwrap_or_default();
+    if amount.is_zero() {
+        return Err(ContractError::NoFeesToWithdraw {});
     }
+    FEE_BALANCES.remove(deps.storage, staking_raw.as_slice());
 
-    // Update state & save
-    state.round += 1;
-    STATE.save(deps.storage, &state)?;
+    // The staking contract credits its stakers in the same message it receives the fees
+    let staking = deps.api.addr_humanize(&staking_raw)?.to_string();
+    let msg_deposit = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: staking.clone(),
+        msg: to_binary(&StakingExecuteMsg::DepositRewards {})?,
+        funds: vec![deduct_tax(
+            &deps.querier,
+            &config.tax_mode,
+            Coin {
+                denom: config.denom,
+                amount,
+            },
+        )?],
+    });
 
-    // calculate next round randomness from now
-    let draw_time = env.block.time.plus_seconds(config.frequency).seconds();
-    let from_genesis = draw_time.checked_sub(DRAND_GENESIS_TIME).unwrap();
-    let next_round = from_genesis
-        .checked_div(DRAND_PERIOD)
-        .unwrap()
-        .checked_add(DRAND_NEXT_ROUND_SECURITY)
-        .unwrap();
+    Ok(Response::new()
+        .add_message(msg_deposit)
+        .add_event(
+            FeePaid {
+                recipient: &staking,
+                amount,
+                kind: FeeKind::Staking,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_distribute_fees")
+        .add_attribute("amount", amount.to_string()))
+}
 
-    // Create new lottery
-    LOTTERY_STATE.save(
+const DEFAULT_REFERRAL_LIMIT: u32 = 100;
+const MAX_REFERRAL_LIMIT: u32 = 500;
+
+pub fn try_claim_referral_rewards(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let referrer_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let mut rewards = REFERRAL_REWARDS
+        .may_load(deps.storage, referrer_raw.as_slice())?
+        .unwrap_or_default();
+
+    // The rewards of the drawn rounds are earned by batch from the cursor of the referrer, they
+    // were withheld from the collector fee of their round when drawn
+    let limit = limit
+        .unwrap_or(DEFAULT_REFERRAL_LIMIT)
+        .min(MAX_REFERRAL_LIMIT) as usize;
+    let start_after = rewards.claim_cursor.clone();
+    let drawn = drawn_referral_rewards(
         deps.storage,
-        &state.round.to_be_bytes(),
-        &LotteryState {
-            draw_time,
-            terrand_round: next_round,
-            terrand_worker: None,
-            prize_rank: state.prize_rank,
-            ticket_price: state.ticket_price,
-            multiplier: state.multiplier,
-            winning_number: None,
-            bonus_number: None,
-        },
+        &referrer_raw,
+        state.round,
+        start_after.clone(),
+        limit,
     )?;
+    rewards.claim_cursor = if drawn.len() == limit {
+        drawn.last().map(|(key, _, _)| key.clone())
+    } else {
+        None
+    };
+    let mut earned = Uint128::zero();
+    for (key, mut pending, reward) in drawn {
+        let end_round = u64::from_be_bytes(key[..8].try_into().unwrap());
+        if end_round < state.round {
+            PENDING_REFERRAL_REWARDS.remove(deps.storage, (referrer_raw.as_slice(), &key));
+        } else if pending.first_round < state.round {
+            pending.first_round = state.round;
+            PENDING_REFERRAL_REWARDS.save(
+                deps.storage,
+                (referrer_raw.as_slice(), &key),
+                &pending,
+            )?;
+        }
+        earned = earned.checked_add(reward).unwrap();
+    }
+    rewards.balance = rewards.balance.checked_add(earned).unwrap();
+    rewards.total_earned = rewards.total_earned.checked_add(earned).unwrap();
+
+    // A batch earning nothing still moves the cursor, only a claim over all the pending
+    // rewards fails
+    if rewards.balance.is_zero() {
+        if start_after.is_none() && rewards.claim_cursor.is_none() {
+            return Err(ContractError::NoReferralRewards {});
+        }
+        REFERRAL_REWARDS.save(deps.storage, referrer_raw.as_slice(), &rewards)?;
+        return Ok(Response::new()
+            .add_attribute("method", "try_claim_referral_rewards")
+            .add_attribute("amount", Uint128::zero().to_string()));
+    }
+    let amount = rewards.balance;
+    rewards.balance = Uint128::zero();
+    REFERRAL_REWARDS.save(deps.storage, referrer_raw.as_slice(), &rewards)?;
+
+    let msg_rewards = CosmosMsg::Bank(BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![deduct_tax(
+            &deps.querier,
+            &config.tax_mode,
+            Coin {
+                denom: config.denom,
+                amount,
+            },
+        )?],
+    });
 
     Ok(Response::new()
-        .add_messages(msgs)
-        .add_attribute("method", "try_draw")
-        .add_attribute("round", state.round.checked_sub(1).unwrap().to_string()))
+        .add_message(msg_rewards)
+        .add_event(
+            ReferralRewardsClaimed {
+                referrer: info.sender.as_str(),
+                amount,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_claim_referral_rewards")
+        .add_attribute("amount", amount.to_string()))
 }
 
-pub fn try_collect(
+pub fn try_cancel_ticket(
     deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
-    round: u64,
-    player: String,
-    game_id: Vec<u64>,
+    env: Env,
+    info: MessageInfo,
+    start_round: u64,
+    game_id: u64,
 ) -> Result<Response, ContractError> {
     let state = STATE.load(deps.storage)?;
     let config = CONFIG.load(deps.storage)?;
-    let player_raw = deps
-        .api
-        .addr_canonicalize(&Addr::unchecked(player.clone()).as_str())?;
-    let lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
+    let player_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let key = (
+        &start_round.to_be_bytes()[..],
+        player_raw.as_slice(),
+        &game_id.to_be_bytes()[..],
+    );
+    let mut game = GAMES.load(deps.storage, key)?;
+    // The token can't be burned, it would still be traded once its game is cancelled. The games
+    // bought before the NFTs were enabled have no token
+    if game.minted {
+        return Err(ContractError::TicketNftNotCancellable {});
+    }
 
-    if lottery.winning_number.is_none() && lottery.bonus_number.is_none() {
-        return Err(ContractError::LotteryInProgress {});
+    // The open round can't be cancelled once the registration is closed, its randomness can be
+    // known already
+    let open_round = if lottery.draw_time < env.block.time.seconds() {
+        state.round.checked_add(1).unwrap()
+    } else {
+        state.round
+    };
+    let first_round = open_round.max(start_round);
+    let end_round = game.end_round.unwrap_or(start_round);
+    if first_round > end_round {
+        return Err(ContractError::NothingToCancel {});
     }
 
-    let mut total_amount_to_send = Uint128::zero();
-    for id in game_id {
-        let game = GAMES.load(
+    if let Some(referrer) = &game.referrer {
+        remove_pending_referral_rewards(
             deps.storage,
-            (
-                &round.to_be_bytes(),
-                &player_raw.as_slice(),
-                &id.to_be_bytes(),
-            ),
+            referrer,
+            start_round,
+            end_round,
+            &game_key(player_raw.as_slice(), game_id),
+            first_round,
+        )?;
+    }
+
+    let price = game_price(deps.storage, start_round, &game)?;
+    let rounds = end_round - first_round + 1;
+    let refund = price.checked_mul(Uint128::from(rounds)).unwrap();
+    let fee = refund.mul(config.cancel_fee);
+
+    // The open round counts the ticket already, the next rounds count it through the deltas
+    if first_round == state.round {
+        LOTTERY_STATS.update(
+            deps.storage,
+            &state.round.to_be_bytes(),
+            |lottery_stats| -> Result<_, ContractError> {
+                let mut update_lottery_stats = lottery_stats.unwrap_or_default();
+                update_lottery_stats.total_ticket_sold -= 1;
+                remove_sales(&mut update_lottery_stats, price, &config);
+                Ok(update_lottery_stats)
+            },
         )?;
+    }
+    if game.end_round.is_some() {
+        remove_ticket_range(
+            deps.storage,
+            state.round,
+            start_round,
+            end_round,
+            first_round,
+            price,
+        )?;
+    }
 
-        if !game.resolved {
-            let match_amount = count_match(
-                &game.number,
-                &lottery.clone().winning_number.unwrap(),
-                state.set_of_balls,
+    if first_round == start_round {
+        GAMES.remove(deps.storage, key);
+        if game.end_round.is_some() {
+            MULTI_ROUND_GAMES.remove(
+                deps.storage,
+                (
+                    &end_round.to_be_bytes(),
+                    &[
+                        &start_round.to_be_bytes()[..],
+                        &game_key(player_raw.as_slice(), game_id),
+                    ]
+                    .concat(),
+                ),
             );
-            let bonus = lottery.bonus_number.unwrap() == game.bonus;
-
-            let prize = if lottery.prize_rank.len() == 9 {
-                match match_amount {
-                    0 if bonus => lottery.prize_rank[0],
-                    1 if !bonus => lottery.prize_rank[1],
-                    1 if bonus => lottery.prize_rank[2],
-                    2 if !bonus => lottery.prize_rank[3],
-                    2 if bonus => lottery.prize_rank[4],
-                    3 if !bonus => lottery.prize_rank[5],
-                    3 if bonus => lottery.prize_rank[6],
-                    4 if !bonus => lottery.prize_rank[7],
-                    4 if bonus => lottery.prize_rank[8],
-                    _ => Uint128::zero(),
-                }
-            } else {
-                match match_amount {
-                    1 if !bonus => lottery.prize_rank[0],
-                    1 if bonus => lottery.prize_rank[1],
-                    2 if !bonus => lottery.prize_rank[2],
-                    2 if bonus => lottery.prize_rank[3],
-                    3 if !bonus => lottery.prize_rank[4],
-                    3 if bonus => lottery.prize_rank[5],
-                    4 if !bonus => lottery.prize_rank[6],
-                    4 if bonus => lottery.prize_rank[7],
-                    _ => Uint128::zero(),
-                }
-            };
-            // let prize = match match_amount {
-            //     0 if bonus => state.prize_rank[0],
-            //     1 if !bonus => state.prize_rank[1],
-            //     1 if bonus => state.prize_rank[2],
-            //     2 if !bonus => state.prize_rank[3],
-            //     2 if bonus => state.prize_rank[4],
-            //     3 if !bonus => state.prize_rank[5],
-            //     3 if bonus => state.prize_rank[6],
-            //     4 if !bonus => state.prize_rank[7],
-            //     4 if bonus => state.prize_rank[8],
-            //     _ => Uint128::zero(),
-            // };
-            let price_multiplier = prize.mul(game.multiplier);
-            total_amount_to_send = total_amount_to_send.checked_add(price_multiplier).unwrap();
-
-            GAMES.update(
+        }
+        if let Some(buyer) = &game.buyer {
+            GIFTS.remove(
                 deps.storage,
                 (
-                    &round.to_be_bytes(),
-                    &player_raw.as_slice(),
-                    &id.to_be_bytes(),
+                    buyer.as_slice(),
+                    &[
+                        &start_round.to_be_bytes()[..],
+                        &game_key(player_raw.as_slice(), game_id),
+                    ]
+                    .concat(),
                 ),
-                |game| -> Result<_, ContractError> {
-                    let mut update_game = game.unwrap();
-                    update_game.resolved = true;
-                    Ok(update_game)
-                },
-            )?;
+            );
         }
+    } else {
+        // The game keeps its key in MULTI_ROUND_GAMES, a round being resolved may have counted
+        // past it already. The rounds cancelled skip it from its end_round
+        let last_round = first_round - 1;
+        game.end_round = if last_round > start_round {
+            Some(last_round)
+        } else {
+            None
+        };
+        // The rounds left may all be collected already
+        game.resolved = game.collected_round == Some(last_round);
+        GAMES.save(deps.storage, key, &game)?;
+    }
+    // The player doesn't play the cancelled rounds anymore without another ticket
+    for cancelled_round in first_round..=end_round {
+        remove_round_ticket(
+            deps.storage,
+            cancelled_round,
+            start_round,
+            &player_raw,
+            price,
+        )?;
     }
 
-    let mut res = Response::new();
-
-    if !total_amount_to_send.is_zero() {
-        let collector_tax_amount = total_amount_to_send.mul(config.fee_collector);
-        let terrand_tax_amount = total_amount_to_send.mul(config.fee_collector_terrand);
+    add_collector_fee(deps.storage, &config, fee)?;
 
-        let msg_prize_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: player,
-            amount: vec![deduct_tax(
-                &deps.querier,
-                Coin {
-                    denom: config.denom.clone(),
-                    amount: total_amount_to_send
-                        .checked_sub(collector_tax_amount)
-                        .unwrap()
-                        .checked_sub(terrand_tax_amount)
-                        .unwrap(),
-                },
-            )?],
-        });
-        res.messages.push(SubMsg::new(msg_prize_payout));
+    let msg_refund = CosmosMsg::Bank(BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![deduct_tax(
+            &deps.querier,
+            &config.tax_mode,
+            Coin {
+                denom: config.denom,
+                amount: refund.checked_sub(fee).unwrap(),
+            },
+        )?],
+    });
 
-        let msg_fee_collector_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: deps
-                .api
-                .addr_humanize(&config.fee_collector_address)?
-                .to_string(),
-            amount: vec![deduct_tax(
-                &deps.querier,
-                Coin {
-                    denom: config.denom.clone(),
-                    amount: collector_tax_amount,
-                },
-            )?],
-        });
-        res.messages.push(SubMsg::new(msg_fee_collector_payout));
-
-        // prepare message to pay tax to terrand worker
-        let msg_fee_terrand_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: deps
-                .api
-                .addr_humanize(&lottery.terrand_worker.unwrap())?
-                .to_string(),
-            amount: vec![deduct_tax(
-                &deps.querier,
-                Coin {
-                    denom: config.denom,
-                    amount: terrand_tax_amount,
-                },
-            )?],
-        });
-        res.messages.push(SubMsg::new(msg_fee_terrand_payout));
-    }
+    Ok(Response::new()
+        .add_message(msg_refund)
+        .add_event(
+            TicketCancelled {
+                round: start_round,
+                player: info.sender.as_str(),
+                game_id,
+                first_round,
+                end_round,
+                refund,
+                cancel_fee: fee,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_cancel_ticket")
+        .add_attribute("start_round", start_round.to_string())
+        .add_attribute("game_id", game_id.to_string())
+        .add_attribute("cancelled_rounds", rounds.to_string())
+        .add_attribute("refund", refund.to_string())
+        .add_attribute("cancel_fee", fee.to_string()))
+}
 
-    res.attributes.push(Attribute::new("method", "try_collect"));
+pub fn try_subscribe(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    numbers: Option<Vec<u8>>,
+    multiplier: Uint128,
+    rounds: u64,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
 
-    Ok(res)
-}
+    if lottery.draw_time < env.block.time.seconds() {
+        return Err(ContractError::RegisterClosed {});
+    }
+    let sent = sent_funds(&config, &info)?;
+    if rounds == 0 {
+        return Err(ContractError::EmptySubscription {});
+    }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&query_config(deps)?),
-        QueryMsg::State {} => to_binary(&query_state(deps)?),
-        QueryMsg::Games {
-            start_after,
-            limit,
-            round,
-            player,
-        } => to_binary(&query_games(deps, start_after, limit, round, player)?),
-        QueryMsg::LotteryState { round } => to_binary(&query_lottery_state(deps, round)?),
-        QueryMsg::LotteriesState { start_after, limit } => {
-            to_binary(&query_lotteries_state(deps, start_after, limit)?)
-        }
-        QueryMsg::GameStats {
-            player,
-            start_after,
-            limit,
-        } => to_binary(&query_game_stats(deps, player, start_after, limit)?),
-        QueryMsg::LotteryStats { round } => to_binary(&query_lottery_stats(deps, round)?),
+    let multiplier_decimal = ticket_multiplier(&lottery, multiplier)?;
+    let round_price = state.ticket_price[0].mul(multiplier_decimal);
+    let expected_amount = round_price.checked_mul(Uint128::from(rounds)).unwrap();
+    if sent != expected_amount {
+        return Err(ContractError::AmountSentError(sent, expected_amount));
+    };
+    if let Some(numbers) = &numbers {
+        validate_numbers(&state, numbers)?;
     }
-}
 
-fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
-    let config = CONFIG.load(deps.storage)?;
-    Ok(ConfigResponse {
-        denom: config.denom,
-        frequency: config.frequency,
-        fee_collector: config.fee_collector,
-        fee_collector_address: deps
-            .api
-            .addr_humanize(&config.fee_collector_address)?
-            .to_string(),
-        fee_collector_terrand: config.fee_collector_terrand,
-        fee_collector_terrand_address: deps.api.addr_humanize(&config.terrand_address)?.to_string(),
-    })
+    let sender_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let address_raw = match address {
+        None => sender_raw.clone(),
+        Some(address) => deps.api.addr_canonicalize(&address)?,
+    };
+    // The games of a gifted subscription are indexed by buyer when played
+    let buyer = if address_raw != sender_raw {
+        Some(sender_raw)
+    } else {
+        None
+    };
+    let subscription_id = SUBSCRIPTION_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    SUBSCRIPTION_COUNT.save(deps.storage, &subscription_id.checked_add(1).unwrap())?;
+    SUBSCRIPTIONS.save(
+        deps.storage,
+        (address_raw.as_slice(), &subscription_id.to_be_bytes()),
+        &Subscription {
+            numbers,
+            multiplier: multiplier_decimal,
+            round_price,
+            start_round: state.round,
+            remaining_rounds: rounds,
+            balance: expected_amount,
+            buyer,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("method", "try_subscribe")
+        .add_attribute("subscription_id", subscription_id.to_string())
+        .add_attribute("start_round", state.round.to_string())
+        .add_attribute("rounds", rounds.to_string())
+        .add_attribute("sender", info.sender)
+        .add_attribute(
+            "recipient",
+            deps.api.addr_humanize(&address_raw)?.to_string(),
+        ))
 }
 
-fn query_state(deps: Deps) -> StdResult<StateResponse> {
+pub fn try_cancel_subscription(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    subscription_id: u64,
+) -> Result<Response, ContractError> {
     let state = STATE.load(deps.storage)?;
-    Ok(StateResponse {
-        round: state.round,
-        set_of_balls: state.set_of_balls,
-        range_min: state.range.min,
-        bonus_set_of_balls: state.bonus_set_of_balls,
-        bonus_range_min: state.bonus_range.min,
-        prize_rank: state.prize_rank,
-        ticket_price: state.ticket_price,
-        multiplier: state.multiplier,
-        range_max: state.range.max,
-        bonus_range_max: state.bonus_range.max,
-    })
+    let config = CONFIG.load(deps.storage)?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
+    let player_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let key = (player_raw.as_slice(), &subscription_id.to_be_bytes()[..]);
+    let mut subscription = SUBSCRIPTIONS
+        .may_load(deps.storage, key)?
+        .ok_or(ContractError::SubscriptionNotFound {})?;
+
+    // Once the registration is closed the subscription still plays the round when it wasn't
+    // played yet, its randomness can be known already
+    let unplayed = !lottery.subscriptions_played
+        && match &lottery.subscriptions_cursor {
+            Some(cursor) => key.joined_key() > *cursor,
+            None => true,
+        };
+    let (rounds, refund) = if lottery.draw_time < env.block.time.seconds()
+        && subscription.start_round <= state.round
+        && unplayed
+    {
+        if subscription.remaining_rounds == 1 {
+            return Err(ContractError::NothingToCancel {});
+        }
+        let refunded = (
+            subscription.remaining_rounds - 1,
+            subscription
+                .balance
+                .checked_sub(subscription.round_price)
+                .unwrap(),
+        );
+        subscription.remaining_rounds = 1;
+        subscription.balance = subscription.round_price;
+        SUBSCRIPTIONS.save(deps.storage, key, &subscription)?;
+        refunded
+    } else {
+        SUBSCRIPTIONS.remove(deps.storage, key);
+        (subscription.remaining_rounds, subscription.balance)
+    };
+
+    // Rounds not drawn yet are not played, refund them
+    let msg_refund = CosmosMsg::Bank(BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![deduct_tax(
+            &deps.querier,
+            &config.tax_mode,
+            Coin {
+                denom: config.denom,
+                amount: refund,
+            },
+        )?],
+    });
+
+    Ok(Response::new()
+        .add_message(msg_refund)
+        .add_event(
+            SubscriptionCancelled {
+                player: info.sender.as_str(),
+                subscription_id,
+                rounds,
+                refund,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_cancel_subscription")
+        .add_attribute("subscription_id", subscription_id.to_string())
+        .add_attribute("refunded_rounds", rounds.to_string())
+        .add_attribute("refund", refund.to_string()))
 }
 
-const DEFAULT_LIMIT: u32 = 10;
-const MAX_LIMIT: u32 = 30;
-fn query_games(
-    deps: Deps,
-    start_after: Option<u64>,
-    limit: Option<u32>,
+const DEFAULT_SUBSCRIPTIONS_LIMIT: u32 = 50;
+const MAX_SUBSCRIPTIONS_LIMIT: u32 = 200;
+
+/// Write the game of the next batch of subscriptions playing the round, from the cursor of the
+/// lottery, returns the player, the id and the subscription of the games written
+fn play_subscriptions(
+    storage: &mut dyn Storage,
     round: u64,
-    player: String,
-) -> StdResult<Vec<GameResponse>> {
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    let start = start_after.map(|d| Bound::Exclusive(d.to_be_bytes().to_vec()));
+    set_of_balls: u8,
+    lottery: &mut LotteryState,
+    config: &Config,
+    limit: usize,
+) -> Result<Vec<(CanonicalAddr, u64, Subscription)>, ContractError> {
+    let mut games = vec![];
+    let start = lottery.subscriptions_cursor.clone().map(Bound::Exclusive);
+    // One more is read to know if the batch is the last one
+    let mut subscriptions = SUBSCRIPTIONS
+        .range(storage, start, None, Order::Ascending)
+        .take(limit + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    if subscriptions.len() > limit {
+        subscriptions.truncate(limit);
+        lottery.subscriptions_cursor = subscriptions.last().map(|(k, _)| k.clone());
+    } else {
+        lottery.subscriptions_played = true;
+        lottery.subscriptions_cursor = None;
+    }
+    for (k, mut subscription) in subscriptions {
+        if subscription.start_round > round {
+            continue;
+        }
+        // The key is the length prefixed player followed by the subscription id
+        let (player, subscription_id) = split_game_key(&k);
+        let mut game = new_game(
+            subscription.numbers.clone(),
+            set_of_balls,
+            subscription.multiplier,
+            None,
+        );
+        game.buyer = subscription.buyer.clone();
+        let game_id = register_game(
+            storage,
+            round,
+            &CanonicalAddr::from(player),
+            game,
+            subscription.round_price,
+            config,
+        )?;
+        games.push((CanonicalAddr::from(player), game_id, subscription.clone()));
 
-    let owner_addr = deps.api.addr_validate(&player)?;
-    let raw_address = deps.api.addr_canonicalize(&owner_addr.as_str())?;
-    let games = GAMES
-        .prefix((&round.to_be_bytes(), raw_address.as_slice()))
-        .range(deps.storage, None, start, Order::Descending)
-        .take(limit)
-        .map(|pair| {
-            pair.map(|(k, game)| GameResponse {
-                number: game.number,
-                bonus: game.bonus,
-                multiplier: game.multiplier,
-                resolved: game.resolved,
-                game_id: u64::from_be_bytes(k.try_into().unwrap()),
-                lottery_id: round,
-            })
-        })
-        .collect::<StdResult<Vec<GameResponse>>>()?;
+        subscription.remaining_rounds -= 1;
+        subscription.balance = subscription
+            .balance
+            .checked_sub(subscription.round_price)
+            .unwrap();
+        if subscription.remaining_rounds == 0 {
+            SUBSCRIPTIONS.remove(storage, (player, subscription_id));
+        } else {
+            SUBSCRIPTIONS.save(storage, (player, subscription_id), &subscription)?;
+        }
+    }
 
     Ok(games)
 }
 
-fn query_lottery_state(deps: Deps, round: u64) -> StdResult<LotteryResponse> {
-    let lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
-    let worker = match lottery.terrand_worker {
-        None => None,
-        Some(terrand_worker) => Some(deps.api.addr_humanize(&terrand_worker)?.to_string()),
-    };
-    Ok(LotteryResponse {
-        draw_time: lottery.draw_time,
-        terrand_round: lottery.terrand_round,
-        terrand_worker: worker,
-        prize_rank: lottery.prize_rank,
-        ticket_price: lottery.ticket_price,
-        multiplier: lottery.multiplier,
-        winning_number: lottery.winning_number,
-        bonus_number: lottery.bonus_number,
-        lottery_id: round,
-    })
-}
-fn query_lotteries_state(
+/// Register events and ticket NFT mints of the games written for the subscriptions
+fn subscription_games_response(
     deps: Deps,
-    start_after: Option<u64>,
-    limit: Option<u32>,
-) -> StdResult<Vec<LotteryResponse>> {
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    let start = start_after.map(|d| Bound::Exclusive(d.to_be_bytes().to_vec()));
-
-    let lotteries = LOTTERY_STATE
-        .range(deps.storage, start, None, Order::Ascending)
-        .take(limit)
-        .map(|pair| {
-            pair.and_then(|(k, lottery)| {
-                let worker = match lottery.terrand_worker {
-                    None => None,
-                    Some(terrand_worker) => {
-                        Some(deps.api.addr_humanize(&terrand_worker)?.to_string())
-                    }
-                };
-                Ok(LotteryResponse {
-                    draw_time: lottery.draw_time,
-                    terrand_round: lottery.terrand_round,
-                    terrand_worker: worker,
-                    prize_rank: lottery.prize_rank,
-                    ticket_price: lottery.ticket_price,
-                    multiplier: lottery.multiplier,
-                    winning_number: lottery.winning_number,
-                    bonus_number: lottery.bonus_number,
-                    lottery_id: u64::from_be_bytes(k.try_into().unwrap()),
-                })
-            })
-        })
-        .collect::<StdResult<Vec<LotteryResponse>>>()?;
-
-    Ok(lotteries)
+    config: &Config,
+    round: u64,
+    games: Vec<(CanonicalAddr, u64, Subscription)>,
+) -> StdResult<(Vec<CosmosMsg>, Vec<Event>)> {
+    let mut msgs = vec![];
+    let mut events = vec![];
+    for (player_raw, game_id, subscription) in games {
+        let player = deps.api.addr_humanize(&player_raw)?;
+        let buyer = match &subscription.buyer {
+            None => player.clone(),
+            Some(buyer) => deps.api.addr_humanize(buyer)?,
+        };
+        events.push(
+            TicketRegistered {
+                round,
+                end_round: round,
+                player: player.as_str(),
+                buyer: buyer.as_str(),
+                game_id,
+                numbers: subscription.numbers.as_deref(),
+                multiplier: subscription.multiplier,
+                amount: subscription.round_price,
+            }
+            .into(),
+        );
+        if let Some(ticket_nft) = &config.ticket_nft {
+            msgs.push(mint_ticket(
+                deps,
+                ticket_nft,
+                round,
+                player.as_str(),
+                game_id,
+            )?);
+        }
+    }
+    Ok((msgs, events))
 }
 
-fn query_game_stats(
-    deps: Deps,
-    player: String,
-    start_after: Option<u64>,
+pub fn try_play_subscriptions(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
     limit: Option<u32>,
-) -> StdResult<Vec<GameStatsResponse>> {
-    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
-    let start = start_after.map(|d| Bound::Exclusive(d.to_be_bytes().to_vec()));
-
-    let owner_addr = deps.api.addr_validate(&player)?;
-    let raw_address = deps.api.addr_canonicalize(&owner_addr.as_str())?;
-    let game_stats = GAMES_STATS
-        .prefix(&raw_address.as_slice())
-        .range(deps.storage, None, start, Order::Descending)
-        .take(limit)
-        .map(|pair| {
-            pair.map(|(k, game_stats)| GameStatsResponse {
-                total_ticket: game_stats.total_ticket,
-                total_spent: game_stats.total_spent,
-                game_stats_id: u64::from_be_bytes(k.try_into().unwrap()),
-            })
-        })
-        .collect::<StdResult<Vec<GameStatsResponse>>>()?;
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
 
-    Ok(game_stats)
-}
+    // A subscription added behind the cursor only starts playing from the next round
+    if lottery.draw_time > env.block.time.seconds() {
+        return Err(ContractError::LotteryInProgress {});
+    }
+    if lottery.subscriptions_played {
+        return Err(ContractError::SubscriptionsAlreadyPlayed {});
+    }
 
-fn query_lottery_stats(deps: Deps, round: u64) -> StdResult<LotteryStatsResponse> {
-    let lottery_stats = LOTTERY_STATS.may_load(deps.storage, &round.to_be_bytes())?;
-
-    let lottery_stats = match lottery_stats {
-        None => LotteryStatsResponse {
-            counter_player: None,
-            total_ticket_sold: None,
-            total_collected: None,
-            lottery_stats_id: round,
-        },
-        Some(lottery_stats) => LotteryStatsResponse {
-            counter_player: lottery_stats.counter_player,
-            total_ticket_sold: lottery_stats.total_ticket_sold,
-            total_collected: lottery_stats.total_collected,
-            lottery_stats_id: round,
-        },
-    };
+    let limit = limit
+        .unwrap_or(DEFAULT_SUBSCRIPTIONS_LIMIT)
+        .min(MAX_SUBSCRIPTIONS_LIMIT) as usize;
+    let games = play_subscriptions(
+        deps.storage,
+        state.round,
+        state.set_of_balls,
+        &mut lottery,
+        &config,
+        limit,
+    )?;
+    LOTTERY_STATE.save(deps.storage, &state.round.to_be_bytes(), &lottery)?;
+    let played = games.len();
+    let (msgs, events) = subscription_games_response(deps.as_ref(), &config, state.round, games)?;
 
-    Ok(lottery_stats)
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_events(events)
+        .add_attribute("method", "try_play_subscriptions")
+        .add_attribute("round", state.round.to_string())
+        .add_attribute("played", played.to_string())
+        .add_attribute("done", lottery.subscriptions_played.to_string()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> StdResult<Response> {
-    let mut config = CONFIG.load(deps.storage)?;
+pub fn try_draw(deps: DepsMut, env: Env, _info: MessageInfo) -> Result<Response, ContractError> {
     let mut state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
 
-    config.fee_collector_address = deps
-        .api
-        .addr_canonicalize(&msg.update_fee_collector_address)?;
-    config.terrand_address = deps.api.addr_canonicalize(&msg.update_terrand_address)?;
-    config.denom = msg.update_denom;
-    config.fee_collector = msg.update_fee_collector;
-    config.frequency = msg.update_frequency;
-    config.fee_collector_terrand = msg.update_fee_collector_terrand;
-    config.live_round_max = msg.update_live_round_max;
-    CONFIG.save(deps.storage, &config)?;
+    if lottery.draw_time > env.block.time.seconds() {
+        return Err(ContractError::LotteryInProgress {});
+    }
 
-    state.ticket_price = msg.update_ticket_price;
-    state.prize_rank = msg.update_prize_rank;
-    state.multiplier = msg.update_multiplier;
-    STATE.save(deps.storage, &state)?;
+    // The draw plays the last batch of subscriptions, the previous ones with PlaySubscriptions
+    let mut subscription_games = vec![];
+    if !lottery.subscriptions_played {
+        let limit = DEFAULT_SUBSCRIPTIONS_LIMIT as usize;
+        let start = lottery.subscriptions_cursor.clone().map(Bound::Exclusive);
+        let pending = SUBSCRIPTIONS
+            .keys(deps.storage, start, None, Order::Ascending)
+            .take(limit + 1)
+            .count();
+        if pending > limit {
+            return Err(ContractError::SubscriptionsNotPlayed {});
+        }
+        subscription_games = play_subscriptions(
+            deps.storage,
+            state.round,
+            state.set_of_balls,
+            &mut lottery,
+            &config,
+            limit,
+        )?;
+        LOTTERY_STATE.save(deps.storage, &state.round.to_be_bytes(), &lottery)?;
+    }
+    let lottery_stats = LOTTERY_STATS.may_load(deps.storage, &state.round.to_be_bytes())?;
 
-    Ok(Response::default())
-}
+    let (mut msgs, mut events) =
+        subscription_games_response(deps.as_ref(), &config, state.round, subscription_games)?;
+    if let Some(lottery_stats) = lottery_stats {
+        let terrand_randomness = query_randomness(deps.as_ref(), &config, lottery.terrand_round)?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::mock_querier::custom_mock_dependencies;
+        let Draw {
+            winning_number,
+            bonus_number,
+        } = draw(
+            terrand_randomness.randomness.as_slice(),
+            &Rules::new(state.set_of_balls, &lottery),
+        )?;
+
+        let worker_raw = deps.api.addr_canonicalize(&terrand_randomness.worker)?;
+        // Update lottery winning and bonus number
+        LOTTERY_STATE.update(
+            deps.storage,
+            &state.round.to_be_bytes(),
+            |lottery_state| -> Result<_, ContractError> {
+                let mut update_lottery_state = lottery_state.unwrap();
+                update_lottery_state.winning_number = Some(winning_number.clone());
+                update_lottery_state.bonus_number = Some(bonus_number);
+                update_lottery_state.randomness = Some(terrand_randomness.randomness.clone());
+                update_lottery_state.terrand_worker = Some(worker_raw.clone());
+                Ok(update_lottery_state)
+            },
+        )?;
+
+        events.push(
+            RoundDrawn {
+                round: state.round,
+                winning_number: &winning_number,
+                bonus_number,
+                terrand_round: lottery.terrand_round,
+                worker: &terrand_randomness.worker,
+                randomness: &terrand_randomness.randomness,
+                total_ticket_sold: lottery_stats.total_ticket_sold,
+                total_collected: lottery_stats.total_collected,
+            }
+            .into(),
+        );
+        events.push(
+            FeePaid {
+                recipient: &terrand_randomness.worker,
+                amount: Uint128::from(1_000_000u128),
+                kind: FeeKind::Randomness,
+            }
+            .into(),
+        );
+        let randomness_fee = CosmosMsg::Bank(BankMsg::Send {
+            to_address: terrand_randomness.worker,
+            amount: vec![deduct_tax(
+                &deps.querier,
+                &config.tax_mode,
+                Coin {
+                    denom: config.denom.clone(),
+                    amount: Uint128::from(1_000_000u128),
+                },
+            )?],
+        });
+
+        msgs.push(randomness_fee);
+
+        // The fees taken on the tickets sold are earned once the round is drawn, less the
+        // referral rewards of its tickets
+        add_collector_fee(
+            deps.storage,
+            &config,
+            lottery_stats
+                .collector_fee
+                .saturating_sub(lottery_stats.referral_rewards),
+        )?;
+        add_fee(deps.storage, &worker_raw, lottery_stats.terrand_fee)?;
+    }
+
+    open_next_round(deps.storage, &env, &config, &mut state)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_events(events)
+        .add_attribute("method", "try_draw")
+        .add_attribute("round", state.round.checked_sub(1).unwrap().to_string()))
+}
+
+/// Close the open round and open the next one, drawn after the frequency from now
+fn open_next_round(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    state: &mut State,
+) -> Result<(), ContractError> {
+    // Update state & save
+    state.round += 1;
+    STATE.save(storage, state)?;
+
+    // calculate next round randomness from now
+    let draw_time = env.block.time.plus_seconds(config.frequency).seconds();
+    let from_genesis = draw_time.checked_sub(DRAND_GENESIS_TIME).unwrap();
+    let next_round = from_genesis
+        .checked_div(DRAND_PERIOD)
+        .unwrap()
+        .checked_add(DRAND_NEXT_ROUND_SECURITY)
+        .unwrap();
+
+    // Create new lottery
+    LOTTERY_STATE.save(
+        storage,
+        &state.round.to_be_bytes(),
+        &LotteryState {
+            draw_time,
+            terrand_round: next_round,
+            terrand_worker: None,
+            prize_rank: state.prize_rank.clone(),
+            ticket_price: state.ticket_price.clone(),
+            multiplier: state.multiplier.clone(),
+            winning_number: None,
+            bonus_number: None,
+            randomness: None,
+            prize_mode: state.prize_mode.clone(),
+            prize_share: state.prize_share.clone(),
+            jackpot: Uint128::zero(),
+            jackpot_share: config.jackpot_share,
+            winners: vec![],
+            resolution_cursor: None,
+            games_counted: false,
+            resolved: false,
+            cancelled: false,
+            subscriptions_cursor: None,
+            subscriptions_played: false,
+        },
+    )?;
+    // Multi-round tickets bought in the previous rounds play the new round too
+    open_round_stats(storage, state.round, config)
+}
+
+/// Query terrand for the randomness of its round
+fn query_randomness(
+    deps: Deps,
+    config: &Config,
+    terrand_round: u64,
+) -> StdResult<terrand::msg::GetRandomResponse> {
+    let msg = terrand::msg::QueryMsg::GetRandomness {
+        round: terrand_round,
+    };
+    let terrand_human = deps.api.addr_humanize(&config.terrand_address)?;
+    let query = WasmQuery::Smart {
+        contract_addr: terrand_human.to_string(),
+        msg: to_binary(&msg)?,
+    };
+    deps.querier.query(&query.into())
+}
+
+pub fn try_abort_round(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    round: u64,
+) -> Result<Response, ContractError> {
+    let mut state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let mut lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+
+    if round != state.round {
+        return Err(ContractError::CannotAbortRound {});
+    }
+    let sender_raw = deps.api.addr_canonicalize(info.sender.as_str())?;
+    let timed_out =
+        lottery.draw_time.checked_add(ABORT_TIMEOUT).unwrap() <= env.block.time.seconds();
+    if !timed_out {
+        if config.owner != Some(sender_raw) {
+            return Err(ContractError::Unauthorized {});
+        }
+        // The beacon of the round can be public once its draw time is passed
+        if lottery.draw_time < env.block.time.seconds() {
+            return Err(ContractError::AbortAfterDrawTime {});
+        }
+    } else if let Ok(randomness) = query_randomness(deps.as_ref(), &config, lottery.terrand_round) {
+        // Only a round terrand didn't deliver is aborted, a known randomness is drawn instead
+        if !randomness.randomness.is_empty() {
+            return Err(ContractError::RandomnessAvailable {});
+        }
+    }
+
+    // The subscriptions are not played, they start again from the next round
+    lottery.cancelled = true;
+    LOTTERY_STATE.save(deps.storage, &round.to_be_bytes(), &lottery)?;
+    open_next_round(deps.storage, &env, &config, &mut state)?;
+
+    Ok(Response::new()
+        .add_event(RoundAborted { round, timed_out }.into())
+        .add_attribute("method", "try_abort_round")
+        .add_attribute("round", round.to_string()))
+}
+
+pub fn try_claim_refund(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    round: u64,
+    player: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+    let player_raw = deps
+        .api
+        .addr_canonicalize(Addr::unchecked(player.clone()).as_str())?;
+
+    if !lottery.cancelled {
+        return Err(ContractError::LotteryNotCancelled {});
+    }
+    if REFUND_CLAIMS.has(deps.storage, (&round.to_be_bytes(), player_raw.as_slice())) {
+        return Err(ContractError::RefundAlreadyClaimed {});
+    }
+
+    // The games bought in the round and the multi-round games bought in the previous rounds
+    // playing it, from the earliest one on. The stats written before it was stored look back
+    // live_round_max - 1 rounds. Each game refunds the price of the round
+    let first_round = GAMES_STATS
+        .may_load(deps.storage, (player_raw.as_slice(), &round.to_be_bytes()))?
+        .and_then(|game_stats| game_stats.first_round)
+        .unwrap_or_else(|| round.saturating_sub(u64::from(config.live_round_max) - 1));
+    let played_rounds = GAMES_STATS
+        .prefix(player_raw.as_slice())
+        .keys(
+            deps.storage,
+            Some(Bound::Inclusive(first_round.to_be_bytes().to_vec())),
+            Some(Bound::Inclusive(round.to_be_bytes().to_vec())),
+            Order::Ascending,
+        )
+        .collect::<Vec<Vec<u8>>>();
+    let mut refund = Uint128::zero();
+    // Refunds per owner, the refund of a minted ticket follows its NFT
+    let mut owner_refunds: Vec<(String, Uint128)> = vec![];
+    for played_round in played_rounds {
+        let start_round = u64::from_be_bytes(played_round.as_slice().try_into().unwrap());
+        let games = GAMES
+            .prefix((&played_round, player_raw.as_slice()))
+            .range(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (game_id, game) in games {
+            if game.end_round.unwrap_or(start_round) < round {
+                continue;
+            }
+            let price = game_price(deps.storage, start_round, &game)?;
+            refund = refund.checked_add(price).unwrap();
+            let owner = match &config.ticket_nft {
+                None => player.clone(),
+                Some(ticket_nft) => ticket_owner(
+                    deps.as_ref(),
+                    ticket_nft,
+                    start_round,
+                    &player,
+                    &[(
+                        u64::from_be_bytes(game_id.as_slice().try_into().unwrap()),
+                        game.minted,
+                    )],
+                )?,
+            };
+            match owner_refunds
+                .iter_mut()
+                .find(|(address, _)| address == &owner)
+            {
+                Some((_, amount)) => *amount = amount.checked_add(price).unwrap(),
+                None => owner_refunds.push((owner, price)),
+            }
+        }
+    }
+    if refund.is_zero() {
+        return Err(ContractError::NothingToRefund {});
+    }
+    REFUND_CLAIMS.save(
+        deps.storage,
+        (&round.to_be_bytes(), player_raw.as_slice()),
+        &Empty {},
+    )?;
+
+    let mut msgs = vec![];
+    for (owner, amount) in owner_refunds {
+        msgs.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: owner,
+            amount: vec![deduct_tax(
+                &deps.querier,
+                &config.tax_mode,
+                Coin {
+                    denom: config.denom.clone(),
+                    amount,
+                },
+            )?],
+        }));
+    }
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_event(
+            RefundClaimed {
+                round,
+                player: &player,
+                amount: refund,
+            }
+            .into(),
+        )
+        .add_attribute("method", "try_claim_refund")
+        .add_attribute("round", round.to_string())
+        .add_attribute("refund", refund.to_string()))
+}
+
+pub fn try_collect(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    round: u64,
+    player: String,
+    game_id: Vec<u64>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let config = CONFIG.load(deps.storage)?;
+    let player_raw = deps
+        .api
+        .addr_canonicalize(Addr::unchecked(player.clone()).as_str())?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+
+    // The multi-round tickets of an aborted round still play the next rounds
+    if !lottery.cancelled && lottery.winning_number.is_none() && lottery.bonus_number.is_none() {
+        return Err(ContractError::LotteryInProgress {});
+    }
+    let lottery_stats = LOTTERY_STATS
+        .may_load(deps.storage, &round.to_be_bytes())?
+        .unwrap_or_default();
+
+    // Prizes won per terrand worker, each worker is paid its fee on the rounds it drew
+    let mut worker_prizes: Vec<(CanonicalAddr, Uint128)> = vec![];
+    let mut minted_games = vec![];
+    let mut collected = 0;
+    for &id in &game_id {
+        let key = (
+            &round.to_be_bytes()[..],
+            player_raw.as_slice(),
+            &id.to_be_bytes()[..],
+        );
+        let mut game = GAMES.load(deps.storage, key)?;
+        minted_games.push((id, game.minted));
+        if game.resolved {
+            collected += 1;
+            continue;
+        }
+        let game_key = game_key(player_raw.as_slice(), id);
+
+        match game.end_round {
+            // The tickets of an aborted round are refunded instead
+            None if lottery.cancelled => continue,
+            None => {
+                game = drawn_game(game, &game_key, &lottery, state.set_of_balls);
+                let prize = ticket_prize(&game, &lottery, &lottery_stats, state.set_of_balls)
+                    .ok_or(ContractError::LotteryNotResolved {})?;
+                add_worker_prize(&mut worker_prizes, &lottery, prize);
+                game.resolved = true;
+            }
+            // Evaluate every drawn round not collected yet, up to the first one not resolved
+            Some(end_round) => {
+                let first_round = game.collected_round.map_or(round, |r| r + 1);
+                let last_round = end_round.min(state.round.checked_sub(1).unwrap());
+                let mut collected_round = game.collected_round;
+                for played_round in first_round..=last_round {
+                    let played_lottery =
+                        LOTTERY_STATE.load(deps.storage, &played_round.to_be_bytes())?;
+                    // An aborted round has no draw and no worker, it is refunded instead
+                    if played_lottery.cancelled {
+                        collected_round = Some(played_round);
+                        continue;
+                    }
+                    let played_stats = LOTTERY_STATS
+                        .may_load(deps.storage, &played_round.to_be_bytes())?
+                        .unwrap_or_default();
+                    let prize = match ticket_prize(
+                        &drawn_game(game.clone(), &game_key, &played_lottery, state.set_of_balls),
+                        &played_lottery,
+                        &played_stats,
+                        state.set_of_balls,
+                    ) {
+                        Some(prize) => prize,
+                        None if collected_round == game.collected_round => {
+                            return Err(ContractError::LotteryNotResolved {})
+                        }
+                        None => break,
+                    };
+                    add_worker_prize(&mut worker_prizes, &played_lottery, prize);
+                    collected_round = Some(played_round);
+                }
+                game.collected_round = collected_round;
+                game.resolved = collected_round == Some(end_round);
+            }
+        }
+        GAMES.save(deps.storage, key, &game)?;
+    }
+    if collected == game_id.len() {
+        return Err(ContractError::AlreadyCollected {});
+    }
+    let total_amount_to_send = worker_prizes
+        .iter()
+        .fold(Uint128::zero(), |total, (_, prize)| {
+            total.checked_add(*prize).unwrap()
+        });
+
+    let mut res = Response::new();
+
+    if !total_amount_to_send.is_zero() {
+        // No fee is taken on the prizes when the fees are only taken on sales
+        let (fee_collector, fee_collector_terrand) = if config.fee_model.on_winnings() {
+            (config.fee_collector, config.fee_collector_terrand)
+        } else {
+            (Decimal::zero(), Decimal::zero())
+        };
+        let collector_tax_amount = total_amount_to_send.mul(fee_collector);
+        let terrand_tax_amount = worker_prizes
+            .iter()
+            .fold(Uint128::zero(), |total, (_, prize)| {
+                total
+                    .checked_add((*prize).mul(fee_collector_terrand))
+                    .unwrap()
+            });
+
+        let amount = total_amount_to_send
+            .checked_sub(collector_tax_amount)
+            .unwrap()
+            .checked_sub(terrand_tax_amount)
+            .unwrap();
+        // The prizes follow the ticket NFTs
+        let recipient = match &config.ticket_nft {
+            None => player.clone(),
+            Some(ticket_nft) => ticket_owner(
+                deps.as_ref(),
+                ticket_nft,
+                round,
+                deps.api.addr_humanize(&player_raw)?.as_str(),
+                &minted_games,
+            )?,
+        };
+        let msg_prize_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.clone(),
+            amount: vec![deduct_tax(
+                &deps.querier,
+                &config.tax_mode,
+                Coin {
+                    denom: config.denom.clone(),
+                    amount,
+                },
+            )?],
+        });
+        res.messages.push(SubMsg::new(msg_prize_payout));
+        res.events.push(
+            PrizeCollected {
+                round,
+                player: &player,
+                recipient: &recipient,
+                game_ids: &game_id,
+                prize: total_amount_to_send,
+                amount,
+                collector_fee: collector_tax_amount,
+                terrand_fee: terrand_tax_amount,
+            }
+            .into(),
+        );
+
+        // Fees are accrued and withdrawn in bulk, only the player is paid
+        add_collector_fee(deps.storage, &config, collector_tax_amount)?;
+        for (worker, prize) in worker_prizes {
+            add_fee(deps.storage, &worker, prize.mul(fee_collector_terrand))?;
+        }
+    }
+
+    res.attributes.push(Attribute::new("method", "try_collect"));
+
+    Ok(res)
+}
+
+fn add_worker_prize(
+    worker_prizes: &mut Vec<(CanonicalAddr, Uint128)>,
+    lottery: &LotteryState,
+    prize: Uint128,
+) {
+    // Rounds with tickets are always drawn by a worker
+    let worker = lottery.terrand_worker.clone().unwrap();
+    match worker_prizes.iter_mut().find(|(w, _)| w == &worker) {
+        Some((_, total)) => *total = total.checked_add(prize).unwrap(),
+        None => worker_prizes.push((worker, prize)),
+    }
+}
+
+const DEFAULT_RESOLVE_LIMIT: u32 = 100;
+const MAX_RESOLVE_LIMIT: u32 = 500;
+pub fn try_resolve(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    round: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let mut lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+
+    if round >= state.round {
+        return Err(ContractError::LotteryInProgress {});
+    }
+    if lottery.resolved {
+        return Err(ContractError::LotteryAlreadyResolved {});
+    }
+    // Resolve in order so the jackpot rolled over is known before counting the next round
+    if round > 0 {
+        let previous_lottery =
+            LOTTERY_STATE.load(deps.storage, &round.checked_sub(1).unwrap().to_be_bytes())?;
+        if !previous_lottery.resolved {
+            return Err(ContractError::PreviousLotteryNotResolved {});
+        }
+    }
+
+    let limit = limit
+        .unwrap_or(DEFAULT_RESOLVE_LIMIT)
+        .min(MAX_RESOLVE_LIMIT) as usize;
+    let tiers = lottery.prize_rank.len();
+    if lottery.winners.is_empty() {
+        lottery.winners = vec![0; tiers];
+    }
+    // Aborted rounds have no winners, their tickets are refunded
+    if lottery.cancelled {
+        lottery.games_counted = true;
+        lottery.resolved = true;
+    }
+
+    // Games bought for the round first
+    let mut games = vec![];
+    if !lottery.games_counted {
+        let start = lottery.resolution_cursor.clone().map(Bound::Exclusive);
+        games = GAMES
+            .sub_prefix(&round.to_be_bytes())
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<Vec<_>>>()?;
+        lottery.resolution_cursor = games.last().map(|(k, _)| k.clone());
+        if games.len() < limit {
+            lottery.games_counted = true;
+            lottery.resolution_cursor = None;
+        }
+    }
+    // Then the multi-round games bought in the previous rounds, from the ones ending this round
+    let mut counted = games.len();
+    let budget = limit - games.len();
+    if lottery.games_counted && !lottery.resolved && budget > 0 {
+        let start = match lottery.resolution_cursor.clone() {
+            Some(cursor) => Bound::Exclusive(cursor),
+            // Length prefixed end round
+            None => Bound::Inclusive([&[0, 8], &round.to_be_bytes()[..]].concat()),
+        };
+        let keys = MULTI_ROUND_GAMES
+            .keys(deps.storage, Some(start), None, Order::Ascending)
+            .take(budget)
+            .collect::<Vec<Vec<u8>>>();
+        counted += keys.len();
+        // Keys are the length prefixed end round, the start round and the game key
+        for k in keys.iter() {
+            let start_round = u64::from_be_bytes(k[10..18].try_into().unwrap());
+            if start_round >= round {
+                continue;
+            }
+            let (player, game_id) = split_game_key(&k[18..]);
+            let game = GAMES.load(deps.storage, (&start_round.to_be_bytes(), player, game_id))?;
+            // Cancelled before the round
+            if game.end_round.unwrap_or(start_round) < round {
+                continue;
+            }
+            games.push((k[18..].to_vec(), game));
+        }
+        if keys.len() < budget {
+            lottery.resolved = true;
+            lottery.resolution_cursor = None;
+        } else {
+            lottery.resolution_cursor = keys.last().cloned();
+        }
+    }
+
+    // Rounds without tickets are not drawn and have no winners
+    if let Some(draw) = Draw::of(&lottery) {
+        let rules = Rules::new(state.set_of_balls, &lottery);
+        for (k, game) in games.iter() {
+            let drawn = drawn_game(game.clone(), k, &lottery, state.set_of_balls);
+            // Store the quick-pick numbers now the randomness is known, multi-round games draw
+            // new numbers every round
+            if game.number.is_empty() && game.end_round.is_none() {
+                let (player, game_id) = split_game_key(k);
+                GAMES.save(
+                    deps.storage,
+                    (&round.to_be_bytes(), player, game_id),
+                    &drawn,
+                )?;
+            }
+            if let Outcome::Won { tier, .. } = evaluate(&Ticket::from(&drawn), &draw, &rules) {
+                lottery.winners[tier] += 1;
+            }
+        }
+    }
+
+    let mut res = Response::new()
+        .add_attribute("method", "try_resolve")
+        .add_attribute("round", round.to_string())
+        .add_attribute("counted", counted.to_string());
+
+    if lottery.resolved {
+        // Roll the jackpot over when the top tier is not progressive or nobody hit it
+        let top_tier_winners = lottery.winners[tiers - 1];
+        if lottery.jackpot_share.is_zero() || top_tier_winners == 0 {
+            let jackpot = round_jackpot(deps.storage, round, &lottery)?;
+            LOTTERY_STATE.update(
+                deps.storage,
+                &round.checked_add(1).unwrap().to_be_bytes(),
+                |lottery_state| -> Result<_, ContractError> {
+                    let mut update_lottery_state = lottery_state.unwrap();
+                    update_lottery_state.jackpot =
+                        update_lottery_state.jackpot.checked_add(jackpot).unwrap();
+                    Ok(update_lottery_state)
+                },
+            )?;
+            res = res.add_attribute("jackpot_rolled_over", jackpot.to_string());
+        }
+    }
+    LOTTERY_STATE.save(deps.storage, &round.to_be_bytes(), &lottery)?;
+
+    Ok(res.add_attribute("resolved", lottery.resolved.to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::Games {
+            start_after,
+            limit,
+            order,
+            round,
+            player,
+        } => to_binary(&query_games(
+            deps,
+            start_after,
+            limit,
+            order,
+            round,
+            player,
+        )?),
+        QueryMsg::LotteryState { round } => to_binary(&query_lottery_state(deps, round)?),
+        QueryMsg::LotteriesState {
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_lotteries_state(deps, start_after, limit, order)?),
+        QueryMsg::GameStats {
+            player,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_game_stats(deps, player, start_after, limit, order)?),
+        QueryMsg::LotteryStats { round } => to_binary(&query_lottery_stats(deps, round)?),
+        QueryMsg::PlayerTickets {
+            player,
+            start_after,
+            limit,
+            order,
+            status,
+        } => to_binary(&query_player_tickets(
+            deps,
+            player,
+            start_after,
+            limit,
+            order,
+            status,
+        )?),
+        QueryMsg::CurrentRound {} => to_binary(&query_current_round(deps, env)?),
+        QueryMsg::RoundPlayers {
+            round,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_round_players(
+            deps,
+            round,
+            start_after,
+            limit,
+            order,
+        )?),
+        QueryMsg::Gifts {
+            buyer,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_gifts(deps, buyer, start_after, limit, order)?),
+        QueryMsg::FeeBalance { address } => to_binary(&query_fee_balance(deps, address)?),
+        QueryMsg::ReferralRewards { referrer } => {
+            to_binary(&query_referral_rewards(deps, referrer)?)
+        }
+        QueryMsg::ReferredPlayers {
+            referrer,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_referred_players(
+            deps,
+            referrer,
+            start_after,
+            limit,
+            order,
+        )?),
+        QueryMsg::Subscriptions {
+            player,
+            start_after,
+            limit,
+            order,
+        } => to_binary(&query_subscriptions(
+            deps,
+            player,
+            start_after,
+            limit,
+            order,
+        )?),
+    }
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse {
+        denom: config.denom,
+        frequency: config.frequency,
+        fee_collector: config.fee_collector,
+        fee_collector_address: deps
+            .api
+            .addr_humanize(&config.fee_collector_address)?
+            .to_string(),
+        fee_collector_terrand: config.fee_collector_terrand,
+        fee_collector_terrand_address: deps.api.addr_humanize(&config.terrand_address)?.to_string(),
+        jackpot_share: config.jackpot_share,
+        cancel_fee: config.cancel_fee,
+        referral_share: config.referral_share,
+        fee_model: config.fee_model,
+        fee_recipients: config
+            .fee_recipients
+            .iter()
+            .map(|recipient| {
+                Ok(FeeRecipientMsg {
+                    address: deps.api.addr_humanize(&recipient.address)?.to_string(),
+                    share: recipient.share,
+                })
+            })
+            .collect::<StdResult<Vec<_>>>()?,
+        staking_address: config
+            .staking_address
+            .map(|address| deps.api.addr_humanize(&address))
+            .transpose()?
+            .map(|address| address.to_string()),
+        tax_mode: config.tax_mode,
+        ticket_nft: config
+            .ticket_nft
+            .map(|address| deps.api.addr_humanize(&address))
+            .transpose()?
+            .map(|address| address.to_string()),
+        owner: match config.owner {
+            None => None,
+            Some(owner) => Some(deps.api.addr_humanize(&owner)?.to_string()),
+        },
+    })
+}
+
+fn query_state(deps: Deps) -> StdResult<StateResponse> {
+    let state = STATE.load(deps.storage)?;
+    Ok(StateResponse {
+        round: state.round,
+        set_of_balls: state.set_of_balls,
+        range_min: state.range.min,
+        bonus_set_of_balls: state.bonus_set_of_balls,
+        bonus_range_min: state.bonus_range.min,
+        prize_rank: state.prize_rank,
+        ticket_price: state.ticket_price,
+        multiplier: state.multiplier,
+        range_max: state.range.max,
+        bonus_range_max: state.bonus_range.max,
+        prize_mode: state.prize_mode,
+        prize_share: state.prize_share,
+    })
+}
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Bounds of a page starting strictly after the cursor in the requested order
+fn page_bounds(start_after: Option<Vec<u8>>, order: Order) -> (Option<Bound>, Option<Bound>) {
+    let start = start_after.map(Bound::Exclusive);
+    match order {
+        Order::Ascending => (start, None),
+        Order::Descending => (None, start),
+    }
+}
+
+/// Keep `limit` items of a page fetched with one extra item, the cursor of the next page is the
+/// last item kept when more items are remaining
+fn next_page<T, C>(
+    mut items: Vec<T>,
+    limit: usize,
+    cursor: impl Fn(&T) -> C,
+) -> (Vec<T>, Option<C>) {
+    if items.len() <= limit {
+        return (items, None);
+    }
+    items.truncate(limit);
+    let next_cursor = items.last().map(cursor);
+    (items, next_cursor)
+}
+
+fn query_games(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+    round: u64,
+    player: String,
+) -> StdResult<GamesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let (min, max) = page_bounds(start_after.map(|d| d.to_be_bytes().to_vec()), order);
+
+    let owner_addr = deps.api.addr_validate(&player)?;
+    let raw_address = deps.api.addr_canonicalize(owner_addr.as_str())?;
+    let state = STATE.load(deps.storage)?;
+    let lottery = LOTTERY_STATE.may_load(deps.storage, &round.to_be_bytes())?;
+    let games = GAMES
+        .prefix((&round.to_be_bytes(), raw_address.as_slice()))
+        .range(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|pair| {
+            let (k, game) = pair?;
+            let game_id = u64::from_be_bytes(k.try_into().unwrap());
+            // Multi-round quick-picks draw new numbers every round
+            let game = match (&lottery, game.end_round) {
+                (Some(lottery), None) => {
+                    let game_key = game_key(raw_address.as_slice(), game_id);
+                    drawn_game(game, &game_key, lottery, state.set_of_balls)
+                }
+                _ => game,
+            };
+            game_response(deps, round, game_id, game)
+        })
+        .collect::<StdResult<Vec<GameResponse>>>()?;
+
+    let (games, next_cursor) = next_page(games, limit, |game| game.game_id);
+    Ok(GamesResponse { games, next_cursor })
+}
+
+fn game_response(deps: Deps, round: u64, game_id: u64, game: Game) -> StdResult<GameResponse> {
+    let buyer = match &game.buyer {
+        None => None,
+        Some(buyer) => Some(deps.api.addr_humanize(buyer)?.to_string()),
+    };
+    Ok(GameResponse {
+        number: game.number,
+        bonus: game.bonus,
+        multiplier: game.multiplier,
+        resolved: game.resolved,
+        quick_pick: game.quick_pick,
+        end_round: game.end_round,
+        buyer,
+        memo: game.memo,
+        game_id,
+        lottery_id: round,
+    })
+}
+
+fn query_gifts(
+    deps: Deps,
+    buyer: String,
+    start_after: Option<(u64, String, u64)>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<GiftsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let start_gift = match start_after {
+        Some((round, player, game_id)) => {
+            let player_raw = deps.api.addr_canonicalize(&player)?;
+            Some(
+                [
+                    &round.to_be_bytes()[..],
+                    &game_key(player_raw.as_slice(), game_id),
+                ]
+                .concat(),
+            )
+        }
+        None => None,
+    };
+    let (min, max) = page_bounds(start_gift, order);
+
+    let state = STATE.load(deps.storage)?;
+    let buyer_addr = deps.api.addr_validate(&buyer)?;
+    let buyer_raw = deps.api.addr_canonicalize(buyer_addr.as_str())?;
+    let gifts = GIFTS
+        .prefix(buyer_raw.as_slice())
+        .keys(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|k| {
+            // The key is the round followed by the game key
+            let (round, game_key) = k.split_at(8);
+            let round = u64::from_be_bytes(round.try_into().unwrap());
+            let (player, game_id) = split_game_key(game_key);
+            let game = GAMES.load(deps.storage, (&round.to_be_bytes(), player, game_id))?;
+            let game = match (
+                LOTTERY_STATE.may_load(deps.storage, &round.to_be_bytes())?,
+                game.end_round,
+            ) {
+                (Some(lottery), None) => drawn_game(game, game_key, &lottery, state.set_of_balls),
+                _ => game,
+            };
+            let game_id = u64::from_be_bytes(game_id.try_into().unwrap());
+            Ok(GiftResponse {
+                player: deps
+                    .api
+                    .addr_humanize(&CanonicalAddr::from(player))?
+                    .to_string(),
+                game: game_response(deps, round, game_id, game)?,
+            })
+        })
+        .collect::<StdResult<Vec<GiftResponse>>>()?;
+
+    let (gifts, next_cursor) = next_page(gifts, limit, |gift| {
+        (gift.game.lottery_id, gift.player.clone(), gift.game.game_id)
+    });
+    Ok(GiftsResponse { gifts, next_cursor })
+}
+
+fn lottery_response(deps: Deps, round: u64, lottery: LotteryState) -> StdResult<LotteryResponse> {
+    let worker = match &lottery.terrand_worker {
+        None => None,
+        Some(terrand_worker) => Some(deps.api.addr_humanize(terrand_worker)?.to_string()),
+    };
+    let jackpot = round_jackpot(deps.storage, round, &lottery)?;
+    Ok(LotteryResponse {
+        draw_time: lottery.draw_time,
+        terrand_round: lottery.terrand_round,
+        terrand_worker: worker,
+        prize_rank: lottery.prize_rank,
+        ticket_price: lottery.ticket_price,
+        multiplier: lottery.multiplier,
+        winning_number: lottery.winning_number,
+        bonus_number: lottery.bonus_number,
+        randomness: lottery.randomness,
+        prize_mode: lottery.prize_mode,
+        prize_share: lottery.prize_share,
+        jackpot,
+        jackpot_share: lottery.jackpot_share,
+        winners: lottery.winners,
+        resolved: lottery.resolved,
+        cancelled: lottery.cancelled,
+        lottery_id: round,
+    })
+}
+
+fn query_lottery_state(deps: Deps, round: u64) -> StdResult<LotteryResponse> {
+    let lottery = LOTTERY_STATE.load(deps.storage, &round.to_be_bytes())?;
+    lottery_response(deps, round, lottery)
+}
+
+fn query_lotteries_state(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<LotteriesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let (min, max) = page_bounds(start_after.map(|d| d.to_be_bytes().to_vec()), order);
+
+    let lotteries = LOTTERY_STATE
+        .range(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|pair| {
+            pair.and_then(|(k, lottery)| {
+                lottery_response(deps, u64::from_be_bytes(k.try_into().unwrap()), lottery)
+            })
+        })
+        .collect::<StdResult<Vec<LotteryResponse>>>()?;
+
+    let (lotteries, next_cursor) = next_page(lotteries, limit, |lottery| lottery.lottery_id);
+    Ok(LotteriesResponse {
+        lotteries,
+        next_cursor,
+    })
+}
+
+fn query_game_stats(
+    deps: Deps,
+    player: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<GamesStatsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let (min, max) = page_bounds(start_after.map(|d| d.to_be_bytes().to_vec()), order);
+
+    let owner_addr = deps.api.addr_validate(&player)?;
+    let raw_address = deps.api.addr_canonicalize(owner_addr.as_str())?;
+    let game_stats = GAMES_STATS
+        .prefix(raw_address.as_slice())
+        .range(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|pair| {
+            pair.map(|(k, game_stats)| GameStatsResponse {
+                total_ticket: game_stats.total_ticket,
+                total_spent: game_stats.total_spent,
+                game_stats_id: u64::from_be_bytes(k.try_into().unwrap()),
+            })
+        })
+        .collect::<StdResult<Vec<GameStatsResponse>>>()?;
+
+    let (game_stats, next_cursor) = next_page(game_stats, limit, |stats| stats.game_stats_id);
+    Ok(GamesStatsResponse {
+        game_stats,
+        next_cursor,
+    })
+}
+
+fn query_lottery_stats(deps: Deps, round: u64) -> StdResult<LotteryStatsResponse> {
+    let lottery_stats = LOTTERY_STATS
+        .may_load(deps.storage, &round.to_be_bytes())?
+        .unwrap_or_default();
+
+    Ok(LotteryStatsResponse {
+        counter_player: lottery_stats.counter_player,
+        total_ticket_sold: lottery_stats.total_ticket_sold,
+        total_collected: lottery_stats.total_collected,
+        collector_fee: lottery_stats.collector_fee,
+        terrand_fee: lottery_stats.terrand_fee,
+        lottery_stats_id: round,
+    })
+}
+
+/// Prize of a game over all the rounds it plays, None while one of them is not known yet
+fn game_prize(
+    storage: &dyn Storage,
+    set_of_balls: u8,
+    game: &Game,
+    game_key: &[u8],
+    round: u64,
+) -> StdResult<Option<Uint128>> {
+    let mut total = Uint128::zero();
+    for played_round in round..=game.end_round.unwrap_or(round) {
+        let lottery = match LOTTERY_STATE.may_load(storage, &played_round.to_be_bytes())? {
+            Some(lottery) => lottery,
+            None => return Ok(None),
+        };
+        let lottery_stats = LOTTERY_STATS
+            .may_load(storage, &played_round.to_be_bytes())?
+            .unwrap_or_default();
+        match ticket_prize(
+            &drawn_game(game.clone(), game_key, &lottery, set_of_balls),
+            &lottery,
+            &lottery_stats,
+            set_of_balls,
+        ) {
+            Some(prize) => total = total.checked_add(prize)?,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(total))
+}
+
+fn query_player_tickets(
+    deps: Deps,
+    player: String,
+    start_after: Option<(u64, u64)>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+    status: Option<TicketStatus>,
+) -> StdResult<PlayerTicketsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let state = STATE.load(deps.storage)?;
+
+    let owner_addr = deps.api.addr_validate(&player)?;
+    let raw_address = deps.api.addr_canonicalize(owner_addr.as_str())?;
+
+    // Games stats are keyed by player first so they index the rounds played, the round of the
+    // cursor is included since it can have games left after the cursor game id
+    let start_round = start_after.map(|(round, _)| Bound::Inclusive(round.to_be_bytes().to_vec()));
+    let (min_round, max_round) = match order {
+        Order::Ascending => (start_round, None),
+        Order::Descending => (None, start_round),
+    };
+    let rounds = GAMES_STATS
+        .prefix(raw_address.as_slice())
+        .keys(deps.storage, min_round, max_round, order)
+        .map(|k| u64::from_be_bytes(k.try_into().unwrap()));
+
+    // The limit bounds the tickets scanned rather than the ones returned, so a status filter
+    // matching few tickets can't walk the whole history of the player in one query
+    let mut tickets = vec![];
+    let mut scanned = 0;
+    let mut last_scanned = None;
+    let mut next_cursor = None;
+    'rounds: for round in rounds {
+        let lottery = LOTTERY_STATE.may_load(deps.storage, &round.to_be_bytes())?;
+        let start_game = match start_after {
+            Some((start_round, game_id)) if start_round == round => {
+                Some(game_id.to_be_bytes().to_vec())
+            }
+            _ => None,
+        };
+        let (min, max) = page_bounds(start_game, order);
+        let games = GAMES
+            .prefix((&round.to_be_bytes(), raw_address.as_slice()))
+            .range(deps.storage, min, max, order);
+        for pair in games {
+            let (k, game) = pair?;
+            if scanned == limit {
+                next_cursor = last_scanned;
+                break 'rounds;
+            }
+            scanned += 1;
+            let game_id = u64::from_be_bytes(k.try_into().unwrap());
+            last_scanned = Some((round, game_id));
+            let game_key = game_key(raw_address.as_slice(), game_id);
+            let prize = game_prize(deps.storage, state.set_of_balls, &game, &game_key, round)?;
+            let game = match (&lottery, game.end_round) {
+                (Some(lottery), None) => drawn_game(game, &game_key, lottery, state.set_of_balls),
+                _ => game,
+            };
+            let (ticket_status, prize) = match prize {
+                None => (TicketStatus::Unresolved, Uint128::zero()),
+                Some(prize) if prize.is_zero() => (TicketStatus::Lost, prize),
+                Some(prize) => (TicketStatus::Won, prize),
+            };
+            if status.is_some() && status != Some(ticket_status.clone()) {
+                continue;
+            }
+
+            tickets.push(PlayerTicketResponse {
+                game: game_response(deps, round, game_id, game)?,
+                status: ticket_status,
+                prize,
+            });
+        }
+    }
+
+    Ok(PlayerTicketsResponse {
+        tickets,
+        next_cursor,
+    })
+}
+
+fn query_current_round(deps: Deps, env: Env) -> StdResult<CurrentRoundResponse> {
+    let state = STATE.load(deps.storage)?;
+    let lottery = LOTTERY_STATE.load(deps.storage, &state.round.to_be_bytes())?;
+    let lottery_stats = LOTTERY_STATS
+        .may_load(deps.storage, &state.round.to_be_bytes())?
+        .unwrap_or_default();
+    let now = env.block.time.seconds();
+
+    Ok(CurrentRoundResponse {
+        round: state.round,
+        draw_time: lottery.draw_time,
+        seconds_remaining: lottery.draw_time.saturating_sub(now),
+        registration_open: lottery.draw_time >= now,
+        terrand_round: lottery.terrand_round,
+        total_ticket_sold: lottery_stats.total_ticket_sold,
+        counter_player: lottery_stats.counter_player,
+        total_collected: lottery_stats.total_collected,
+        jackpot: lottery.jackpot.checked_add(lottery_stats.jackpot)?,
+        ticket_price: lottery.ticket_price,
+        multiplier: lottery.multiplier,
+    })
+}
+
+fn query_round_players(
+    deps: Deps,
+    round: u64,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<RoundPlayersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let start = match start_after {
+        None => None,
+        Some(player) => {
+            let owner_addr = deps.api.addr_validate(&player)?;
+            Some(deps.api.addr_canonicalize(owner_addr.as_str())?.to_vec())
+        }
+    };
+    let (min, max) = page_bounds(start, order);
+
+    let players = ROUND_PLAYERS
+        .prefix(&round.to_be_bytes())
+        .keys(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|k| Ok(deps.api.addr_humanize(&k.into())?.to_string()))
+        .collect::<StdResult<Vec<String>>>()?;
+
+    let (players, next_cursor) = next_page(players, limit, |player| player.clone());
+    Ok(RoundPlayersResponse {
+        players,
+        next_cursor,
+    })
+}
+
+fn query_fee_balance(deps: Deps, address: String) -> StdResult<FeeBalanceResponse> {
+    let recipient_addr = deps.api.addr_validate(&address)?;
+    let recipient_raw = deps.api.addr_canonicalize(recipient_addr.as_str())?;
+    let balance = FEE_BALANCES
+        .may_load(deps.storage, recipient_raw.as_slice())?
+        .unwrap_or_default();
+    Ok(FeeBalanceResponse { balance })
+}
+
+fn query_referral_rewards(deps: Deps, referrer: String) -> StdResult<ReferralRewardsResponse> {
+    let referrer_addr = deps.api.addr_validate(&referrer)?;
+    let referrer_raw = deps.api.addr_canonicalize(referrer_addr.as_str())?;
+    let state = STATE.load(deps.storage)?;
+    let rewards = REFERRAL_REWARDS
+        .may_load(deps.storage, referrer_raw.as_slice())?
+        .unwrap_or_default();
+    let earned =
+        drawn_referral_rewards(deps.storage, &referrer_raw, state.round, None, usize::MAX)?
+            .into_iter()
+            .fold(Uint128::zero(), |total, (_, _, reward)| {
+                total.checked_add(reward).unwrap()
+            });
+    // The rounds from the open round on, the tickets ending before it are all earned
+    let pending = PENDING_REFERRAL_REWARDS
+        .prefix(referrer_raw.as_slice())
+        .range(
+            deps.storage,
+            Some(Bound::Inclusive(state.round.to_be_bytes().to_vec())),
+            None,
+            Order::Ascending,
+        )
+        .try_fold(Uint128::zero(), |total, item| -> StdResult<_> {
+            let (key, pending) = item?;
+            let end_round = u64::from_be_bytes(key[..8].try_into().unwrap());
+            let rounds = end_round - pending.first_round.max(state.round) + 1;
+            Ok(total.checked_add(pending.reward.checked_mul(Uint128::from(rounds))?)?)
+        })?;
+    Ok(ReferralRewardsResponse {
+        balance: rewards.balance.checked_add(earned).unwrap(),
+        total_earned: rewards.total_earned.checked_add(earned).unwrap(),
+        pending,
+    })
+}
+
+fn query_referred_players(
+    deps: Deps,
+    referrer: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<ReferredPlayersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let start = match start_after {
+        None => None,
+        Some(player) => {
+            let owner_addr = deps.api.addr_validate(&player)?;
+            Some(deps.api.addr_canonicalize(owner_addr.as_str())?.to_vec())
+        }
+    };
+    let (min, max) = page_bounds(start, order);
+
+    let referrer_addr = deps.api.addr_validate(&referrer)?;
+    let referrer_raw = deps.api.addr_canonicalize(referrer_addr.as_str())?;
+    let players = REFERRED_PLAYERS
+        .prefix(referrer_raw.as_slice())
+        .keys(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|k| Ok(deps.api.addr_humanize(&k.into())?.to_string()))
+        .collect::<StdResult<Vec<String>>>()?;
+
+    let (players, next_cursor) = next_page(players, limit, |player| player.clone());
+    Ok(ReferredPlayersResponse {
+        players,
+        next_cursor,
+    })
+}
+
+fn query_subscriptions(
+    deps: Deps,
+    player: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+    order: Option<OrderBy>,
+) -> StdResult<SubscriptionsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let order: Order = order.unwrap_or(OrderBy::Asc).into();
+    let (min, max) = page_bounds(start_after.map(|d| d.to_be_bytes().to_vec()), order);
+
+    let owner_addr = deps.api.addr_validate(&player)?;
+    let raw_address = deps.api.addr_canonicalize(owner_addr.as_str())?;
+    let subscriptions = SUBSCRIPTIONS
+        .prefix(raw_address.as_slice())
+        .range(deps.storage, min, max, order)
+        .take(limit + 1)
+        .map(|pair| {
+            let (k, subscription) = pair?;
+            let buyer = match subscription.buyer {
+                None => None,
+                Some(buyer) => Some(deps.api.addr_humanize(&buyer)?.to_string()),
+            };
+            Ok(SubscriptionResponse {
+                subscription_id: u64::from_be_bytes(k.try_into().unwrap()),
+                numbers: subscription.numbers,
+                multiplier: subscription.multiplier,
+                round_price: subscription.round_price,
+                start_round: subscription.start_round,
+                remaining_rounds: subscription.remaining_rounds,
+                balance: subscription.balance,
+                buyer,
+            })
+        })
+        .collect::<StdResult<Vec<SubscriptionResponse>>>()?;
+
+    let (subscriptions, next_cursor) = next_page(subscriptions, limit, |subscription| {
+        subscription.subscription_id
+    });
+    Ok(SubscriptionsResponse {
+        subscriptions,
+        next_cursor,
+    })
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::CannotMigrate(stored.contract));
+    }
+    let stored_version = Version::parse(&stored.version)?;
+    let new_version = Version::parse(CONTRACT_VERSION)?;
+    if stored_version > new_version {
+        return Err(ContractError::CannotMigrateVersion(
+            stored.version,
+            CONTRACT_VERSION.to_string(),
+        ));
+    }
+
+    let applied = migrate_storage(deps.storage, &stored_version, &new_version)?;
+
+    let mut config = CONFIG.load(deps.storage)?;
+    let mut state = STATE.load(deps.storage)?;
+
+    config.fee_collector_address = deps
+        .api
+        .addr_canonicalize(&msg.update_fee_collector_address)?;
+    config.terrand_address = deps.api.addr_canonicalize(&msg.update_terrand_address)?;
+    config.denom = msg.update_denom;
+    config.fee_collector = msg.update_fee_collector;
+    config.frequency = msg.update_frequency;
+    config.fee_collector_terrand = msg.update_fee_collector_terrand;
+    config.live_round_max = msg.update_live_round_max;
+    config.jackpot_share = msg.update_jackpot_share;
+    config.cancel_fee = msg.update_cancel_fee;
+    config.referral_share = msg.update_referral_share;
+    config.fee_model = msg.update_fee_model;
+    config.fee_recipients = fee_recipients(deps.as_ref(), msg.update_fee_recipients)?;
+    config.staking_address = optional_addr(deps.as_ref(), msg.update_staking_address)?;
+    config.tax_mode = msg.update_tax_mode;
+    let ticket_nft = optional_addr(deps.as_ref(), msg.update_ticket_nft)?;
+    if config.ticket_nft.is_some() && ticket_nft != config.ticket_nft {
+        return Err(ContractError::TicketNftLocked {});
+    }
+    config.ticket_nft = ticket_nft;
+    config.owner = Some(deps.api.addr_canonicalize(&msg.update_owner)?);
+
+    state.ticket_price = msg.update_ticket_price;
+    state.prize_rank = msg.update_prize_rank;
+    state.multiplier = msg.update_multiplier;
+    state.prize_mode = msg.update_prize_mode;
+    state.prize_share = msg.update_prize_share;
+    validate_config(&config, &state)?;
+
+    CONFIG.save(deps.storage, &config)?;
+    STATE.save(deps.storage, &state)?;
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION)
+        .add_attribute("migrations", format!("[{}]", applied.join(", "))))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, unused_variables)]
+mod tests {
+    use super::*;
+    use crate::engine::quick_pick_numbers;
+    use crate::mock_querier::custom_mock_dependencies;
+    use crate::state::{FeeModel, GameStats, LegacyLotteryStats, TaxMode, LEGACY_LOTTERY_STATS};
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{coins, from_binary, Attribute, Coin, Decimal, Timestamp, Uint128};
+    use cosmwasm_std::{
+        coins, from_binary, Api, Attribute, Coin, Decimal, OwnedDeps, Timestamp, Uint128,
+    };
     use std::str::FromStr;
 
-    fn default_init(deps: DepsMut) {
-        let msg = InstantiateMsg {
-            denom: "uusd".to_string(),
-            frequency: 300,
-            fee_collector: Decimal::from_str("0.05").unwrap(),
-            fee_collector_address: "STAKING".to_string(),
-            fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
-            terrand_address: "TERRAND".to_string(),
-            set_of_balls: 4,
-            range_min: 1,
-            range_max: 16,
-            bonus_set_of_balls: 1,
-            bonus_range_min: 1,
-            bonus_range_max: 8,
-            prize_rank: vec![
-                Uint128::from(1_000_000u128),
-                Uint128::from(2_000_000u128),
-                Uint128::from(5_000_000u128),
-                Uint128::from(10_000_000u128),
-                Uint128::from(30_000_000u128),
-                Uint128::from(50_000_000u128),
-                Uint128::from(150_000_000u128),
-                Uint128::from(1_000_000_000u128),
-                Uint128::from(10_000_000_000u128),
-            ],
-            ticket_price: vec![
-                Uint128::from(1_000_000u128),
-                Uint128::from(2_000_000u128),
-                Uint128::from(5_000_000u128),
-            ],
-            multiplier: vec![
-                Decimal::from_str("1").unwrap(),
-                Decimal::from_str("2").unwrap(),
-                Decimal::from_str("5").unwrap(),
-            ],
-            live_round_max: 5,
+    fn default_instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            denom: "uusd".to_string(),
+            frequency: 300,
+            fee_collector: Decimal::from_str("0.05").unwrap(),
+            fee_collector_address: "STAKING".to_string(),
+            fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
+            terrand_address: "TERRAND".to_string(),
+            set_of_balls: 4,
+            range_min: 1,
+            range_max: 16,
+            bonus_set_of_balls: 1,
+            bonus_range_min: 1,
+            bonus_range_max: 8,
+            prize_rank: vec![
+                Uint128::from(1_000_000u128),
+                Uint128::from(2_000_000u128),
+                Uint128::from(5_000_000u128),
+                Uint128::from(10_000_000u128),
+                Uint128::from(30_000_000u128),
+                Uint128::from(50_000_000u128),
+                Uint128::from(150_000_000u128),
+                Uint128::from(1_000_000_000u128),
+                Uint128::from(10_000_000_000u128),
+            ],
+            ticket_price: vec![
+                Uint128::from(1_000_000u128),
+                Uint128::from(2_000_000u128),
+                Uint128::from(5_000_000u128),
+            ],
+            multiplier: vec![
+                Decimal::from_str("1").unwrap(),
+                Decimal::from_str("2").unwrap(),
+                Decimal::from_str("5").unwrap(),
+            ],
+            live_round_max: 5,
+            jackpot_share: Decimal::zero(),
+            cancel_fee: Decimal::zero(),
+            referral_share: Decimal::zero(),
+            fee_model: FeeModel::OnWinnings,
+            fee_recipients: vec![],
+            staking_address: None,
+            tax_mode: TaxMode::Terra,
+            ticket_nft: None,
+            prize_mode: PrizeMode::Fixed,
+            prize_share: vec![],
+        }
+    }
+
+    fn init_with(deps: DepsMut, msg: InstantiateMsg) {
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let res = instantiate(deps, env, mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    fn default_init(deps: DepsMut) {
+        init_with(deps, default_instantiate_msg());
+    }
+
+    fn custom_init_prize(deps: DepsMut) {
+        let msg = InstantiateMsg {
+            denom: "uusd".to_string(),
+            frequency: 300,
+            fee_collector: Decimal::from_str("0.05").unwrap(),
+            fee_collector_address: "STAKING".to_string(),
+            fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
+            terrand_address: "TERRAND".to_string(),
+            set_of_balls: 4,
+            range_min: 1,
+            range_max: 16,
+            bonus_set_of_balls: 1,
+            bonus_range_min: 1,
+            bonus_range_max: 8,
+            prize_rank: vec![
+                Uint128::from(1_000_000u128),
+                Uint128::from(5_000_000u128),
+                Uint128::from(10_000_000u128),
+                Uint128::from(30_000_000u128),
+                Uint128::from(50_000_000u128),
+                Uint128::from(150_000_000u128),
+                Uint128::from(1_000_000_000u128),
+                Uint128::from(10_000_000_000u128),
+            ],
+            ticket_price: vec![
+                Uint128::from(1_000_000u128),
+                Uint128::from(2_000_000u128),
+                Uint128::from(5_000_000u128),
+            ],
+            multiplier: vec![
+                Decimal::from_str("1").unwrap(),
+                Decimal::from_str("2").unwrap(),
+                Decimal::from_str("5").unwrap(),
+            ],
+            live_round_max: 5,
+            jackpot_share: Decimal::zero(),
+            cancel_fee: Decimal::zero(),
+            referral_share: Decimal::zero(),
+            fee_model: FeeModel::OnWinnings,
+            fee_recipients: vec![],
+            staking_address: None,
+            tax_mode: TaxMode::Terra,
+            ticket_nft: None,
+            prize_mode: PrizeMode::Fixed,
+            prize_share: vec![],
+        };
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let res = instantiate(deps, env, mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies(&[]);
+        default_init(deps.as_mut());
+    }
+
+    #[test]
+    fn try_register() {
+        let mut deps = mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(5_000_000u128),
+            }],
+        );
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(301);
+
+        // Error time not started yet
+        let err = execute(deps.as_mut(), env, sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::RegisterClosed {});
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        // Remove this because not fitting with the current design
+        // // Error duplicated numbers
+        // let msg = ExecuteMsg::Register {
+        //     numbers: vec![vec![5, 7, 5, 15, 2], vec![1, 2, 17, 1, 3]],
+        //     address: None,
+        //     live_round: 1
+        // };
+        // let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        // assert_eq!(err, ContractError::WrongSetOfBallsOrDuplicateNotAllowed {});
+
+        // Error bonus out of range
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 10]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::BonusOutOfRange {});
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 0]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::BonusOutOfRange {});
+
+        // Error ball out of range
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 17, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidNumbers(4, 1));
+
+        // live round error max life exceeded
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 0,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::LiveRoundMaxLifeExceeded {});
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 6,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::LiveRoundMaxLifeExceeded {});
+
+        // // Wrong set of balls
+        // let msg = ExecuteMsg::Register {
+        //     numbers: vec![vec![5, 7, 1, 15, 4, 4], vec![1, 2, 17, 5, 1]],
+        //     address: None,
+        //     live_round: 1
+        // };
+        // let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        // assert_eq!(err, ContractError::WrongSetOfBallsOrDuplicateNotAllowed {});
+
+        // Success
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                Attribute::new("method", "try_register"),
+                Attribute::new("round", "[0]"),
+                Attribute::new("live_round", "1"),
+                Attribute::new("ticket_amount", "1"),
+                Attribute::new("sender", "alice"),
+                Attribute::new("recipient", "alice"),
+            ]
+        );
+        // Error sent less than required
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 2, 17, 6, 4]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 4,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::AmountSentError(
+                Uint128::from(1_000_000u128),
+                Uint128::from(4_000_000u128)
+            )
+        );
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(4_000_000u128),
+            }],
+        );
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 2, 16, 6, 4]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 4,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                Attribute::new("method", "try_register"),
+                Attribute::new("round", "[0, 1, 2, 3]"),
+                Attribute::new("live_round", "4"),
+                Attribute::new("ticket_amount", "1"),
+                Attribute::new("sender", "alice"),
+                Attribute::new("recipient", "alice"),
+            ]
+        );
+
+        let games = query_games(
+            deps.as_ref(),
+            None,
+            None,
+            Some(OrderBy::Desc),
+            0,
+            "alice".to_string(),
+        )
+        .unwrap()
+        .games;
+        assert_eq!(
+            games,
+            vec![
+                GameResponse {
+                    number: vec![1, 2, 16, 6],
+                    bonus: 4,
+                    multiplier: Decimal::from_str("1").unwrap(),
+                    resolved: false,
+                    quick_pick: false,
+                    end_round: Some(3),
+                    buyer: None,
+                    memo: None,
+                    game_id: 1,
+                    lottery_id: 0
+                },
+                GameResponse {
+                    number: vec![5, 7, 12, 15],
+                    bonus: 1,
+                    multiplier: Decimal::from_str("5").unwrap(),
+                    resolved: false,
+                    quick_pick: false,
+                    end_round: None,
+                    buyer: None,
+                    memo: None,
+                    game_id: 0,
+                    lottery_id: 0
+                }
+            ]
+        );
+        // The ticket is stored once in the round it is bought
+        let games = query_games(
+            deps.as_ref(),
+            None,
+            None,
+            Some(OrderBy::Desc),
+            1,
+            "alice".to_string(),
+        )
+        .unwrap()
+        .games;
+        assert!(games.is_empty());
+
+        // Error sent
+        let sender = mock_info(
+            "bob",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(10_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::AmountSentError(
+                Uint128::from(10_000_000u128),
+                Uint128::from(5_000_000u128)
+            )
+        );
+
+        // Success
+        let sender = mock_info(
+            "bob",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(5_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
+
+        assert_eq!(
+            res.attributes,
+            vec![
+                Attribute::new("method", "try_register"),
+                Attribute::new("round", "[0]"),
+                Attribute::new("live_round", "1"),
+                Attribute::new("ticket_amount", "1"),
+                Attribute::new("sender", "bob"),
+                Attribute::new("recipient", "bob"),
+            ]
+        );
+
+        let games = query_games(
+            deps.as_ref(),
+            None,
+            None,
+            Some(OrderBy::Desc),
+            0,
+            "bob".to_string(),
+        )
+        .unwrap()
+        .games;
+        assert_eq!(
+            games,
+            vec![GameResponse {
+                number: vec![5, 7, 12, 15],
+                bonus: 1,
+                multiplier: Decimal::from_str("5").unwrap(),
+                resolved: false,
+                quick_pick: false,
+                end_round: None,
+                buyer: None,
+                memo: None,
+                game_id: 0,
+                lottery_id: 0
+            },]
+        );
+    }
+
+    #[test]
+    fn try_draw() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let msg = ExecuteMsg::Draw {};
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LotteryInProgress {});
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        env.block.time = env.block.time.plus_seconds(300);
+        let msg = ExecuteMsg::Draw {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+
+        let past_lottery_state = query_lottery_state(deps.as_ref(), 0).unwrap();
+        assert_eq!(past_lottery_state.terrand_round, 13);
+        assert_eq!(past_lottery_state.draw_time, 1595431350);
+        assert_eq!(past_lottery_state.bonus_number, None);
+        assert_eq!(past_lottery_state.winning_number, None);
+        assert_eq!(
+            past_lottery_state.multiplier,
+            vec![
+                Decimal::from_str("1").unwrap(),
+                Decimal::from_str("2").unwrap(),
+                Decimal::from_str("5").unwrap()
+            ]
+        );
+        assert_eq!(
+            past_lottery_state.ticket_price,
+            vec![
+                Uint128::from(1000000u128),
+                Uint128::from(2000000u128),
+                Uint128::from(5000000u128)
+            ]
+        );
+        assert_eq!(
+            past_lottery_state.prize_rank,
+            vec![
+                Uint128::from(1000000u128),
+                Uint128::from(2000000u128),
+                Uint128::from(5000000u128),
+                Uint128::from(10000000u128),
+                Uint128::from(30000000u128),
+                Uint128::from(50000000u128),
+                Uint128::from(150000000u128),
+                Uint128::from(1000000000u128),
+                Uint128::from(10000000000u128)
+            ]
+        );
+
+        let new_lottery_state = query_lottery_state(deps.as_ref(), 1).unwrap();
+        assert_eq!(new_lottery_state.terrand_round, 23);
+        assert_eq!(new_lottery_state.draw_time, 1595431650);
+        assert_eq!(new_lottery_state.bonus_number, None);
+        assert_eq!(new_lottery_state.winning_number, None);
+        assert_eq!(
+            new_lottery_state.multiplier,
+            vec![
+                Decimal::from_str("1").unwrap(),
+                Decimal::from_str("2").unwrap(),
+                Decimal::from_str("5").unwrap()
+            ]
+        );
+        assert_eq!(
+            new_lottery_state.ticket_price,
+            vec![
+                Uint128::from(1000000u128),
+                Uint128::from(2000000u128),
+                Uint128::from(5000000u128)
+            ]
+        );
+        assert_eq!(
+            new_lottery_state.prize_rank,
+            vec![
+                Uint128::from(1000000u128),
+                Uint128::from(2000000u128),
+                Uint128::from(5000000u128),
+                Uint128::from(10000000u128),
+                Uint128::from(30000000u128),
+                Uint128::from(50000000u128),
+                Uint128::from(150000000u128),
+                Uint128::from(1000000000u128),
+                Uint128::from(10000000000u128)
+            ]
+        );
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(10_000_000u128),
+            }],
+        );
+
+        let res = execute(deps.as_mut(), env.clone(), sender, msg.clone()).unwrap();
+        let sender = mock_info(
+            "bob",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(10_000_000u128),
+            }],
+        );
+        let res = execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+
+        env.block.time = env.block.time.plus_seconds(300);
+        let msg = ExecuteMsg::Draw {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let worker_msg = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "worker".to_string(),
+            amount: vec![Coin::new(990_099, "uusd")],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(worker_msg)]);
+
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        println!("{:?}", lottery_stats);
+        let new_lottery_state = query_lottery_state(deps.as_ref(), 1).unwrap();
+        assert_eq!(new_lottery_state.bonus_number, Some(7));
+        assert_eq!(new_lottery_state.winning_number, Some(vec![4, 15, 6, 4]));
+
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 2).unwrap();
+        println!("{:?}", lottery_stats);
+        let new_lottery_state = query_lottery_state(deps.as_ref(), 2).unwrap();
+
+        assert_eq!(new_lottery_state.bonus_number, None);
+        assert_eq!(new_lottery_state.winning_number, None);
+    }
+
+    #[test]
+    fn events() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let attribute = |event: &Event, key: &str| {
+            event
+                .attributes
+                .iter()
+                .find(|attr| attr.key == key)
+                .map(|attr| attr.value.clone())
+                .unwrap()
+        };
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 2, 2]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: Some("bob".to_string()),
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[Coin::new(1_000_000, "uusd")]),
+            msg,
+        )
+        .unwrap();
+        assert_eq!(res.events.len(), 1);
+        let event = &res.events[0];
+        assert_eq!(event.ty, "loterra_ticket_registered");
+        assert_eq!(attribute(event, "round"), "0");
+        assert_eq!(attribute(event, "end_round"), "0");
+        assert_eq!(attribute(event, "player"), "bob");
+        assert_eq!(attribute(event, "buyer"), "alice");
+        assert_eq!(attribute(event, "game_id"), "0");
+        assert_eq!(attribute(event, "numbers"), "4,15,6,2");
+        assert_eq!(attribute(event, "bonus"), "2");
+        assert_eq!(attribute(event, "quick_pick"), "false");
+        assert_eq!(attribute(event, "amount"), "1000000");
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(300);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        assert_eq!(res.events.len(), 2);
+        let event = &res.events[0];
+        assert_eq!(event.ty, "loterra_round_drawn");
+        assert_eq!(attribute(event, "round"), "0");
+        assert_eq!(attribute(event, "winning_number"), "4,15,6,4");
+        assert_eq!(attribute(event, "bonus_number"), "7");
+        assert_eq!(attribute(event, "worker"), "worker");
+        assert_eq!(attribute(event, "total_ticket_sold"), "1");
+        assert_eq!(attribute(event, "total_collected"), "1000000");
+        let event = &res.events[1];
+        assert_eq!(event.ty, "loterra_fee_paid");
+        assert_eq!(attribute(event, "recipient"), "worker");
+        assert_eq!(attribute(event, "amount"), "1000000");
+        assert_eq!(attribute(event, "kind"), "randomness");
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "bob".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env, mock_info("bob", &[]), msg).unwrap();
+        assert_eq!(res.events.len(), 1);
+        let event = &res.events[0];
+        assert_eq!(event.ty, "loterra_prize_collected");
+        assert_eq!(attribute(event, "round"), "0");
+        assert_eq!(attribute(event, "player"), "bob");
+        assert_eq!(attribute(event, "recipient"), "bob");
+        assert_eq!(attribute(event, "game_ids"), "0");
+        assert_eq!(attribute(event, "prize"), "50000000");
+        assert_eq!(attribute(event, "collector_fee"), "2500000");
+        assert_eq!(attribute(event, "terrand_fee"), "500000");
+        assert_eq!(attribute(event, "amount"), "47000000");
+    }
+
+    #[test]
+    fn refund_and_cancel_events() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let event = |res: &Response, ty: &str| {
+            res.events
+                .iter()
+                .find(|event| event.ty == ty)
+                .unwrap()
+                .attributes
+                .iter()
+                .map(|attr| (attr.key.clone(), attr.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        let attr = |key: &str, value: &str| (key.to_string(), value.to_string());
+
+        let msg = ExecuteMsg::Subscribe {
+            numbers: None,
+            multiplier: Uint128::from(1_000_000u128),
+            rounds: 2,
+            address: None,
+        };
+        let sender = mock_info("carol", &[Coin::new(2_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 2, 2]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: Some("dave".to_string()),
+        };
+        let sender = mock_info("alice", &[Coin::new(2_000_000, "uusd")]);
+        let res = execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        // The amount is the total paid for every round of the ticket
+        let ticket = event(&res, "loterra_ticket_registered");
+        assert!(ticket.contains(&attr("end_round", "1")));
+        assert!(ticket.contains(&attr("amount", "2000000")));
+
+        // The tickets of the subscriptions are registered by the draw, for a single round
+        env.block.time = env.block.time.plus_seconds(300);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let ticket = event(&res, "loterra_ticket_registered");
+        assert!(ticket.contains(&attr("end_round", "0")));
+        assert!(ticket.contains(&attr("player", "carol")));
+        assert!(ticket.contains(&attr("buyer", "carol")));
+        assert!(ticket.contains(&attr("game_id", "0")));
+        assert!(ticket.contains(&attr("quick_pick", "true")));
+        assert!(ticket.contains(&attr("amount", "1000000")));
+
+        let msg = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, "loterra_ticket_cancelled"),
+            vec![
+                attr("round", "0"),
+                attr("player", "alice"),
+                attr("game_id", "0"),
+                attr("first_round", "1"),
+                attr("end_round", "1"),
+                attr("refund", "1000000"),
+                attr("cancel_fee", "0"),
+            ]
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("dave", &[]),
+            ExecuteMsg::ClaimReferralRewards { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            event(&res, "loterra_referral_rewards_claimed"),
+            vec![attr("referrer", "dave"), attr("amount", "20000")]
+        );
+
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 2, 2]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let sender = mock_info("bob", &[Coin::new(1_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let msg = ExecuteMsg::AbortRound { round: 1 };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, "loterra_round_aborted"),
+            vec![attr("round", "1"), attr("timed_out", "false")]
+        );
+
+        let msg = ExecuteMsg::ClaimRefund {
+            round: 1,
+            player: "bob".to_string(),
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, "loterra_refund_claimed"),
+            vec![
+                attr("round", "1"),
+                attr("player", "bob"),
+                attr("amount", "1000000"),
+            ]
+        );
+
+        let msg = ExecuteMsg::CancelSubscription { subscription_id: 0 };
+        let res = execute(deps.as_mut(), env, mock_info("carol", &[]), msg).unwrap();
+        assert_eq!(
+            event(&res, "loterra_subscription_cancelled"),
+            vec![
+                attr("player", "carol"),
+                attr("subscription_id", "0"),
+                attr("rounds", "1"),
+                attr("refund", "1000000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn try_collect() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(10_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(5_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        // Alice winning number found
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(2_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        // Bob 3 numbers found and 1 bonus
+        let sender = mock_info(
+            "bob",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(2_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 5, 7]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        // Charlie 2 numbers found and 0 bonus
+        let sender = mock_info(
+            "charlie",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(2_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 2, 2]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        // Mario 0 numbers found and 1 bonus refund
+        let sender = mock_info(
+            "mario",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 1, 1, 1, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        // Mario 0 numbers found and 1 bonus refund
+        let sender = mock_info(
+            "mario",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 1, 1, 1, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        // Mario 0 numbers found
+        let sender = mock_info(
+            "mario",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 1, 1, 1, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        env.block.time = env.block.time.plus_seconds(300);
+        let msg = ExecuteMsg::Draw {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![0, 1],
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "alice".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(18_799_000_000u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+        // Collect again error with no prize
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "bob".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "bob".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(281_000_000u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "charlie".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "charlie".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(93_069_306u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+
+        // Win a refund
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "mario".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "mario".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(930_693u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+        // Win 2UST
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "mario".to_string(),
+            game_id: vec![1],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "mario".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_861_386u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+
+        // Win 0UST
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "mario".to_string(),
+            game_id: vec![2],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        // Fees are accrued and withdrawn in bulk
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(1_020_150_000u128));
+        let fees = query_fee_balance(deps.as_ref(), "worker".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(204_030_000u128));
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("worker", &[]),
+            ExecuteMsg::WithdrawFees {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "worker".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(203_030_000u128),
+                }],
+            }))]
+        );
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("worker", &[]),
+            ExecuteMsg::WithdrawFees {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoFeesToWithdraw {});
+
+        // Error too soon to collect
+        let msg = ExecuteMsg::Collect {
+            round: 1,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+
+        let err = execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LotteryInProgress {});
+    }
+
+    #[test]
+    fn try_collect_on_custom_init() {
+        let mut deps = custom_mock_dependencies(&[]);
+        custom_init_prize(deps.as_mut());
+
+        // bonus not counting
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(2_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 1, 1, 1, 7]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        // 1 number
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 1, 1, 1, 7]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        env.block.time = env.block.time.plus_seconds(300);
+        let msg = ExecuteMsg::Draw {};
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(res.messages.len(), 0);
+
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![1],
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "alice".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(9_306_930u128),
+            }],
+        });
+        assert_eq!(res.messages, vec![SubMsg::new(msg_payout)]);
+    }
+
+    #[test]
+    fn round_players() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(2_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        execute(deps.as_mut(), mock_env(), sender.clone(), msg.clone()).unwrap();
+        // Alice play a second ticket, she should not be counted twice
+        execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+
+        let sender = mock_info(
+            "bob",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![5, 7, 12, 15, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(
+            lottery_stats,
+            LotteryStatsResponse {
+                counter_player: 2,
+                total_ticket_sold: 3,
+                total_collected: Uint128::from(3_000_000u128),
+                collector_fee: Uint128::zero(),
+                terrand_fee: Uint128::zero(),
+                lottery_stats_id: 0
+            }
+        );
+        // The tickets of the next rounds are counted when they open, their players when bought
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(lottery_stats.total_ticket_sold, 0);
+        assert_eq!(lottery_stats.counter_player, 1);
+        let players = query_round_players(deps.as_ref(), 1, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string()]);
+
+        let players = query_round_players(deps.as_ref(), 0, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string(), "bob".to_string()]);
+        let players = query_round_players(deps.as_ref(), 0, Some("alice".to_string()), None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["bob".to_string()]);
+        let players = query_round_players(deps.as_ref(), 0, None, Some(1), None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string()]);
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        for _ in 0..2 {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("alice", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        }
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(lottery_stats.counter_player, 1);
+        assert_eq!(lottery_stats.total_ticket_sold, 2);
+        assert_eq!(lottery_stats.total_collected, Uint128::from(2_000_000u128));
+        let players = query_round_players(deps.as_ref(), 1, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string()]);
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 2).unwrap();
+        assert_eq!(lottery_stats.counter_player, 0);
+        assert_eq!(lottery_stats.total_collected, Uint128::zero());
+    }
+
+    #[test]
+    fn player_tickets() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        // Winning numbers on round 0
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 1, 1, 1, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        execute(deps.as_mut(), mock_env(), sender.clone(), msg.clone()).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        // Round 1 is not drawn
+        execute(deps.as_mut(), env, sender, msg).unwrap();
+
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.next_cursor, None);
+        assert_eq!(
+            tickets
+                .tickets
+                .iter()
+                .map(|t| (t.game.lottery_id, t.game.game_id, t.status.clone(), t.prize))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0, TicketStatus::Won, Uint128::from(10_000_000_000u128)),
+                (0, 1, TicketStatus::Lost, Uint128::zero()),
+                (1, 0, TicketStatus::Unresolved, Uint128::zero()),
+            ]
+        );
+
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            None,
+            None,
+            None,
+            Some(TicketStatus::Won),
+        )
+        .unwrap();
+        assert_eq!(tickets.tickets.len(), 1);
+        assert_eq!(tickets.tickets[0].game.number, vec![4, 15, 6, 4]);
+
+        // The limit bounds the tickets scanned, the filtered out ones still move the cursor
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            Some((0, 0)),
+            Some(1),
+            None,
+            Some(TicketStatus::Won),
+        )
+        .unwrap();
+        assert!(tickets.tickets.is_empty());
+        assert_eq!(tickets.next_cursor, Some((0, 1)));
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            tickets.next_cursor,
+            Some(1),
+            None,
+            Some(TicketStatus::Won),
+        )
+        .unwrap();
+        assert!(tickets.tickets.is_empty());
+        assert_eq!(tickets.next_cursor, None);
+
+        // Walk the pages in both orders
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            Some((0, 0)),
+            Some(1),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(tickets.tickets.len(), 1);
+        assert_eq!(tickets.next_cursor, Some((0, 1)));
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            tickets.next_cursor,
+            Some(1),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Unresolved);
+        assert_eq!(tickets.next_cursor, None);
+
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            Some((1, 0)),
+            Some(1),
+            Some(OrderBy::Desc),
+            None,
+        )
+        .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Lost);
+        assert_eq!(tickets.next_cursor, Some((0, 1)));
+        let tickets = query_player_tickets(
+            deps.as_ref(),
+            "alice".to_string(),
+            tickets.next_cursor,
+            Some(1),
+            Some(OrderBy::Desc),
+            None,
+        )
+        .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Won);
+        assert_eq!(tickets.next_cursor, None);
+
+        let tickets =
+            query_player_tickets(deps.as_ref(), "bob".to_string(), None, None, None, None).unwrap();
+        assert!(tickets.tickets.is_empty());
+    }
+
+    #[test]
+    fn pagination() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(3_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 2, 3, 4, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 3,
+            address: None,
+            memo: None,
+            referrer: None,
         };
+        for _ in 0..3 {
+            execute(deps.as_mut(), mock_env(), sender.clone(), msg.clone()).unwrap();
+        }
+
+        // start_after is exclusive in the requested order
+        let games =
+            query_games(deps.as_ref(), None, Some(2), None, 0, "alice".to_string()).unwrap();
+        assert_eq!(
+            games.games.iter().map(|g| g.game_id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(games.next_cursor, Some(1));
+        let games = query_games(
+            deps.as_ref(),
+            games.next_cursor,
+            Some(2),
+            None,
+            0,
+            "alice".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            games.games.iter().map(|g| g.game_id).collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(games.next_cursor, None);
+
+        let games = query_games(
+            deps.as_ref(),
+            Some(2),
+            None,
+            Some(OrderBy::Desc),
+            0,
+            "alice".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            games.games.iter().map(|g| g.game_id).collect::<Vec<_>>(),
+            vec![1, 0]
+        );
 
+        // Play the next rounds too
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 2, 3, 4, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
         let mut env = mock_env();
         env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
-        let res = instantiate(deps, env, mock_info("creator", &[]), msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        for _ in 0..2 {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("alice", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+            execute(deps.as_mut(), env.clone(), sender.clone(), msg.clone()).unwrap();
+        }
+
+        let game_stats =
+            query_game_stats(deps.as_ref(), "alice".to_string(), Some(0), Some(1), None).unwrap();
+        assert_eq!(game_stats.game_stats[0].game_stats_id, 1);
+        assert_eq!(game_stats.next_cursor, Some(1));
+        let game_stats = query_game_stats(
+            deps.as_ref(),
+            "alice".to_string(),
+            Some(2),
+            None,
+            Some(OrderBy::Desc),
+        )
+        .unwrap();
+        assert_eq!(
+            game_stats
+                .game_stats
+                .iter()
+                .map(|s| s.game_stats_id)
+                .collect::<Vec<_>>(),
+            vec![1, 0]
+        );
+
+        let lotteries = query_lotteries_state(deps.as_ref(), None, None, None).unwrap();
+        assert_eq!(
+            lotteries
+                .lotteries
+                .iter()
+                .map(|l| l.lottery_id)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        let lotteries =
+            query_lotteries_state(deps.as_ref(), None, Some(1), Some(OrderBy::Desc)).unwrap();
+        assert_eq!(lotteries.lotteries[0].lottery_id, 2);
+        assert_eq!(lotteries.next_cursor, Some(2));
+        let lotteries = query_lotteries_state(
+            deps.as_ref(),
+            lotteries.next_cursor,
+            Some(1),
+            Some(OrderBy::Desc),
+        )
+        .unwrap();
+        assert_eq!(lotteries.lotteries[0].lottery_id, 1);
+        assert_eq!(lotteries.next_cursor, Some(1));
     }
 
-    fn custom_init_prize(deps: DepsMut) {
-        let msg = InstantiateMsg {
-            denom: "uusd".to_string(),
-            frequency: 300,
-            fee_collector: Decimal::from_str("0.05").unwrap(),
-            fee_collector_address: "STAKING".to_string(),
-            fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
-            terrand_address: "TERRAND".to_string(),
-            set_of_balls: 4,
-            range_min: 1,
-            range_max: 16,
-            bonus_set_of_balls: 1,
-            bonus_range_min: 1,
-            bonus_range_max: 8,
-            prize_rank: vec![
-                Uint128::from(1_000_000u128),
-                Uint128::from(5_000_000u128),
-                Uint128::from(10_000_000u128),
-                Uint128::from(30_000_000u128),
-                Uint128::from(50_000_000u128),
-                Uint128::from(150_000_000u128),
-                Uint128::from(1_000_000_000u128),
-                Uint128::from(10_000_000_000u128),
-            ],
-            ticket_price: vec![
-                Uint128::from(1_000_000u128),
-                Uint128::from(2_000_000u128),
-                Uint128::from(5_000_000u128),
-            ],
-            multiplier: vec![
-                Decimal::from_str("1").unwrap(),
-                Decimal::from_str("2").unwrap(),
-                Decimal::from_str("5").unwrap(),
-            ],
-            live_round_max: 5,
+    #[test]
+    fn current_round() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(4_000_000u128),
+            }],
+        );
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 2, 3, 4, 1]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(100);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+
+        let current = query_current_round(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(
+            current,
+            CurrentRoundResponse {
+                round: 0,
+                draw_time: DRAND_GENESIS_TIME + 300,
+                seconds_remaining: 200,
+                registration_open: true,
+                terrand_round: 13,
+                total_ticket_sold: 1,
+                counter_player: 1,
+                total_collected: Uint128::from(2_000_000u128),
+                jackpot: Uint128::zero(),
+                ticket_price: vec![
+                    Uint128::from(1_000_000u128),
+                    Uint128::from(2_000_000u128),
+                    Uint128::from(5_000_000u128),
+                ],
+                multiplier: vec![
+                    Decimal::from_str("1").unwrap(),
+                    Decimal::from_str("2").unwrap(),
+                    Decimal::from_str("5").unwrap(),
+                ],
+            }
+        );
+
+        // Draw time passed but not drawn yet
+        env.block.time = env.block.time.plus_seconds(201);
+        let current = query_current_round(deps.as_ref(), env.clone()).unwrap();
+        assert_eq!(current.seconds_remaining, 0);
+        assert!(!current.registration_open);
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let current = query_current_round(deps.as_ref(), env).unwrap();
+        assert_eq!(current.round, 1);
+        assert_eq!(current.seconds_remaining, 300);
+        assert!(current.registration_open);
+        assert_eq!(current.total_ticket_sold, 1);
+    }
+
+    #[test]
+    fn progressive_jackpot() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let mut msg = default_instantiate_msg();
+        msg.jackpot_share = Decimal::percent(10);
+        init_with(deps.as_mut(), msg);
+
+        // Round 0 nobody hit the top tier
+        let sender = mock_info(
+            "alice",
+            &[Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(1_000_000u128),
+            }],
+        );
+        let losing_msg = ExecuteMsg::Register {
+            numbers: Some(vec![1, 1, 1, 1, 1]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
         };
+        execute(deps.as_mut(), mock_env(), sender.clone(), losing_msg).unwrap();
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        assert_eq!(
+            query_lottery_state(deps.as_ref(), 0).unwrap().jackpot,
+            Uint128::from(100_000u128)
+        );
+
+        // Can't count the open round
+        let msg = ExecuteMsg::Resolve {
+            round: 1,
+            limit: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LotteryInProgress {});
+
+        let msg = ExecuteMsg::Resolve {
+            round: 0,
+            limit: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                Attribute::new("method", "try_resolve"),
+                Attribute::new("round", "0"),
+                Attribute::new("counted", "1"),
+                Attribute::new("jackpot_rolled_over", "100000"),
+                Attribute::new("resolved", "true"),
+            ]
+        );
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::LotteryAlreadyResolved {});
+        assert_eq!(
+            query_lottery_state(deps.as_ref(), 1).unwrap().jackpot,
+            Uint128::from(100_000u128)
+        );
+
+        // Round 1 alice and bob hit the top tier, the same randomness is used by the mock
+        let winning_msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 1,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        for player in &["alice", "bob"] {
+            let sender = mock_info(
+                player,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128),
+                }],
+            );
+            execute(deps.as_mut(), env.clone(), sender, winning_msg.clone()).unwrap();
+        }
+        assert_eq!(
+            query_current_round(deps.as_ref(), env.clone())
+                .unwrap()
+                .jackpot,
+            Uint128::from(300_000u128)
+        );
+
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+
+        // The top tier can't be collected before the winners are counted
+        let collect_msg = ExecuteMsg::Collect {
+            round: 1,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            collect_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LotteryNotResolved {});
+
+        // Count by batch of one game
+        let msg = ExecuteMsg::Resolve {
+            round: 1,
+            limit: Some(1),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[3], Attribute::new("resolved", "false"));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.attributes[3], Attribute::new("resolved", "true"));
+
+        let lottery = query_lottery_state(deps.as_ref(), 1).unwrap();
+        assert_eq!(lottery.winners, vec![0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(lottery.jackpot, Uint128::from(300_000u128));
+        // Jackpot won, nothing rolled over
+        assert_eq!(
+            query_lottery_state(deps.as_ref(), 2).unwrap().jackpot,
+            Uint128::zero()
+        );
+
+        // Each winner gets half of the jackpot
+        let res = execute(deps.as_mut(), env, mock_info("alice", &[]), collect_msg).unwrap();
+        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
+            to_address: "alice".to_string(),
+            amount: vec![Coin {
+                denom: "uusd".to_string(),
+                amount: Uint128::from(139_603u128),
+            }],
+        });
+        assert_eq!(res.messages[0], SubMsg::new(msg_payout));
+    }
+
+    #[test]
+    fn pari_mutuel() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let mut msg = default_instantiate_msg();
+        msg.prize_mode = PrizeMode::PariMutuel;
+        msg.prize_share = vec![Decimal::percent(10); 8];
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let err = instantiate(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidPrizeShare {});
+        msg.prize_share = vec![Decimal::percent(20); 9];
+        let err = instantiate(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidPrizeShare {});
+        msg.prize_share = vec![Decimal::percent(10); 9];
+        init_with(deps.as_mut(), msg);
+
+        // Alice and bob hit the top tier, charlie 3 numbers and the bonus
+        for (player, numbers) in &[
+            ("alice", vec![4, 15, 6, 4, 7]),
+            ("bob", vec![4, 15, 6, 4, 7]),
+            ("charlie", vec![4, 15, 6, 5, 7]),
+            ("mario", vec![1, 1, 1, 1, 1]),
+        ] {
+            let sender = mock_info(
+                player,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(5_000_000u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(numbers.clone()),
+                multiplier: Uint128::from(5_000_000u128),
+                live_round: 1,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), mock_env(), sender, msg).unwrap();
+        }
+
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+
+        // Every tier needs the winners counted
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "charlie".to_string(),
+            game_id: vec![0],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::LotteryNotResolved {});
+
+        let resolve_msg = ExecuteMsg::Resolve {
+            round: 0,
+            limit: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            resolve_msg,
+        )
+        .unwrap();
+        assert_eq!(
+            query_lottery_state(deps.as_ref(), 0).unwrap().winners,
+            vec![0, 0, 0, 0, 0, 0, 1, 0, 2]
+        );
 
-        let mut env = mock_env();
-        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
-        let res = instantiate(deps, env, mock_info("creator", &[]), msg).unwrap();
-        assert_eq!(0, res.messages.len());
-    }
-    #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies(&[]);
-        default_init(deps.as_mut());
+        // 10% of 20 UST for charlie alone
+        let tickets =
+            query_player_tickets(deps.as_ref(), "charlie".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].prize, Uint128::from(2_000_000u128));
+        // 10% of 20 UST shared by alice and bob
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].prize, Uint128::from(1_000_000u128));
+        let tickets =
+            query_player_tickets(deps.as_ref(), "mario".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Lost);
+
+        execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap();
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(100_000u128));
     }
 
     #[test]
-    fn try_register() {
-        let mut deps = mock_dependencies(&[]);
+    fn quick_pick() {
+        let mut deps = custom_mock_dependencies(&[]);
         default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
         let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
+            numbers: None,
+            multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: None,
         };
         let sender = mock_info(
             "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(5_000_000u128),
+                amount: Uint128::from(1_000_000u128),
             }],
         );
-        let mut env = mock_env();
-        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(301);
-
-        // Error time not started yet
-        let err = execute(deps.as_mut(), env, sender.clone(), msg).unwrap_err();
-        assert_eq!(err, ContractError::RegisterClosed {});
-
-        let mut env = mock_env();
-        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
 
-        // Remove this because not fitting with the current design
-        // // Error duplicated numbers
-        // let msg = ExecuteMsg::Register {
-        //     numbers: vec![vec![5, 7, 5, 15, 2], vec![1, 2, 17, 1, 3]],
-        //     address: None,
-        //     live_round: 1
-        // };
-        // let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        // assert_eq!(err, ContractError::WrongSetOfBallsOrDuplicateNotAllowed {});
+        // Numbers are unknown until the draw
+        let games = query_games(deps.as_ref(), None, None, None, 0, "alice".to_string()).unwrap();
+        assert!(games.games[0].quick_pick);
+        assert!(games.games[0].number.is_empty());
 
-        // Error bonus out of range
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 10],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 1,
-            address: None,
-        };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        assert_eq!(err, ContractError::BonusOutOfRange {});
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 0],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 1,
-            address: None,
-        };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        assert_eq!(err, ContractError::BonusOutOfRange {});
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
 
-        // live round error max life exceeded
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 0,
-            address: None,
-        };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        assert_eq!(err, ContractError::LiveRoundMaxLifeExceeded {});
+        let randomness = query_lottery_state(deps.as_ref(), 0)
+            .unwrap()
+            .randomness
+            .unwrap();
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
+        let (number, bonus) =
+            quick_pick_numbers(randomness.as_slice(), &game_key(alice_raw.as_slice(), 0), 4);
+        let games = query_games(deps.as_ref(), None, None, None, 0, "alice".to_string()).unwrap();
+        assert_eq!(games.games[0].number, number);
+        assert_eq!(games.games[0].bonus, bonus);
+        assert_eq!(number.len(), 4);
 
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 6,
-            address: None,
+        // Counting the winners stores the numbers
+        let msg = ExecuteMsg::Resolve {
+            round: 0,
+            limit: None,
         };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        assert_eq!(err, ContractError::LiveRoundMaxLifeExceeded {});
+        execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap();
+        let game = GAMES
+            .load(
+                &deps.storage,
+                (
+                    &0u64.to_be_bytes(),
+                    alice_raw.as_slice(),
+                    &0u64.to_be_bytes(),
+                ),
+            )
+            .unwrap();
+        assert_eq!(game.number, number);
+        assert_eq!(game.bonus, bonus);
+    }
 
-        // // Wrong set of balls
-        // let msg = ExecuteMsg::Register {
-        //     numbers: vec![vec![5, 7, 1, 15, 4, 4], vec![1, 2, 17, 5, 1]],
-        //     address: None,
-        //     live_round: 1
-        // };
-        // let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        // assert_eq!(err, ContractError::WrongSetOfBallsOrDuplicateNotAllowed {});
+    #[test]
+    fn subscription() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
-        // Success
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 1,
+        // More rounds than live_round_max
+        let msg = ExecuteMsg::Subscribe {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            rounds: 8,
             address: None,
         };
-        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
-        assert_eq!(
-            res.attributes,
-            vec![
-                Attribute::new("method", "try_register"),
-                Attribute::new("round", "[0]"),
-                Attribute::new("live_round", "1"),
-                Attribute::new("ticket_amount", "1"),
-                Attribute::new("sender", "alice"),
-                Attribute::new("recipient", "alice"),
-            ]
-        );
-        // Error sent less than required
         let sender = mock_info(
             "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(1_000_000u128),
+                amount: Uint128::from(5_000_000u128),
             }],
         );
-
-        let msg = ExecuteMsg::Register {
-            numbers: vec![1, 2, 17, 6, 4],
-            multiplier: Uint128::from(1_000_000u128),
-            live_round: 4,
-            address: None,
-        };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
+        let err = execute(deps.as_mut(), env.clone(), sender, msg.clone()).unwrap_err();
         assert_eq!(
             err,
             ContractError::AmountSentError(
-                Uint128::from(1_000_000u128),
-                Uint128::from(4_000_000u128)
+                Uint128::from(5_000_000u128),
+                Uint128::from(8_000_000u128)
             )
         );
         let sender = mock_info(
             "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(4_000_000u128),
+                amount: Uint128::from(8_000_000u128),
             }],
         );
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
 
-        let msg = ExecuteMsg::Register {
-            numbers: vec![1, 2, 17, 6, 4],
-            multiplier: Uint128::from(1_000_000u128),
-            live_round: 4,
-            address: None,
-        };
-        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
-        assert_eq!(
-            res.attributes,
-            vec![
-                Attribute::new("method", "try_register"),
-                Attribute::new("round", "[0, 1, 2, 3]"),
-                Attribute::new("live_round", "4"),
-                Attribute::new("ticket_amount", "1"),
-                Attribute::new("sender", "alice"),
-                Attribute::new("recipient", "alice"),
-            ]
-        );
+        // Nothing is written until the round is drawn
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
 
-        let games = query_games(deps.as_ref(), None, None, 0, "alice".to_string()).unwrap();
-        assert_eq!(
-            games,
-            vec![
-                GameResponse {
-                    number: vec![1, 2, 17, 6],
-                    bonus: 4,
-                    multiplier: Decimal::from_str("1").unwrap(),
-                    resolved: false,
-                    game_id: 1,
-                    lottery_id: 0
-                },
-                GameResponse {
-                    number: vec![5, 7, 12, 15],
-                    bonus: 1,
-                    multiplier: Decimal::from_str("5").unwrap(),
-                    resolved: false,
-                    game_id: 0,
-                    lottery_id: 0
-                }
-            ]
-        );
-        let games = query_games(deps.as_ref(), None, None, 1, "alice".to_string()).unwrap();
-        assert_eq!(
-            games,
-            vec![GameResponse {
-                number: vec![1, 2, 17, 6],
-                bonus: 4,
-                multiplier: Decimal::from_str("1").unwrap(),
-                resolved: false,
-                game_id: 0,
-                lottery_id: 1
-            }]
-        );
-        let games = query_games(deps.as_ref(), None, None, 2, "alice".to_string()).unwrap();
+        for round in 0..2u64 {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+            let stats = query_lottery_stats(deps.as_ref(), round).unwrap();
+            assert_eq!(stats.total_ticket_sold, 1);
+            assert_eq!(stats.counter_player, 1);
+            assert_eq!(stats.total_collected, Uint128::from(1_000_000u128));
+            let games =
+                query_games(deps.as_ref(), None, None, None, round, "alice".to_string()).unwrap();
+            assert_eq!(games.games[0].number, vec![4, 15, 6, 4]);
+        }
+        let lottery = query_lottery_state(deps.as_ref(), 0).unwrap();
+        assert!(lottery.winning_number.is_some());
+
+        let subscriptions =
+            query_subscriptions(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert_eq!(subscriptions.subscriptions[0].remaining_rounds, 6);
         assert_eq!(
-            games,
-            vec![GameResponse {
-                number: vec![1, 2, 17, 6],
-                bonus: 4,
-                multiplier: Decimal::from_str("1").unwrap(),
-                resolved: false,
-                game_id: 0,
-                lottery_id: 2
-            }]
+            subscriptions.subscriptions[0].balance,
+            Uint128::from(6_000_000u128)
         );
-        let games = query_games(deps.as_ref(), None, None, 3, "alice".to_string()).unwrap();
+
+        let msg = ExecuteMsg::CancelSubscription { subscription_id: 0 };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SubscriptionNotFound {});
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
         assert_eq!(
-            games,
-            vec![GameResponse {
-                number: vec![1, 2, 17, 6],
-                bonus: 4,
-                multiplier: Decimal::from_str("1").unwrap(),
-                resolved: false,
-                game_id: 0,
-                lottery_id: 3
-            }]
+            res.attributes[3],
+            Attribute::new("refund", "6000000".to_string())
         );
+        match &res.messages[0].msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, .. }) => assert_eq!(to_address, "alice"),
+            _ => panic!("unexpected message"),
+        }
+        let subscriptions =
+            query_subscriptions(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert!(subscriptions.subscriptions.is_empty());
+
+        // Cancelled subscriptions stop playing
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 2).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+    }
+
+    #[test]
+    fn cancel_subscription_after_registration_closed() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let msg = ExecuteMsg::Subscribe {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            rounds: 3,
+            address: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[Coin::new(3_000_000, "uusd")]),
+            msg,
+        )
+        .unwrap();
+
+        // The round closed still plays, only the next rounds are refunded
+        env.block.time = env.block.time.plus_seconds(301);
+        let msg = ExecuteMsg::CancelSubscription { subscription_id: 0 };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[2], Attribute::new("refunded_rounds", "2"));
+        assert_eq!(res.attributes[3], Attribute::new("refund", "2000000"));
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NothingToCancel {});
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 1);
+        assert_eq!(stats.total_collected, Uint128::from(1_000_000u128));
+        let subscriptions =
+            query_subscriptions(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert!(subscriptions.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn subscription_batches() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let subscribe = |numbers: Vec<u8>| ExecuteMsg::Subscribe {
+            numbers: Some(numbers),
+            multiplier: Uint128::from(1_000_000u128),
+            rounds: 1,
+            address: None,
+        };
+        let sender = mock_info("alice", &[Coin::new(1_000_000, "uusd")]);
+
+        // A short or out of range ticket would stop every draw
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            sender.clone(),
+            subscribe(vec![3]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidNumbers(4, 1));
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            sender.clone(),
+            subscribe(vec![4, 15, 6, 17, 7]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidNumbers(4, 1));
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            sender.clone(),
+            subscribe(vec![4, 15, 6, 4, 9]),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::BonusOutOfRange {});
+
+        for _ in 0..80 {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                sender.clone(),
+                subscribe(vec![4, 15, 6, 4, 7]),
+            )
+            .unwrap();
+        }
+        let play = |limit: u32| ExecuteMsg::PlaySubscriptions { limit: Some(limit) };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), play(40)).unwrap_err();
+        assert_eq!(err, ContractError::LotteryInProgress {});
+
+        // More subscriptions than a draw plays
+        env.block.time = env.block.time.plus_seconds(300);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::SubscriptionsNotPlayed {});
+
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), play(40)).unwrap();
+        assert_eq!(res.attributes[2], Attribute::new("played", "40"));
+        assert_eq!(res.attributes[3], Attribute::new("done", "false"));
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 40);
+
+        // The draw plays the last batch
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 80);
+        assert_eq!(stats.total_collected, Uint128::from(80_000_000u128));
+        let subscriptions =
+            query_subscriptions(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert!(subscriptions.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn multi_round_tickets() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
-        // Error sent
         let sender = mock_info(
-            "bob",
+            "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(10_000_000u128),
+                amount: Uint128::from(3_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 1,
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 3,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let err = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap_err();
-        assert_eq!(
-            err,
-            ContractError::AmountSentError(
-                Uint128::from(10_000_000u128),
-                Uint128::from(5_000_000u128)
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
+        assert!(!GAMES.has(
+            &deps.storage,
+            (
+                &1u64.to_be_bytes(),
+                alice_raw.as_slice(),
+                &0u64.to_be_bytes()
             )
-        );
+        ));
 
-        // Success
+        let draw = |deps: &mut OwnedDeps<_, _, _>, env: &mut Env| {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        };
+        let collect = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+
+        draw(&mut deps, &mut env);
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_ticket_sold, 1);
+        assert_eq!(stats.counter_player, 1);
+        assert_eq!(stats.total_collected, Uint128::from(1_000_000u128));
+
+        // Alice plays a single ticket too, she is already counted in this round
         let sender = mock_info(
-            "bob",
+            "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(5_000_000u128),
+                amount: Uint128::from(1_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
+            numbers: Some(vec![1, 1, 1, 1, 1]),
+            multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let res = execute(deps.as_mut(), env.clone(), sender.clone(), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_ticket_sold, 2);
+        assert_eq!(stats.counter_player, 1);
+
+        // Only the drawn rounds are paid
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            collect.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            collect.clone(),
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Unresolved);
+        assert_eq!(tickets.tickets[0].game.end_round, Some(2));
+
+        draw(&mut deps, &mut env);
+        draw(&mut deps, &mut env);
+        let stats = query_lottery_stats(deps.as_ref(), 3).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+
+        // The ticket is counted in the winners of every round it plays
+        for round in 0..3 {
+            let msg = ExecuteMsg::Resolve { round, limit: None };
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+            let lottery = query_lottery_state(deps.as_ref(), round).unwrap();
+            assert_eq!(lottery.winners[8], 1);
+        }
+
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].status, TicketStatus::Won);
+        assert_eq!(tickets.tickets[0].prize, Uint128::from(30_000_000_000u128));
+
+        execute(deps.as_mut(), env, mock_info("alice", &[]), collect).unwrap();
+        let game = GAMES
+            .load(
+                &deps.storage,
+                (
+                    &0u64.to_be_bytes(),
+                    alice_raw.as_slice(),
+                    &0u64.to_be_bytes(),
+                ),
+            )
+            .unwrap();
+        assert_eq!(game.collected_round, Some(2));
+        assert!(game.resolved);
+    }
+
+    #[test]
+    fn cancel_ticket() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            cancel_fee: Decimal::from_str("1.1").unwrap(),
+            ..default_instantiate_msg()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::CancelFeeTooHigh {});
+        let msg = InstantiateMsg {
+            cancel_fee: Decimal::from_str("0.1").unwrap(),
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        let register = |deps: &mut OwnedDeps<_, _, _>, env: &Env| {
+            let sender = mock_info(
+                "alice",
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(3_000_000u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 3,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        };
+        let cancel = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
 
+        // All the rounds are refunded while the ticket round is open
+        register(&mut deps, &env);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            cancel.clone(),
+        )
+        .unwrap();
         assert_eq!(
-            res.attributes,
-            vec![
-                Attribute::new("method", "try_register"),
-                Attribute::new("round", "[0]"),
-                Attribute::new("live_round", "1"),
-                Attribute::new("ticket_amount", "1"),
-                Attribute::new("sender", "bob"),
-                Attribute::new("recipient", "bob"),
-            ]
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(2_673_267u128),
+                }],
+            }))]
         );
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(300_000u128));
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+        assert_eq!(stats.counter_player, 0);
+        assert_eq!(stats.total_collected, Uint128::zero());
+        assert!(!GAMES_STATS.has(&deps.storage, (alice_raw.as_slice(), &0u64.to_be_bytes())));
+        let players = query_round_players(deps.as_ref(), 0, None, None, None).unwrap();
+        assert!(players.players.is_empty());
+
+        // Once its round is drawn only the next rounds are refunded
+        register(&mut deps, &env);
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_ticket_sold, 1);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            cancel.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[3].value, "2");
+        assert_eq!(res.attributes[4].value, "2000000");
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+        assert_eq!(stats.total_collected, Uint128::zero());
+        let game_stats = GAMES_STATS
+            .load(&deps.storage, (alice_raw.as_slice(), &0u64.to_be_bytes()))
+            .unwrap();
+        assert_eq!(game_stats.total_spent, Uint128::from(1_000_000u128));
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[0].game.end_round, None);
+
+        let err = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), cancel);
+        assert_eq!(err.unwrap_err(), ContractError::NothingToCancel {});
+
+        // The cancelled rounds are not played anymore
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 2).unwrap();
+        assert_eq!(stats.total_ticket_sold, 0);
+        assert_eq!(stats.counter_player, 0);
+    }
+
+    #[test]
+    fn cancel_ticket_price() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            ticket_price: vec![
+                Uint128::from(500_000u128),
+                Uint128::from(1_000_000u128),
+                Uint128::from(2_500_000u128),
+            ],
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        // The rounds are refunded at the price paid, not at the multiplier tier requested
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(2_000_000u128),
+            live_round: 2,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let sender = mock_info("alice", &[Coin::new(2_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.total_collected, Uint128::from(1_000_000u128));
 
-        let games = query_games(deps.as_ref(), None, None, 0, "bob".to_string()).unwrap();
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_collected, Uint128::from(1_000_000u128));
+        let msg = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let res = execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap();
         assert_eq!(
-            games,
-            vec![GameResponse {
-                number: vec![5, 7, 12, 15],
-                bonus: 1,
-                multiplier: Decimal::from_str("5").unwrap(),
-                resolved: false,
-                game_id: 0,
-                lottery_id: 0
-            },]
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin::new(990_099, "uusd")],
+            }))]
         );
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.total_collected, Uint128::zero());
     }
 
     #[test]
-    fn try_draw() {
+    fn collect_after_partial_cancel() {
         let mut deps = custom_mock_dependencies(&[]);
         default_init(deps.as_mut());
-
-        let msg = ExecuteMsg::Draw {};
-        let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), msg).unwrap_err();
-        assert_eq!(err, ContractError::LotteryInProgress {});
-
         let mut env = mock_env();
         env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 3,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        let sender = mock_info("alice", &[Coin::new(3_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
         env.block.time = env.block.time.plus_seconds(300);
-        let msg = ExecuteMsg::Draw {};
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
 
-        let past_lottery_state = query_lottery_state(deps.as_ref(), 0).unwrap();
-        assert_eq!(past_lottery_state.terrand_round, 13);
-        assert_eq!(past_lottery_state.draw_time, 1595431350);
-        assert_eq!(past_lottery_state.bonus_number, None);
-        assert_eq!(past_lottery_state.winning_number, None);
-        assert_eq!(
-            past_lottery_state.multiplier,
-            vec![
-                Decimal::from_str("1").unwrap(),
-                Decimal::from_str("2").unwrap(),
-                Decimal::from_str("5").unwrap()
-            ]
-        );
-        assert_eq!(
-            past_lottery_state.ticket_price,
-            vec![
-                Uint128::from(1000000u128),
-                Uint128::from(2000000u128),
-                Uint128::from(5000000u128)
-            ]
-        );
+        let collect = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            collect.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        // Only the collected round is left, the ticket can't be collected again
+        let msg = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
         assert_eq!(
-            past_lottery_state.prize_rank,
-            vec![
-                Uint128::from(1000000u128),
-                Uint128::from(2000000u128),
-                Uint128::from(5000000u128),
-                Uint128::from(10000000u128),
-                Uint128::from(30000000u128),
-                Uint128::from(50000000u128),
-                Uint128::from(150000000u128),
-                Uint128::from(1000000000u128),
-                Uint128::from(10000000000u128)
-            ]
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin::new(1_980_198, "uusd")],
+            }))]
         );
+        let err = execute(deps.as_mut(), env, mock_info("alice", &[]), collect).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyCollected {});
+    }
+
+    #[test]
+    fn cancel_ticket_players() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        let register = |deps: &mut OwnedDeps<_, _, _>, env: &Env, player: &str| {
+            let sender = mock_info(
+                player,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(3_000_000u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 3,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        };
+        let cancel = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let counter_players = |deps: &OwnedDeps<_, _, _>| {
+            (0..3)
+                .map(|round| {
+                    let stats = query_lottery_stats(deps.as_ref(), round).unwrap();
+                    let players = query_round_players(deps.as_ref(), round, None, None, None)
+                        .unwrap()
+                        .players;
+                    assert_eq!(stats.counter_player, players.len() as u64);
+                    stats.counter_player
+                })
+                .collect::<Vec<u64>>()
+        };
+
+        let draw = |deps: &mut OwnedDeps<_, _, _>, env: &mut Env| {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        };
+
+        // The players are counted in all the rounds of their tickets when bought
+        register(&mut deps, &env, "alice");
+        register(&mut deps, &env, "bob");
+        assert_eq!(counter_players(&deps), vec![2, 2, 2]);
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            cancel.clone(),
+        )
+        .unwrap();
+        assert_eq!(counter_players(&deps), vec![1, 1, 1]);
+
+        // Once the next round is open the player is only removed from the rounds cancelled
+        register(&mut deps, &env, "alice");
+        draw(&mut deps, &mut env);
+        assert_eq!(counter_players(&deps), vec![2, 2, 2]);
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), cancel).unwrap();
+        assert_eq!(counter_players(&deps), vec![2, 1, 1]);
+        let players = query_round_players(deps.as_ref(), 1, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string()]);
+        draw(&mut deps, &mut env);
+        assert_eq!(counter_players(&deps), vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn cancel_ticket_during_resolution() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            prize_mode: PrizeMode::PariMutuel,
+            prize_share: vec![Decimal::percent(10); 9],
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        let register = |deps: &mut OwnedDeps<_, _, _>, env: &Env, player, numbers, live_round| {
+            let sender = mock_info(
+                player,
+                &[Coin::new(1_000_000 * u128::from(live_round), "uusd")],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(numbers),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        };
+        let draw = |deps: &mut OwnedDeps<_, _, _>, env: &mut Env| {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        };
+        let resolve = |deps: &mut OwnedDeps<_, _, _>, env: &Env, round, limit| {
+            let msg = ExecuteMsg::Resolve { round, limit };
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        };
+
+        // Alice hits the top tier in the rounds 1 to 5, bob loses in the rounds 2 to 4
+        draw(&mut deps, &mut env);
+        register(&mut deps, &env, "alice", vec![4, 15, 6, 4, 7], 5);
+        draw(&mut deps, &mut env);
+        register(&mut deps, &env, "bob", vec![1, 1, 1, 1, 1], 3);
+        draw(&mut deps, &mut env);
+        draw(&mut deps, &mut env);
+        for round in 0..3 {
+            resolve(&mut deps, &env, round, None);
+        }
+        // The resolution of the round 3 counted bob ending in the round 4 only
+        resolve(&mut deps, &env, 3, Some(1));
+        assert!(!query_lottery_state(deps.as_ref(), 3).unwrap().resolved);
+
+        // Cancelling the rounds 4 and 5 makes alice end in the round 3, behind the cursor
+        let cancel = ExecuteMsg::CancelTicket {
+            start_round: 1,
+            game_id: 0,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), cancel).unwrap();
+        resolve(&mut deps, &env, 3, None);
+        let lottery = query_lottery_state(deps.as_ref(), 3).unwrap();
+        assert!(lottery.resolved);
+        assert_eq!(lottery.winners, vec![0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        // Alice collects her share of the top tier of the rounds 1 to 3
+        let msg = ExecuteMsg::Collect {
+            round: 1,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        // The cancelled rounds are not played by alice anymore
+        draw(&mut deps, &mut env);
+        resolve(&mut deps, &env, 4, None);
+        let lottery = query_lottery_state(deps.as_ref(), 4).unwrap();
+        assert_eq!(lottery.winners, vec![0; 9]);
+        let players = query_round_players(deps.as_ref(), 4, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["bob".to_string()]);
+        let players = query_round_players(deps.as_ref(), 5, None, None, None)
+            .unwrap()
+            .players;
+        assert!(players.is_empty());
+    }
+
+    #[test]
+    fn abort_round() {
+        let mut deps = custom_mock_dependencies(&[]);
+        // The refunds look live_round_max - 1 rounds back
+        let msg = InstantiateMsg {
+            live_round_max: 0,
+            ..default_instantiate_msg()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::LiveRoundMaxZero {});
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
-        let new_lottery_state = query_lottery_state(deps.as_ref(), 1).unwrap();
-        assert_eq!(new_lottery_state.terrand_round, 23);
-        assert_eq!(new_lottery_state.draw_time, 1595431650);
-        assert_eq!(new_lottery_state.bonus_number, None);
-        assert_eq!(new_lottery_state.winning_number, None);
-        assert_eq!(
-            new_lottery_state.multiplier,
-            vec![
-                Decimal::from_str("1").unwrap(),
-                Decimal::from_str("2").unwrap(),
-                Decimal::from_str("5").unwrap()
-            ]
+        let register = |deps: &mut OwnedDeps<_, _, _>, env: &Env, numbers, live_round: u16| {
+            let sender = mock_info(
+                "alice",
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128 * live_round as u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(numbers),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        };
+        let draw = |deps: &mut OwnedDeps<_, _, _>, env: &mut Env| {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        };
+        register(&mut deps, &env, vec![1, 1, 1, 1, 1], 1);
+        register(&mut deps, &env, vec![4, 15, 6, 4, 7], 3);
+        draw(&mut deps, &mut env);
+        register(&mut deps, &env, vec![1, 1, 1, 1, 1], 1);
+
+        // Only the owner can abort the open round before the timeout
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::AbortRound { round: 1 },
         );
-        assert_eq!(
-            new_lottery_state.ticket_price,
-            vec![
-                Uint128::from(1000000u128),
-                Uint128::from(2000000u128),
-                Uint128::from(5000000u128)
-            ]
+        assert_eq!(res.unwrap_err(), ContractError::Unauthorized {});
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AbortRound { round: 0 },
         );
-        assert_eq!(
-            new_lottery_state.prize_rank,
-            vec![
-                Uint128::from(1000000u128),
-                Uint128::from(2000000u128),
-                Uint128::from(5000000u128),
-                Uint128::from(10000000u128),
-                Uint128::from(30000000u128),
-                Uint128::from(50000000u128),
-                Uint128::from(150000000u128),
-                Uint128::from(1000000000u128),
-                Uint128::from(10000000000u128)
-            ]
+        assert_eq!(res.unwrap_err(), ContractError::CannotAbortRound {});
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AbortRound { round: 1 },
+        )
+        .unwrap();
+        let state = query_state(deps.as_ref()).unwrap();
+        assert_eq!(state.round, 2);
+        let lottery = query_lottery_state(deps.as_ref(), 1).unwrap();
+        assert!(lottery.cancelled);
+        assert_eq!(lottery.winning_number, None);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::ClaimRefund {
+                round: 0,
+                player: "alice".to_string(),
+            },
         );
+        assert_eq!(res.unwrap_err(), ContractError::LotteryNotCancelled {});
 
-        let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 2,
-            address: None,
+        // The ticket bought in the round and the round of the multi-round ticket are refunded,
+        // anyone can claim them for alice. The multi-round ticket is found even once the
+        // live_round_max it was bought with is lowered
+        let msg = MigrateMsg {
+            update_live_round_max: 1,
+            ..default_migrate_msg()
         };
-        let sender = mock_info(
-            "alice",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(10_000_000u128),
-            }],
+        migrate(deps.as_mut(), env.clone(), msg).unwrap();
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimRefund {
+                round: 1,
+                player: "alice".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_980_198u128),
+                }],
+            }))
         );
-
-        let res = execute(deps.as_mut(), env.clone(), sender, msg.clone()).unwrap();
-        let sender = mock_info(
-            "bob",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(10_000_000u128),
-            }],
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::ClaimRefund {
+                round: 1,
+                player: "alice".to_string(),
+            },
         );
-        let res = execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
-
-        env.block.time = env.block.time.plus_seconds(300);
-        let msg = ExecuteMsg::Draw {};
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        let worker_msg = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin::new(990_099, "uusd")],
-        });
-        assert_eq!(res.messages, vec![SubMsg::new(worker_msg)]);
+        assert_eq!(res.unwrap_err(), ContractError::RefundAlreadyClaimed {});
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimRefund {
+                round: 1,
+                player: "bob".to_string(),
+            },
+        );
+        assert_eq!(res.unwrap_err(), ContractError::NothingToRefund {});
 
-        let lottery_stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
-        println!("{:?}", lottery_stats);
-        let new_lottery_state = query_lottery_state(deps.as_ref(), 1).unwrap();
-        assert_eq!(new_lottery_state.bonus_number, Some(7));
-        assert_eq!(new_lottery_state.winning_number, Some(vec![4, 15, 6, 4]));
+        // The aborted round has no winners, the multi-round ticket plays the next round
+        draw(&mut deps, &mut env);
+        for round in 0..3 {
+            let msg = ExecuteMsg::Resolve { round, limit: None };
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        }
+        let lottery = query_lottery_state(deps.as_ref(), 1).unwrap();
+        assert!(lottery.resolved);
+        assert_eq!(lottery.winners[8], 0);
+        let tickets =
+            query_player_tickets(deps.as_ref(), "alice".to_string(), None, None, None, None)
+                .unwrap();
+        assert_eq!(tickets.tickets[1].status, TicketStatus::Won);
+        assert_eq!(tickets.tickets[1].prize, Uint128::from(20_000_000_000u128));
 
-        let lottery_stats = query_lottery_stats(deps.as_ref(), 2).unwrap();
-        println!("{:?}", lottery_stats);
-        let new_lottery_state = query_lottery_state(deps.as_ref(), 2).unwrap();
+        // The owner can't abort a round once its beacon can be known
+        env.block.time = env.block.time.plus_seconds(301);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AbortRound { round: 3 },
+        );
+        assert_eq!(res.unwrap_err(), ContractError::AbortAfterDrawTime {});
 
-        assert_eq!(new_lottery_state.bonus_number, None);
-        assert_eq!(new_lottery_state.winning_number, None);
+        // Anyone can abort a round once terrand is late, as long as its randomness is unknown
+        env.block.time = env.block.time.plus_seconds(ABORT_TIMEOUT);
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::AbortRound { round: 3 },
+        );
+        assert_eq!(res.unwrap_err(), ContractError::RandomnessAvailable {});
+        deps.querier.set_randomness(Binary::default());
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::AbortRound { round: 3 },
+        )
+        .unwrap();
     }
 
     #[test]
-    fn try_collect() {
+    fn collect_after_aborted_round() {
         let mut deps = custom_mock_dependencies(&[]);
         default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
         let sender = mock_info(
             "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(10_000_000u128),
+                amount: Uint128::from(3_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![5, 7, 12, 15, 1],
-            multiplier: Uint128::from(5_000_000u128),
-            live_round: 2,
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 3,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AbortRound { round: 0 },
+        )
+        .unwrap();
+        for _ in 0..2 {
+            env.block.time = env.block.time.plus_seconds(300);
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("bob", &[]),
+                ExecuteMsg::Draw {},
+            )
+            .unwrap();
+        }
+        for round in 0..3 {
+            let msg = ExecuteMsg::Resolve { round, limit: None };
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        }
 
-        // Alice winning number found
-        let sender = mock_info(
-            "alice",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(2_000_000u128),
-            }],
+        // The aborted start round is skipped, the next rounds are collected
+        let msg = ExecuteMsg::Collect {
+            round: 0,
+            player: "alice".to_string(),
+            game_id: vec![0],
+        };
+        let res = execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(18_799_000_000u128),
+                }],
+            }))]
         );
-        let msg = ExecuteMsg::Register {
-            numbers: vec![4, 15, 6, 4, 7],
-            multiplier: Uint128::from(2_000_000u128),
-            live_round: 1,
-            address: None,
+        let game = GAMES
+            .load(
+                &deps.storage,
+                (
+                    &0u64.to_be_bytes(),
+                    deps.api.addr_canonicalize("alice").unwrap().as_slice(),
+                    &0u64.to_be_bytes(),
+                ),
+            )
+            .unwrap();
+        assert_eq!(game.collected_round, Some(2));
+        assert!(game.resolved);
+    }
+
+    #[test]
+    fn gifts() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
+        let gift = |deps: &mut OwnedDeps<_, _, _>, player: &str, memo: Option<String>| {
+            let sender = mock_info(
+                "alice",
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 1,
+                address: Some(player.to_string()),
+                memo,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg)
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        let err = gift(&mut deps, "bob", Some("a".repeat(65)));
+        assert_eq!(err.unwrap_err(), ContractError::MemoTooLong(64));
+        gift(&mut deps, "bob", Some("Happy birthday".to_string())).unwrap();
+        gift(&mut deps, "carol", None).unwrap();
+        // Tickets bought for herself are not gifts
+        gift(&mut deps, "alice", None).unwrap();
 
-        // Bob 3 numbers found and 1 bonus
-        let sender = mock_info(
-            "bob",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(2_000_000u128),
-            }],
-        );
-        let msg = ExecuteMsg::Register {
-            numbers: vec![4, 15, 6, 5, 7],
-            multiplier: Uint128::from(2_000_000u128),
-            live_round: 1,
-            address: None,
+        let games = query_games(deps.as_ref(), None, None, None, 0, "bob".to_string()).unwrap();
+        assert_eq!(games.games[0].buyer, Some("alice".to_string()));
+        assert_eq!(games.games[0].memo, Some("Happy birthday".to_string()));
+        let games = query_games(deps.as_ref(), None, None, None, 0, "alice".to_string()).unwrap();
+        assert_eq!(games.games[0].buyer, None);
+
+        let gifts = query_gifts(deps.as_ref(), "alice".to_string(), None, Some(1), None).unwrap();
+        assert_eq!(gifts.gifts.len(), 1);
+        let next_cursor = gifts.next_cursor.clone();
+        assert_eq!(next_cursor, Some((0, gifts.gifts[0].player.clone(), 0)));
+        let page =
+            query_gifts(deps.as_ref(), "alice".to_string(), next_cursor, None, None).unwrap();
+        assert_eq!(page.gifts.len(), 1);
+        assert_eq!(page.next_cursor, None);
+        assert_ne!(page.gifts[0].player, gifts.gifts[0].player);
+
+        // A gift cancelled by its player is not listed anymore
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::CancelTicket {
+                start_round: 0,
+                game_id: 0,
+            },
+        )
+        .unwrap();
+        let gifts = query_gifts(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert_eq!(gifts.gifts.len(), 1);
+        assert_eq!(gifts.gifts[0].player, "carol".to_string());
+
+        // A gifted subscription is a gift in every round it plays
+        let msg = ExecuteMsg::Subscribe {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            rounds: 1,
+            address: Some("dave".to_string()),
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        let sender = mock_info("alice", &[Coin::new(1_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        let subscriptions =
+            query_subscriptions(deps.as_ref(), "dave".to_string(), None, None, None).unwrap();
+        assert_eq!(
+            subscriptions.subscriptions[0].buyer,
+            Some("alice".to_string())
+        );
 
-        // Charlie 2 numbers found and 0 bonus
-        let sender = mock_info(
-            "charlie",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(2_000_000u128),
-            }],
+        env.block.time = env.block.time.plus_seconds(300);
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let event = res
+            .events
+            .iter()
+            .find(|event| event.ty == "loterra_ticket_registered")
+            .unwrap();
+        assert!(event
+            .attributes
+            .contains(&Attribute::new("buyer", "alice".to_string())));
+        let gifts = query_gifts(deps.as_ref(), "alice".to_string(), None, None, None).unwrap();
+        assert_eq!(gifts.gifts.len(), 2);
+        assert!(gifts.gifts.iter().any(|gift| gift.player == "dave"));
+        assert_eq!(gifts.gifts[0].game.number, vec![4, 15, 6, 4]);
+    }
+
+    #[test]
+    fn referral() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.06").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::ReferralShareTooHigh {});
+        // The collector only earns fees on the winnings, it can't pay the rewards of every sale
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            ..default_instantiate_msg()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg);
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::ReferralWithoutFeeOnSales {}
         );
-        let msg = ExecuteMsg::Register {
-            numbers: vec![4, 15, 6, 2, 2],
-            multiplier: Uint128::from(2_000_000u128),
-            live_round: 1,
-            address: None,
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
 
-        // Mario 0 numbers found and 1 bonus refund
-        let sender = mock_info(
-            "mario",
-            &[Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(1_000_000u128),
-            }],
+        let register = |deps: &mut OwnedDeps<_, _, _>, sender: &str, address, referrer: &str| {
+            let sender = mock_info(
+                sender,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 1,
+                address,
+                memo: None,
+                referrer: Some(referrer.to_string()),
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg)
+        };
+        let err = register(&mut deps, "alice", None, "alice");
+        assert_eq!(err.unwrap_err(), ContractError::InvalidReferrer {});
+        let err = register(&mut deps, "carol", Some("alice".to_string()), "alice");
+        assert_eq!(err.unwrap_err(), ContractError::InvalidReferrer {});
+        register(&mut deps, "alice", None, "bob").unwrap();
+
+        // The reward is earned once the round is drawn
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.balance, Uint128::zero());
+        assert_eq!(rewards.pending, Uint128::from(20_000u128));
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimReferralRewards { limit: None },
+        );
+        assert_eq!(err.unwrap_err(), ContractError::NoReferralRewards {});
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.balance, Uint128::from(20_000u128));
+        assert_eq!(rewards.total_earned, Uint128::from(20_000u128));
+        assert_eq!(rewards.pending, Uint128::zero());
+        let players =
+            query_referred_players(deps.as_ref(), "bob".to_string(), None, None, None).unwrap();
+        assert_eq!(players.players, vec!["alice".to_string()]);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimReferralRewards { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0],
+            SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "bob".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(19_801u128),
+                }],
+            }))
         );
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.balance, Uint128::zero());
+        assert_eq!(rewards.total_earned, Uint128::from(20_000u128));
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimReferralRewards { limit: None },
+        );
+        assert_eq!(err.unwrap_err(), ContractError::NoReferralRewards {});
+
+        // The reward is withheld from the fee of its round
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(30_000u128));
         let msg = ExecuteMsg::Register {
-            numbers: vec![1, 1, 1, 1, 7],
+            numbers: Some(vec![4, 15, 6, 4, 7]),
             multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
-        // Mario 0 numbers found and 1 bonus refund
+        let sender = mock_info("carol", &[Coin::new(1_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(80_000u128));
+    }
+
+    #[test]
+    fn referral_cancelled_rounds() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+
         let sender = mock_info(
-            "mario",
+            "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(1_000_000u128),
+                amount: Uint128::from(3_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![4, 1, 1, 1, 1],
+            numbers: Some(vec![4, 15, 6, 4, 7]),
             multiplier: Uint128::from(1_000_000u128),
-            live_round: 1,
+            live_round: 3,
             address: None,
+            memo: None,
+            referrer: Some("bob".to_string()),
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
-        // Mario 0 numbers found
+        let res = execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        assert_eq!(res.attributes[1].value, "60000");
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.pending, Uint128::from(60_000u128));
+
+        // The rounds cancelled earn nothing
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        let cancel = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), cancel).unwrap();
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.balance, Uint128::from(20_000u128));
+        assert_eq!(rewards.pending, Uint128::zero());
+
+        // Neither do the aborted rounds
         let sender = mock_info(
-            "mario",
+            "carol",
             &[Coin {
                 denom: "uusd".to_string(),
                 amount: Uint128::from(1_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![1, 1, 1, 1, 1],
+            numbers: Some(vec![4, 15, 6, 4, 7]),
             multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: Some("bob".to_string()),
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AbortRound { round: 1 },
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::ClaimReferralRewards { limit: None },
+        )
+        .unwrap();
+        assert_eq!(res.attributes[1].value, "20000");
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.total_earned, Uint128::from(20_000u128));
+        // The rewards of the cancelled rounds are not withheld from their fees
+        let stats = LOTTERY_STATS
+            .load(&deps.storage, &2u64.to_be_bytes())
+            .unwrap();
+        assert_eq!(stats.referral_rewards, Uint128::zero());
+        assert!(PENDING_REFERRAL_REWARDS
+            .prefix(deps.api.addr_canonicalize("bob").unwrap().as_slice())
+            .range(&deps.storage, None, None, Order::Ascending)
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn referral_rewards_earned_by_round() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let bob_raw = deps.api.addr_canonicalize("bob").unwrap();
+        let pending_entries = |deps: &OwnedDeps<_, _, _>| {
+            PENDING_REFERRAL_REWARDS
+                .prefix(bob_raw.as_slice())
+                .range(deps.as_ref().storage, None, None, Order::Ascending)
+                .count()
+        };
+        let claim = |deps: &mut OwnedDeps<_, _, _>, env: &Env| {
+            let msg = ExecuteMsg::ClaimReferralRewards { limit: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+            res.attributes[1].value.clone()
+        };
+        let draw = |deps: &mut OwnedDeps<_, _, _>, env: &mut Env| {
+            env.block.time = env.block.time.plus_seconds(300);
+            let msg = ExecuteMsg::Draw {};
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        };
+
+        // A multi-round ticket is pending once, whatever its amount of rounds
+        let msg = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
+            live_round: 3,
+            address: None,
+            memo: None,
+            referrer: Some("bob".to_string()),
+        };
+        let sender = mock_info("alice", &[Coin::new(3_000_000, "uusd")]);
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        assert_eq!(pending_entries(&deps), 1);
+
+        // The rounds drawn are earned, the next ones stay pending
+        draw(&mut deps, &mut env);
+        assert_eq!(claim(&mut deps, &env), "20000");
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.pending, Uint128::from(40_000u128));
+        assert_eq!(pending_entries(&deps), 1);
+
+        draw(&mut deps, &mut env);
+        draw(&mut deps, &mut env);
+        assert_eq!(claim(&mut deps, &env), "40000");
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.total_earned, Uint128::from(60_000u128));
+        assert_eq!(rewards.pending, Uint128::zero());
+        assert_eq!(pending_entries(&deps), 0);
+    }
 
+    #[test]
+    fn claim_referral_rewards_by_batch() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            referral_share: Decimal::from_str("0.02").unwrap(),
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
         let mut env = mock_env();
         env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        for player in &["alice", "carol"] {
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 1,
+                address: None,
+                memo: None,
+                referrer: Some("bob".to_string()),
+            };
+            let sender = mock_info(player, &[Coin::new(1_000_000, "uusd")]);
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        }
         env.block.time = env.block.time.plus_seconds(300);
         let msg = ExecuteMsg::Draw {};
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        let claim = |deps: &mut OwnedDeps<_, _, _>| {
+            let msg = ExecuteMsg::ClaimReferralRewards { limit: Some(1) };
+            execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg)
+        };
 
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "alice".to_string(),
-            game_id: vec![0, 1],
+        // Each batch pays the tickets it scanned and resumes after them
+        let res = claim(&mut deps).unwrap();
+        assert_eq!(res.attributes[1].value, "20000");
+        assert_eq!(res.messages.len(), 1);
+        let res = claim(&mut deps).unwrap();
+        assert_eq!(res.attributes[1].value, "20000");
+        // The last batch found the end of the entries without a reward
+        let res = claim(&mut deps).unwrap();
+        assert_eq!(res.attributes[1].value, "0");
+        assert!(res.messages.is_empty());
+        let err = claim(&mut deps).unwrap_err();
+        assert_eq!(err, ContractError::NoReferralRewards {});
+        let rewards = query_referral_rewards(deps.as_ref(), "bob".to_string()).unwrap();
+        assert_eq!(rewards.total_earned, Uint128::from(40_000u128));
+    }
+
+    #[test]
+    fn fee_on_sales() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        // The fees on sales are not shared between the winners
+        let msg = InstantiateMsg {
+            fee_model: FeeModel::OnSales,
+            prize_mode: PrizeMode::PariMutuel,
+            prize_share: vec![Decimal::percent(11); 9],
+            ..default_instantiate_msg()
         };
-        let res = execute(
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::InvalidPrizeShare {});
+        let msg = InstantiateMsg {
+            fee_model: FeeModel::OnSales,
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+
+        for &(player, live_round) in &[("alice", 1u16), ("bob", 2)] {
+            let sender = mock_info(
+                player,
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128 * live_round as u128),
+                }],
+            );
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 4, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        }
+        let stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(stats.collector_fee, Uint128::from(100_000u128));
+        assert_eq!(stats.terrand_fee, Uint128::from(20_000u128));
+
+        // The fees are earned when the round is drawn
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::zero());
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("alice", &[]),
-            msg.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
         )
         .unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "alice".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(18_799_000_000u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(999_000_000u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(199_000_000u128),
-            }],
-        });
-        assert_eq!(
-            res.messages,
-            vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
-            ]
-        );
-        // Collect again error with no prize
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        assert_eq!(res.messages.len(), 0);
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(100_000u128));
+        let fees = query_fee_balance(deps.as_ref(), "worker".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(20_000u128));
+        // The next round of bob's ticket pays its fees in the round it plays
+        let stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(stats.collector_fee, Uint128::from(50_000u128));
 
+        // No fee is taken on the prize
         let msg = ExecuteMsg::Collect {
             round: 0,
-            player: "bob".to_string(),
+            player: "alice".to_string(),
             game_id: vec![0],
         };
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "bob".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(281_000_000u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(14_851_485u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(2_970_297u128),
-            }],
-        });
+        let res = execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap();
         assert_eq!(
             res.messages,
-            vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
-            ]
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(9_999_000_000u128),
+                }],
+            }))]
         );
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(100_000u128));
+    }
 
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "charlie".to_string(),
-            game_id: vec![0],
+    #[test]
+    fn fee_recipients() {
+        let mut deps = custom_mock_dependencies(&[]);
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        // The shares of the recipients must add up to the fee collector share
+        let msg = InstantiateMsg {
+            fee_recipients: vec![
+                FeeRecipientMsg {
+                    address: "STAKING".to_string(),
+                    share: Decimal::percent(3),
+                },
+                FeeRecipientMsg {
+                    address: "DAO".to_string(),
+                    share: Decimal::percent(3),
+                },
+            ],
+            ..default_instantiate_msg()
         };
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "charlie".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(93_069_306u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(4_950_495u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(990_099u128),
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::InvalidFeeRecipients {});
+        // Nothing can be split without a fee collector share
+        let msg = InstantiateMsg {
+            fee_collector: Decimal::zero(),
+            fee_recipients: vec![FeeRecipientMsg {
+                address: "STAKING".to_string(),
+                share: Decimal::zero(),
             }],
-        });
-        assert_eq!(
-            res.messages,
-            vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
-            ]
-        );
-
-        // Win a refund
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "mario".to_string(),
-            game_id: vec![0],
+            ..default_instantiate_msg()
         };
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "mario".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(930_693u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(49_504u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(9_900u128),
-            }],
-        });
-        assert_eq!(
-            res.messages,
-            vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
-            ]
-        );
-        // Win 2UST
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "mario".to_string(),
-            game_id: vec![1],
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::InvalidFeeRecipients {});
+        // The staking contract must be paid the fees it distributes
+        let msg = InstantiateMsg {
+            staking_address: Some("DAO".to_string()),
+            ..default_instantiate_msg()
         };
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "mario".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(1_861_386u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(99_009u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(19_801u128),
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::StakingNotFeeRecipient {});
+        let msg = InstantiateMsg {
+            fee_recipients: vec![FeeRecipientMsg {
+                address: "DAO".to_string(),
+                share: Decimal::percent(5),
             }],
-        });
-        assert_eq!(
-            res.messages,
-            vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
-            ]
-        );
-
-        // Win 0UST
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "mario".to_string(),
-            game_id: vec![2],
-        };
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
-        assert_eq!(res.messages.len(), 0);
-
-        // Error too soon to collect
-        let msg = ExecuteMsg::Collect {
-            round: 1,
-            player: "alice".to_string(),
-            game_id: vec![0],
+            staking_address: Some("STAKING".to_string()),
+            ..default_instantiate_msg()
         };
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::StakingNotFeeRecipient {});
+        let msg = InstantiateMsg {
+            fee_model: FeeModel::OnSales,
+            fee_recipients: vec![
+                FeeRecipientMsg {
+                    address: "STAKING".to_string(),
+                    share: Decimal::percent(3),
+                },
+                FeeRecipientMsg {
+                    address: "DAO".to_string(),
+                    share: Decimal::percent(2),
+                },
+            ],
+            ..default_instantiate_msg()
+        };
+        init_with(deps.as_mut(), msg);
+        let config = query_config(deps.as_ref()).unwrap();
+        assert_eq!(config.fee_recipients.len(), 2);
+        assert_eq!(config.fee_recipients[1].address, "DAO".to_string());
 
-        let err = execute(deps.as_mut(), env, mock_info("alice", &[]), msg).unwrap_err();
-        assert_eq!(err, ContractError::LotteryInProgress {});
-    }
-
-    #[test]
-    fn try_collect_on_custom_init() {
-        let mut deps = custom_mock_dependencies(&[]);
-        custom_init_prize(deps.as_mut());
-
-        // bonus not counting
         let sender = mock_info(
             "alice",
             &[Coin {
                 denom: "uusd".to_string(),
-                amount: Uint128::from(2_000_000u128),
+                amount: Uint128::from(1_000_000u128),
             }],
         );
         let msg = ExecuteMsg::Register {
-            numbers: vec![1, 1, 1, 1, 7],
-            multiplier: Uint128::from(2_000_000u128),
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
 
-        // 1 number
-        let msg = ExecuteMsg::Register {
-            numbers: vec![4, 1, 1, 1, 7],
-            multiplier: Uint128::from(2_000_000u128),
+        let fees = query_fee_balance(deps.as_ref(), "STAKING".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(30_000u128));
+        let fees = query_fee_balance(deps.as_ref(), "DAO".to_string()).unwrap();
+        assert_eq!(fees.balance, Uint128::from(20_000u128));
+    }
+
+    #[test]
+    fn tax_mode() {
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let register = ExecuteMsg::Register {
+            numbers: Some(vec![4, 15, 6, 4, 7]),
+            multiplier: Uint128::from(1_000_000u128),
             live_round: 1,
             address: None,
+            memo: None,
+            referrer: None,
         };
-        let res = execute(deps.as_mut(), mock_env(), sender.clone(), msg).unwrap();
+        let cancel = ExecuteMsg::CancelTicket {
+            start_round: 0,
+            game_id: 0,
+        };
+        let refund = |amount: u128| {
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(amount),
+                }],
+            }))]
+        };
+
+        // Without tax the contract runs with the standard querier, no Terra query is made
+        for (tax_mode, amount) in &[
+            (TaxMode::None, 1_000_000u128),
+            (
+                TaxMode::Fixed {
+                    rate: Decimal::percent(2),
+                },
+                980_392,
+            ),
+        ] {
+            let mut deps = mock_dependencies(&[]);
+            let msg = InstantiateMsg {
+                tax_mode: tax_mode.clone(),
+                ..default_instantiate_msg()
+            };
+            instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+            let sender = mock_info(
+                "alice",
+                &[Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::from(1_000_000u128),
+                }],
+            );
+            execute(deps.as_mut(), env.clone(), sender, register.clone()).unwrap();
+            let res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("alice", &[]),
+                cancel.clone(),
+            )
+            .unwrap();
+            assert_eq!(res.messages, refund(*amount));
+        }
+    }
+
+    fn default_migrate_msg() -> MigrateMsg {
+        MigrateMsg {
+            update_denom: "uusd".to_string(),
+            update_frequency: 300,
+            update_fee_collector: Decimal::from_str("0.05").unwrap(),
+            update_fee_collector_address: "STAKING".to_string(),
+            update_fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
+            update_terrand_address: "TERRAND".to_string(),
+            update_prize_rank: vec![],
+            update_ticket_price: vec![Uint128::from(1_000_000u128)],
+            update_multiplier: vec![Decimal::one()],
+            update_live_round_max: 5,
+            update_jackpot_share: Decimal::zero(),
+            update_cancel_fee: Decimal::zero(),
+            update_referral_share: Decimal::zero(),
+            update_fee_model: FeeModel::OnWinnings,
+            update_fee_recipients: vec![],
+            update_staking_address: None,
+            update_tax_mode: TaxMode::Terra,
+            update_ticket_nft: None,
+            update_owner: "creator".to_string(),
+            update_prize_mode: PrizeMode::Fixed,
+            update_prize_share: vec![],
+        }
+    }
+
+    #[test]
+    fn migrate_version_checks() {
+        let mut deps = mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        // Different contract
+        set_contract_version(deps.as_mut().storage, "crates.io:other", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), default_migrate_msg()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrate("crates.io:other".to_string())
+        );
+
+        // Downgrade
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), default_migrate_msg()).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotMigrateVersion("99.0.0".to_string(), CONTRACT_VERSION.to_string())
+        );
+
+        // Same version only updates the config, it is validated like at instantiate
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, CONTRACT_VERSION).unwrap();
+        let msg = MigrateMsg {
+            update_staking_address: Some("DAO".to_string()),
+            ..default_migrate_msg()
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::StakingNotFeeRecipient {});
+        let res = migrate(deps.as_mut(), mock_env(), default_migrate_msg()).unwrap();
+        assert_eq!(res.attributes[3], Attribute::new("migrations", "[]"));
+        let version = get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION);
+    }
 
+    #[test]
+    fn collect_tickets_before_and_after_nft() {
+        let mut deps = custom_mock_dependencies(&[]);
+        default_init(deps.as_mut());
         let mut env = mock_env();
         env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
-        env.block.time = env.block.time.plus_seconds(300);
-        let msg = ExecuteMsg::Draw {};
-        let res = execute(deps.as_mut(), env.clone(), mock_info("alice", &[]), msg).unwrap();
+        let register = |deps: DepsMut, env: Env| {
+            let msg = ExecuteMsg::Register {
+                numbers: Some(vec![4, 15, 6, 5, 7]),
+                multiplier: Uint128::from(1_000_000u128),
+                live_round: 1,
+                address: None,
+                memo: None,
+                referrer: None,
+            };
+            let sender = mock_info("alice", &[Coin::new(1_000_000, "uusd")]);
+            execute(deps, env, sender, msg).unwrap()
+        };
 
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "alice".to_string(),
-            game_id: vec![0],
+        // The first tickets are bought before the NFTs are enabled
+        for _ in 0..2 {
+            let res = register(deps.as_mut(), env.clone());
+            assert!(res.messages.is_empty());
+        }
+        let init = default_instantiate_msg();
+        let msg = MigrateMsg {
+            update_prize_rank: init.prize_rank,
+            update_ticket_price: init.ticket_price,
+            update_multiplier: init.multiplier,
+            update_ticket_nft: Some("TICKET_NFT".to_string()),
+            ..default_migrate_msg()
         };
-        let res = execute(
-            deps.as_mut(),
-            env.clone(),
-            mock_info("alice", &[]),
-            msg.clone(),
-        )
-        .unwrap();
+        migrate(deps.as_mut(), env.clone(), msg.clone()).unwrap();
+        let res = register(deps.as_mut(), env.clone());
+        assert_eq!(res.messages.len(), 1);
 
-        assert_eq!(res.messages.len(), 0);
+        // The minted tickets stay on their contract
+        for update_ticket_nft in &[None, Some("OTHER_NFT".to_string())] {
+            let msg = MigrateMsg {
+                update_ticket_nft: update_ticket_nft.clone(),
+                ..msg.clone()
+            };
+            let err = migrate(deps.as_mut(), env.clone(), msg).unwrap_err();
+            assert_eq!(err, ContractError::TicketNftLocked {});
+        }
 
-        let msg = ExecuteMsg::Collect {
-            round: 0,
-            player: "alice".to_string(),
-            game_id: vec![1],
+        // Only the tickets without a token can be cancelled
+        let cancel = |deps: DepsMut, game_id: u64| {
+            let msg = ExecuteMsg::CancelTicket {
+                start_round: 0,
+                game_id,
+            };
+            execute(deps, env.clone(), mock_info("alice", &[]), msg)
         };
-        let res = execute(
+        let err = cancel(deps.as_mut(), 2).unwrap_err();
+        assert_eq!(err, ContractError::TicketNftNotCancellable {});
+        let res = cancel(deps.as_mut(), 1).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin::new(990_099, "uusd")],
+            })
+        );
+
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(
             deps.as_mut(),
             env.clone(),
-            mock_info("alice", &[]),
-            msg.clone(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Draw {},
         )
         .unwrap();
-        let msg_payout = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "alice".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(9_306_930u128),
-            }],
-        });
-        let msg_fee_collector = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "STAKING".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(495_049u128),
-            }],
-        });
-        let msg_fee_worker = CosmosMsg::Bank(BankMsg::Send {
-            to_address: "worker".to_string(),
-            amount: vec![Coin {
-                denom: "uusd".to_string(),
-                amount: Uint128::from(99_009u128),
-            }],
-        });
+        let collect = |deps: DepsMut, game_id: Vec<u64>| {
+            let msg = ExecuteMsg::Collect {
+                round: 0,
+                player: "alice".to_string(),
+                game_id,
+            };
+            execute(deps, env.clone(), mock_info("alice", &[]), msg)
+        };
+
+        // The ticket minted can't be paid while its owner is unknown
+        let err = collect(deps.as_mut(), vec![2]).unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+
+        // The ticket without a token stays with the player
+        let res = collect(deps.as_mut(), vec![0]).unwrap();
         assert_eq!(
-            res.messages,
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![Coin::new(140_000_000, "uusd")],
+            })
+        );
+    }
+
+    #[test]
+    fn migrate_lottery_stats_layout() {
+        let mut deps = mock_dependencies(&[]);
+        default_init(deps.as_mut());
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+
+        // Snapshot of the old layout, counter_player counted twice for alice
+        let alice_raw = deps.api.addr_canonicalize("alice").unwrap();
+        let bob_raw = deps.api.addr_canonicalize("bob").unwrap();
+        for player in &[&alice_raw, &bob_raw] {
+            GAMES_STATS
+                .save(
+                    deps.as_mut().storage,
+                    (player.as_slice(), &0u64.to_be_bytes()),
+                    &GameStats {
+                        total_ticket: 1,
+                        total_spent: Uint128::from(1_000_000u128),
+                        first_round: None,
+                    },
+                )
+                .unwrap();
+        }
+        LEGACY_LOTTERY_STATS
+            .save(
+                deps.as_mut().storage,
+                &0u64.to_be_bytes(),
+                &LegacyLotteryStats {
+                    counter_player: Some(3),
+                    total_ticket_sold: Some(2),
+                    total_collected: Some(Uint128::from(2_000_000u128)),
+                },
+            )
+            .unwrap();
+        LEGACY_LOTTERY_STATS
+            .save(
+                deps.as_mut().storage,
+                &1u64.to_be_bytes(),
+                &LegacyLotteryStats {
+                    counter_player: None,
+                    total_ticket_sold: None,
+                    total_collected: None,
+                },
+            )
+            .unwrap();
+        // The new layout can't read null values
+        assert!(LOTTERY_STATS
+            .load(deps.as_ref().storage, &1u64.to_be_bytes())
+            .is_err());
+
+        let res = migrate(deps.as_mut(), mock_env(), default_migrate_msg()).unwrap();
+        assert_eq!(
+            res.attributes,
             vec![
-                SubMsg::new(msg_payout),
-                SubMsg::new(msg_fee_collector),
-                SubMsg::new(msg_fee_worker)
+                Attribute::new("method", "migrate"),
+                Attribute::new("from_version", "0.1.0"),
+                Attribute::new("to_version", CONTRACT_VERSION),
+                Attribute::new("migrations", "[0.2.0, 0.3.0]"),
             ]
         );
+
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 0).unwrap();
+        assert_eq!(lottery_stats.counter_player, 2);
+        assert_eq!(lottery_stats.total_ticket_sold, 2);
+        assert_eq!(lottery_stats.total_collected, Uint128::from(2_000_000u128));
+        let lottery_stats = query_lottery_stats(deps.as_ref(), 1).unwrap();
+        assert_eq!(lottery_stats.counter_player, 0);
+        assert_eq!(lottery_stats.total_ticket_sold, 0);
+        let players = query_round_players(deps.as_ref(), 0, None, None, None)
+            .unwrap()
+            .players;
+        assert_eq!(players, vec!["alice".to_string(), "bob".to_string()]);
+    }
+
+    #[test]
+    fn migrate_lottery_resolution() {
+        let mut deps = mock_dependencies(&[]);
+        default_init(deps.as_mut());
+
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME).plus_seconds(300);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info("alice", &[]),
+            ExecuteMsg::Draw {},
+        )
+        .unwrap();
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.2.0").unwrap();
+
+        let res = migrate(deps.as_mut(), mock_env(), default_migrate_msg()).unwrap();
+        assert_eq!(res.attributes[3], Attribute::new("migrations", "[0.3.0]"));
+        assert!(query_lottery_state(deps.as_ref(), 0).unwrap().resolved);
+        assert!(!query_lottery_state(deps.as_ref(), 1).unwrap().resolved);
+    }
+
+    mod invariants {
+        use super::*;
+        use crate::engine::count_match;
+        use crate::mock_querier::WasmMockQuerier;
+        use cosmwasm_std::testing::{MockApi, MockStorage};
+        use proptest::prelude::*;
+
+        const PLAYERS: [&str; 3] = ["alice", "bob", "carol"];
+        const LIVE_ROUND_MAX: u16 = 5;
+
+        #[derive(Clone, Debug)]
+        enum Op {
+            Register {
+                player: usize,
+                numbers: Vec<u8>,
+                multiplier: u128,
+                live_round: u16,
+            },
+            Subscribe {
+                player: usize,
+                numbers: Vec<u8>,
+                multiplier: u128,
+                rounds: u64,
+            },
+            Cancel {
+                player: usize,
+                game: prop::sample::Index,
+            },
+            /// Collect a game from a drawn or aborted round, only the rounds drawn so far for a
+            /// multi-round game
+            Collect {
+                player: usize,
+                game: prop::sample::Index,
+            },
+            Draw(Vec<u8>),
+            Abort,
+            /// Count the winners of a drawn round not resolved yet a few games at a time, the
+            /// rounds before it being resolved first
+            Resolve {
+                round: prop::sample::Index,
+                limit: u32,
+            },
+        }
+
+        /// Numbers from the whole range or from the lowest balls, winning often in the draws of
+        /// `randomness`
+        fn numbers() -> impl Strategy<Value = Vec<u8>> {
+            prop_oneof![
+                (prop::collection::vec(1..=16u8, 4), 1..=8u8),
+                (prop::collection::vec(1..=2u8, 4), 1..=2u8),
+            ]
+            .prop_map(|(mut numbers, bonus)| {
+                numbers.push(bonus);
+                numbers
+            })
+        }
+
+        fn multiplier() -> impl Strategy<Value = u128> {
+            prop::sample::select(vec![1_000_000u128, 2_000_000, 5_000_000])
+        }
+
+        /// Any randomness or one drawing the lowest balls and bonuses, the hex digits of its
+        /// first bytes being the balls and the last one the bonus
+        fn randomness() -> impl Strategy<Value = Vec<u8>> {
+            let low_balls = prop::sample::select(vec![0x11u8, 0x12, 0x21, 0x22]);
+            prop_oneof![
+                prop::collection::vec(any::<u8>(), 32),
+                (
+                    prop::collection::vec(low_balls, 2),
+                    prop::collection::vec(any::<u8>(), 29),
+                    0..4u8,
+                )
+                    .prop_map(|(balls, mut randomness, bonus)| {
+                        randomness.push(bonus);
+                        [balls, randomness].concat()
+                    }),
+            ]
+        }
+
+        fn op(max_live_round: u16) -> impl Strategy<Value = Op> {
+            let register = (
+                0..PLAYERS.len(),
+                numbers(),
+                multiplier(),
+                1..=max_live_round,
+            )
+                .prop_map(|(player, numbers, multiplier, live_round)| Op::Register {
+                    player,
+                    numbers,
+                    multiplier,
+                    live_round,
+                });
+            let subscribe = (0..PLAYERS.len(), numbers(), multiplier(), 1..=3u64).prop_map(
+                |(player, numbers, multiplier, rounds)| Op::Subscribe {
+                    player,
+                    numbers,
+                    multiplier,
+                    rounds,
+                },
+            );
+            let cancel = (0..PLAYERS.len(), any::<prop::sample::Index>())
+                .prop_map(|(player, game)| Op::Cancel { player, game });
+            let collect = (0..PLAYERS.len(), any::<prop::sample::Index>())
+                .prop_map(|(player, game)| Op::Collect { player, game });
+            let resolve = (any::<prop::sample::Index>(), 1..=3u32)
+                .prop_map(|(round, limit)| Op::Resolve { round, limit });
+            prop_oneof![
+                6 => register,
+                2 => subscribe,
+                3 => cancel,
+                3 => collect,
+                2 => randomness().prop_map(Op::Draw),
+                1 => Just(Op::Abort),
+                3 => resolve,
+            ]
+        }
+
+        /// Operations followed by enough draws for every multi-round ticket and subscription to
+        /// play its rounds
+        fn ops(max_live_round: u16) -> impl Strategy<Value = Vec<Op>> {
+            (
+                prop::collection::vec(op(max_live_round), 1..30),
+                prop::collection::vec(randomness(), usize::from(max_live_round).max(3)),
+            )
+                .prop_map(|(mut ops, draws)| {
+                    ops.extend(draws.into_iter().map(Op::Draw));
+                    ops
+                })
+        }
+
+        type Deps = OwnedDeps<MockStorage, MockApi, WasmMockQuerier>;
+
+        /// Prizes paid by (start round, player, game id, prize)
+        type Payouts = Vec<(u64, usize, u64, Uint128)>;
+
+        /// Games bought so far by (start round, player, game id, game)
+        fn games(deps: &Deps) -> Vec<(u64, usize, u64, Game)> {
+            let state = STATE.load(&deps.storage).unwrap();
+            let mut games = vec![];
+            for round in 0..=state.round {
+                for (player, address) in PLAYERS.iter().enumerate() {
+                    let player_raw = deps.api.addr_canonicalize(address).unwrap();
+                    let player_games = GAMES
+                        .prefix((&round.to_be_bytes(), player_raw.as_slice()))
+                        .range(&deps.storage, None, None, Order::Ascending)
+                        .map(|item| {
+                            let (game_id, game) = item.unwrap();
+                            let game_id = u64::from_be_bytes(game_id.try_into().unwrap());
+                            (round, player, game_id, game)
+                        });
+                    games.extend(player_games);
+                }
+            }
+            games
+        }
+
+        /// Price paid per round, the multiplier tiers being prices of 1 UST
+        fn round_price(multiplier: u128) -> u128 {
+            multiplier / 2
+        }
+
+        /// Price paid per round for a game, from its multiplier rather than its stored price
+        fn paid_price(game: &Game) -> Uint128 {
+            Uint128::from(500_000u128) * game.multiplier
+        }
+
+        fn refund_attribute(res: &Response) -> Uint128 {
+            let refund = res.attributes.iter().find(|attr| attr.key == "refund");
+            Uint128::from_str(&refund.unwrap().value).unwrap()
+        }
+
+        /// Prize paid by a collect, before the fees
+        fn prize_attribute(res: &Response) -> Uint128 {
+            res.events
+                .iter()
+                .filter(|event| event.ty == "loterra_prize_collected")
+                .flat_map(|event| event.attributes.iter())
+                .find(|attr| attr.key == "prize")
+                .map_or(Uint128::zero(), |attr| {
+                    Uint128::from_str(&attr.value).unwrap()
+                })
+        }
+
+        /// Collect a single game, recording its prize. Rounds not resolved yet in pari-mutuel
+        /// mode are collected later
+        fn collect(
+            deps: &mut Deps,
+            env: &Env,
+            payouts: &mut Payouts,
+            start_round: u64,
+            player: usize,
+            game_id: u64,
+        ) -> Option<Uint128> {
+            let msg = ExecuteMsg::Collect {
+                round: start_round,
+                player: PLAYERS[player].to_string(),
+                game_id: vec![game_id],
+            };
+            let info = mock_info(PLAYERS[player], &[]);
+            match execute(deps.as_mut(), env.clone(), info, msg) {
+                Ok(res) => {
+                    let prize = prize_attribute(&res);
+                    payouts.push((start_round, player, game_id, prize));
+                    Some(prize)
+                }
+                Err(ContractError::AlreadyCollected {})
+                | Err(ContractError::LotteryNotResolved {}) => None,
+                Err(err) => panic!("{}", err),
+            }
+        }
+
+        /// Every operation played with tickets priced 0.5 UST for the first tier, the tiers
+        /// sharing the rounds collected with a progressive jackpot when `pari_mutuel`. The
+        /// refund of a cancel is checked against the price of the rounds cancelled
+        fn play(ops: Vec<Op>, pari_mutuel: bool) -> (Deps, Env, Payouts) {
+            let mut deps = custom_mock_dependencies(&[]);
+            let mut msg = InstantiateMsg {
+                ticket_price: vec![
+                    Uint128::from(500_000u128),
+                    Uint128::from(1_000_000u128),
+                    Uint128::from(2_500_000u128),
+                ],
+                live_round_max: LIVE_ROUND_MAX,
+                ..default_instantiate_msg()
+            };
+            if pari_mutuel {
+                msg.prize_mode = PrizeMode::PariMutuel;
+                msg.prize_share = vec![Decimal::percent(5); 9];
+                msg.jackpot_share = Decimal::percent(10);
+            }
+            init_with(deps.as_mut(), msg);
+            let mut env = mock_env();
+            env.block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+            let mut payouts = vec![];
+
+            for op in ops {
+                match op {
+                    Op::Register {
+                        player,
+                        numbers,
+                        multiplier,
+                        live_round,
+                    } => {
+                        let sender = mock_info(
+                            PLAYERS[player],
+                            &[Coin {
+                                denom: "uusd".to_string(),
+                                amount: Uint128::from(round_price(multiplier) * live_round as u128),
+                            }],
+                        );
+                        let msg = ExecuteMsg::Register {
+                            numbers: Some(numbers),
+                            multiplier: Uint128::from(multiplier),
+                            live_round,
+                            address: None,
+                            memo: None,
+                            referrer: None,
+                        };
+                        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+                    }
+                    Op::Subscribe {
+                        player,
+                        numbers,
+                        multiplier,
+                        rounds,
+                    } => {
+                        let sender = mock_info(
+                            PLAYERS[player],
+                            &[Coin {
+                                denom: "uusd".to_string(),
+                                amount: Uint128::from(round_price(multiplier) * rounds as u128),
+                            }],
+                        );
+                        let msg = ExecuteMsg::Subscribe {
+                            numbers: Some(numbers),
+                            multiplier: Uint128::from(multiplier),
+                            rounds,
+                            address: None,
+                        };
+                        execute(deps.as_mut(), env.clone(), sender, msg).unwrap();
+                    }
+                    Op::Cancel { player, game } => {
+                        let player_games = games(&deps)
+                            .into_iter()
+                            .filter(|(_, p, _, _)| *p == player)
+                            .collect::<Vec<_>>();
+                        if player_games.is_empty() {
+                            continue;
+                        }
+                        let (start_round, _, game_id, cancelled) = game.get(&player_games);
+                        let msg = ExecuteMsg::CancelTicket {
+                            start_round: *start_round,
+                            game_id: *game_id,
+                        };
+                        let info = mock_info(PLAYERS[player], &[]);
+                        // The registration is open, the rounds from the open one are cancelled
+                        let first_round = STATE.load(&deps.storage).unwrap().round;
+                        let end_round = cancelled.end_round.unwrap_or(*start_round);
+                        match execute(deps.as_mut(), env.clone(), info, msg) {
+                            Ok(res) => {
+                                let rounds = end_round - first_round.max(*start_round) + 1;
+                                let price = paid_price(cancelled) * Uint128::from(rounds);
+                                assert_eq!(refund_attribute(&res), price);
+                            }
+                            Err(ContractError::NothingToCancel {}) => {}
+                            Err(err) => panic!("{}", err),
+                        }
+                    }
+                    Op::Collect { player, game } => {
+                        let state = STATE.load(&deps.storage).unwrap();
+                        let player_games = games(&deps)
+                            .into_iter()
+                            .filter(|(start, p, _, _)| *p == player && *start < state.round)
+                            .collect::<Vec<_>>();
+                        if player_games.is_empty() {
+                            continue;
+                        }
+                        let (start_round, _, game_id, _) = game.get(&player_games);
+                        collect(
+                            &mut deps,
+                            &env,
+                            &mut payouts,
+                            *start_round,
+                            player,
+                            *game_id,
+                        );
+                    }
+                    Op::Draw(randomness) => {
+                        deps.querier.set_randomness(Binary::from(randomness));
+                        env.block.time = env.block.time.plus_seconds(300);
+                        let msg = ExecuteMsg::Draw {};
+                        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+                    }
+                    Op::Abort => {
+                        let round = STATE.load(&deps.storage).unwrap().round;
+                        let msg = ExecuteMsg::AbortRound { round };
+                        execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg)
+                            .unwrap();
+                    }
+                    Op::Resolve { round, limit } => {
+                        let state = STATE.load(&deps.storage).unwrap();
+                        let unresolved = (0..state.round)
+                            .filter(|round| {
+                                !LOTTERY_STATE
+                                    .load(&deps.storage, &round.to_be_bytes())
+                                    .unwrap()
+                                    .resolved
+                            })
+                            .collect::<Vec<_>>();
+                        if unresolved.is_empty() {
+                            continue;
+                        }
+                        let round = *round.get(&unresolved);
+                        resolve_rounds(&mut deps, &env, round);
+                        let msg = ExecuteMsg::Resolve {
+                            round,
+                            limit: Some(limit),
+                        };
+                        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+                    }
+                }
+            }
+            (deps, env, payouts)
+        }
+
+        /// Count the winners of the drawn rounds before `end`
+        fn resolve_rounds(deps: &mut Deps, env: &Env, end: u64) {
+            for round in 0..end {
+                while !LOTTERY_STATE
+                    .load(&deps.storage, &round.to_be_bytes())
+                    .unwrap()
+                    .resolved
+                {
+                    let msg = ExecuteMsg::Resolve { round, limit: None };
+                    execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+                }
+            }
+        }
+
+        /// Games of the player playing the round, a cancel shortens or removes them
+        fn playing(
+            games: &[(u64, usize, u64, Game)],
+            round: u64,
+        ) -> impl Iterator<Item = &(u64, usize, u64, Game)> {
+            games.iter().filter(move |(start, _, _, game)| {
+                *start <= round && round <= game.end_round.unwrap_or(*start)
+            })
+        }
+
+        fn rules() -> Rules {
+            Rules {
+                set_of_balls: 4,
+                prize_rank: default_instantiate_msg().prize_rank,
+                prize_mode: PrizeMode::Fixed,
+                jackpot_share: Decimal::zero(),
+            }
+        }
+
+        fn spent(deps: &Deps, player: &str, round: u64) -> Uint128 {
+            let player_raw = deps.api.addr_canonicalize(player).unwrap();
+            GAMES_STATS
+                .may_load(&deps.storage, (player_raw.as_slice(), &round.to_be_bytes()))
+                .unwrap()
+                .map_or(Uint128::zero(), |stats| stats.total_spent)
+        }
+
+        fn total_collected(deps: &Deps, round: u64) -> Uint128 {
+            LOTTERY_STATS
+                .may_load(&deps.storage, &round.to_be_bytes())
+                .unwrap()
+                .unwrap_or_default()
+                .total_collected
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn players_spent_the_rounds_collected(
+                ops in ops(LIVE_ROUND_MAX),
+                pari_mutuel in any::<bool>(),
+            ) {
+                // Each player spends on a round the price of its games playing it, the
+                // multi-round tickets included, and the round collects what its players spent
+                let (deps, _, _) = play(ops, pari_mutuel);
+                let state = STATE.load(&deps.storage).unwrap();
+                let games = games(&deps);
+                for round in 0..=state.round {
+                    let mut total_spent = Uint128::zero();
+                    for (player, address) in PLAYERS.iter().enumerate() {
+                        let paid = playing(&games, round)
+                            .filter(|(_, p, _, _)| *p == player)
+                            .fold(Uint128::zero(), |total, (_, _, _, game)| {
+                                total + paid_price(game)
+                            });
+                        let spent = spent(&deps, address, round);
+                        prop_assert_eq!(spent, paid, "{} in round {}", address, round);
+                        total_spent += spent;
+                    }
+                    prop_assert_eq!(total_spent, total_collected(&deps, round), "round {}", round);
+                }
+            }
+
+            #[test]
+            fn aborted_rounds_refund_the_games_playing_them(
+                ops in ops(LIVE_ROUND_MAX),
+                pari_mutuel in any::<bool>(),
+            ) {
+                let (mut deps, env, _) = play(ops, pari_mutuel);
+                let state = STATE.load(&deps.storage).unwrap();
+                let games = games(&deps);
+                for round in 0..state.round {
+                    if !LOTTERY_STATE.load(&deps.storage, &round.to_be_bytes()).unwrap().cancelled {
+                        continue;
+                    }
+                    for (player, address) in PLAYERS.iter().enumerate() {
+                        let price = playing(&games, round)
+                            .filter(|(_, p, _, _)| *p == player)
+                            .fold(Uint128::zero(), |total, (_, _, _, game)| {
+                                total + paid_price(game)
+                            });
+                        let msg = ExecuteMsg::ClaimRefund {
+                            round,
+                            player: address.to_string(),
+                        };
+                        match execute(deps.as_mut(), env.clone(), mock_info(address, &[]), msg) {
+                            Ok(res) => prop_assert_eq!(refund_attribute(&res), price),
+                            Err(ContractError::NothingToRefund {}) => {
+                                prop_assert!(price.is_zero())
+                            }
+                            Err(err) => panic!("{}", err),
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn rounds_count_the_games_playing_them(
+                ops in ops(LIVE_ROUND_MAX),
+                pari_mutuel in any::<bool>(),
+            ) {
+                let (deps, _, _) = play(ops, pari_mutuel);
+                let state = STATE.load(&deps.storage).unwrap();
+                let games = games(&deps);
+                for round in 0..=state.round {
+                    let collected = playing(&games, round)
+                        .fold(Uint128::zero(), |total, (_, _, _, game)| {
+                            total + paid_price(game)
+                        });
+                    let mut players = playing(&games, round)
+                        .map(|(_, player, _, _)| PLAYERS[*player].to_string())
+                        .collect::<Vec<_>>();
+                    players.sort();
+                    players.dedup();
+
+                    let stats = query_lottery_stats(deps.as_ref(), round).unwrap();
+                    prop_assert_eq!(stats.total_collected, collected);
+                    prop_assert_eq!(stats.counter_player, players.len() as u64);
+                    let round_players =
+                        query_round_players(deps.as_ref(), round, None, None, None).unwrap();
+                    prop_assert_eq!(round_players.players, players);
+                }
+            }
+
+            #[test]
+            fn resolved_rounds_count_the_winners_playing_them(
+                ops in ops(LIVE_ROUND_MAX),
+                pari_mutuel in any::<bool>(),
+            ) {
+                // The resolutions interleaved with the cancels count the games still playing
+                let (mut deps, env, _) = play(ops, pari_mutuel);
+                let state = STATE.load(&deps.storage).unwrap();
+                resolve_rounds(&mut deps, &env, state.round);
+                let games = games(&deps);
+                for round in 0..state.round {
+                    let lottery = LOTTERY_STATE.load(&deps.storage, &round.to_be_bytes()).unwrap();
+                    let mut winners = vec![0; lottery.prize_rank.len()];
+                    if let Some(draw) = Draw::of(&lottery) {
+                        let rules = Rules::new(state.set_of_balls, &lottery);
+                        for (_, _, _, game) in playing(&games, round) {
+                            if let Outcome::Won { tier, .. } =
+                                evaluate(&Ticket::from(game), &draw, &rules)
+                            {
+                                winners[tier] += 1;
+                            }
+                        }
+                    }
+                    prop_assert_eq!(&lottery.winners, &winners, "round {}", round);
+                }
+            }
+
+            #[test]
+            fn count_match_is_symmetric(
+                game in prop::collection::vec(1..=16u8, 4),
+                lottery in prop::collection::vec(1..=16u8, 4),
+                game_bonus in 1..=8u8,
+                lottery_bonus in 1..=8u8,
+            ) {
+                prop_assert_eq!(count_match(&game, &lottery, 4), count_match(&lottery, &game, 4));
+                // Swapping the ticket and the draw gives the same outcome
+                let ticket = |number: &Vec<u8>, bonus| Ticket {
+                    number: number.clone(),
+                    bonus,
+                    multiplier: Decimal::one(),
+                };
+                let draw = |winning_number: &Vec<u8>, bonus_number| Draw {
+                    winning_number: winning_number.clone(),
+                    bonus_number,
+                };
+                prop_assert_eq!(
+                    evaluate(&ticket(&game, game_bonus), &draw(&lottery, lottery_bonus), &rules()),
+                    evaluate(&ticket(&lottery, lottery_bonus), &draw(&game, game_bonus), &rules())
+                );
+            }
+
+            #[test]
+            fn drawn_numbers_are_in_range(randomness in prop::collection::vec(any::<u8>(), 32)) {
+                let draw = draw(&randomness, &rules()).unwrap();
+                prop_assert_eq!(draw.winning_number.len(), 4);
+                prop_assert!(draw.winning_number.iter().all(|n| (1..=16).contains(n)));
+                prop_assert!((1..=8).contains(&draw.bonus_number));
+            }
+        }
+
+        proptest! {
+            // A double payout needs a few operations on the same game in a row, e.g. collect,
+            // cancel and collect again
+            #![proptest_config(ProptestConfig::with_cases(1024))]
+
+            #[test]
+            fn games_never_pay_twice(ops in ops(LIVE_ROUND_MAX), pari_mutuel in any::<bool>()) {
+                // The collects interleaved with the draws and cancels pay a multi-round game
+                // the rounds drawn so far, collecting the rest pays the prize of its rounds
+                let (mut deps, env, mut payouts) = play(ops, pari_mutuel);
+                let state = STATE.load(&deps.storage).unwrap();
+                resolve_rounds(&mut deps, &env, state.round);
+                let games = games(&deps);
+                for (start_round, player, game_id, game) in &games {
+                    collect(&mut deps, &env, &mut payouts, *start_round, *player, *game_id);
+                    let again =
+                        collect(&mut deps, &env, &mut payouts, *start_round, *player, *game_id);
+                    prop_assert_eq!(again.unwrap_or_default(), Uint128::zero());
+
+                    let player_raw = deps.api.addr_canonicalize(PLAYERS[*player]).unwrap();
+                    let game_key = game_key(player_raw.as_slice(), *game_id);
+                    let prize =
+                        game_prize(&deps.storage, state.set_of_balls, game, &game_key, *start_round)
+                            .unwrap()
+                            .unwrap();
+                    let paid = payouts
+                        .iter()
+                        .filter(|(s, p, g, _)| (s, p, g) == (start_round, player, game_id))
+                        .fold(Uint128::zero(), |total, (_, _, _, prize)| total + *prize);
+                    prop_assert_eq!(paid, prize, "game {} of {}", game_id, PLAYERS[*player]);
+                }
+                // Cancelled games were never paid
+                let paid_games = payouts.iter().filter(|(_, _, _, prize)| !prize.is_zero());
+                for (s, p, g, _) in paid_games {
+                    let kept = games.iter().any(|(start, player, id, _)| (s, p, g) == (start, player, id));
+                    prop_assert!(kept, "game {} of {}", g, PLAYERS[*p]);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,335 @@
+//! Rules of the game free of storage: drawing the winning numbers from the terrand randomness
+//! and evaluating the tickets against them. The contract draws and collects with these same
+//! functions, other contracts and indexers can depend on this crate with the `library` feature
+//! to import the exact rules.
+
+use cosmwasm_std::{Decimal, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Mul;
+
+use crate::ContractError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PrizeMode {
+    /// Each tier pays the fixed amount of prize_rank
+    Fixed,
+    /// Each tier shares its prize_share of the round total_collected between its winners
+    PariMutuel,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for PrizeMode {
+    fn default() -> Self {
+        PrizeMode::Fixed
+    }
+}
+
+/// Rules of a round
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Rules {
+    pub set_of_balls: u8,
+    pub prize_rank: Vec<Uint128>,
+    #[serde(default)]
+    pub prize_mode: PrizeMode,
+    /// The top tier shares the progressive jackpot when not zero
+    #[serde(default)]
+    pub jackpot_share: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Draw {
+    pub winning_number: Vec<u8>,
+    pub bonus_number: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Ticket {
+    pub number: Vec<u8>,
+    pub bonus: u8,
+    pub multiplier: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Lost,
+    /// Tier of prize_rank won, the prize before fees being None when the tier shares a pool
+    /// between the winners of the round
+    Won {
+        tier: usize,
+        prize: Option<Uint128>,
+    },
+}
+
+pub fn bonus_number(number: &char) -> Result<u8, ContractError> {
+    let bonus_number = match number {
+        '0' => 1u8,
+        '1' => 1u8,
+        '2' => 2u8,
+        '3' => 2u8,
+        '4' => 3u8,
+        '5' => 3u8,
+        '6' => 4u8,
+        '7' => 4u8,
+        '8' => 5u8,
+        '9' => 5u8,
+        'a' => 6u8,
+        'b' => 6u8,
+        'c' => 7u8,
+        'd' => 7u8,
+        'e' => 8u8,
+        'f' => 8u8,
+        _ => return Err(ContractError::Unauthorized {}),
+    };
+
+    Ok(bonus_number)
+}
+
+pub fn winning_number(number: Vec<char>, set_of_balls: u8) -> Result<Vec<u8>, ContractError> {
+    let mut winning_number: Vec<u8> = vec![];
+    for n in number {
+        let number = match n {
+            '1' => 1u8,
+            '2' => 2u8,
+            '3' => 3u8,
+            '4' => 4u8,
+            '5' => 5u8,
+            '6' => 6u8,
+            '7' => 7u8,
+            '8' => 8u8,
+            '9' => 9u8,
+            'a' => 10u8,
+            'b' => 11u8,
+            'c' => 12u8,
+            'd' => 13u8,
+            'e' => 14u8,
+            'f' => 15u8,
+            '0' => 16u8,
+            _ => return Err(ContractError::Unauthorized {}),
+        };
+
+        if
+        /* !winning_number.contains(&number) && */
+        winning_number.len() != set_of_balls as usize {
+            winning_number.push(number);
+        }
+    }
+
+    Ok(winning_number)
+}
+
+/// Winning numbers and bonus drawn from the terrand randomness
+pub fn draw(randomness: &[u8], rules: &Rules) -> Result<Draw, ContractError> {
+    let numbers: Vec<_> = hex::encode(randomness).chars().collect();
+    Ok(Draw {
+        winning_number: winning_number(numbers.clone(), rules.set_of_balls)?,
+        bonus_number: bonus_number(numbers.last().unwrap())?,
+    })
+}
+
+/// Quick-pick numbers and bonus of a game, hashing the round randomness with the game key so
+/// they can't be known when the ticket is bought. They are mapped like the winning numbers
+pub fn quick_pick_numbers(randomness: &[u8], game_key: &[u8], set_of_balls: u8) -> (Vec<u8>, u8) {
+    let mut hasher = Sha256::new();
+    hasher.input(randomness);
+    hasher.input(game_key);
+    let numbers: Vec<_> = hex::encode(hasher.result()).chars().collect();
+
+    // Hex digits always map to a number
+    (
+        winning_number(numbers.clone(), set_of_balls).unwrap(),
+        bonus_number(numbers.last().unwrap()).unwrap(),
+    )
+}
+
+pub fn count_match(game: &[u8], lottery: &[u8], set_of_balls: u8) -> u8 {
+    let mut count = 0;
+    for i in 0..set_of_balls as usize {
+        if game[i] == lottery[i] {
+            count += 1
+        }
+    }
+
+    count
+}
+
+/// Tier of prize_rank won by a ticket, None when the ticket won nothing
+pub fn prize_tier(ticket: &Ticket, draw: &Draw, rules: &Rules) -> Option<usize> {
+    let match_amount = count_match(&ticket.number, &draw.winning_number, rules.set_of_balls);
+    let bonus = draw.bonus_number == ticket.bonus;
+
+    let tier = match match_amount {
+        0 if bonus => 0,
+        1 if !bonus => 1,
+        1 if bonus => 2,
+        2 if !bonus => 3,
+        2 if bonus => 4,
+        3 if !bonus => 5,
+        3 if bonus => 6,
+        4 if !bonus => 7,
+        4 if bonus => 8,
+        _ => return None,
+    };
+
+    // Without the 9th tier the bonus alone does not win
+    if rules.prize_rank.len() == 9 {
+        Some(tier)
+    } else {
+        tier.checked_sub(1)
+    }
+}
+
+/// Outcome of a ticket, the fixed prizes being known from the draw alone
+pub fn evaluate(ticket: &Ticket, draw: &Draw, rules: &Rules) -> Outcome {
+    let tier = match prize_tier(ticket, draw, rules) {
+        None => return Outcome::Lost,
+        Some(tier) => tier,
+    };
+    let progressive = tier == rules.prize_rank.len() - 1 && !rules.jackpot_share.is_zero();
+    let prize = match rules.prize_mode {
+        PrizeMode::Fixed if !progressive => Some(rules.prize_rank[tier].mul(ticket.multiplier)),
+        _ => None,
+    };
+
+    Outcome::Won { tier, prize }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANDOMNESS: &[u8] = b"OdRl+j6PHnN84dy12n4Oq1BrGktD73FW4SKPihxfB9I=";
+
+    fn rules(tiers: usize) -> Rules {
+        Rules {
+            set_of_balls: 4,
+            prize_rank: (1..=tiers as u128).map(Uint128::from).collect(),
+            prize_mode: PrizeMode::Fixed,
+            jackpot_share: Decimal::zero(),
+        }
+    }
+
+    fn ticket(number: [u8; 4], bonus: u8) -> Ticket {
+        Ticket {
+            number: number.to_vec(),
+            bonus,
+            multiplier: Decimal::from_ratio(2u128, 1u128),
+        }
+    }
+
+    fn winning_draw() -> Draw {
+        Draw {
+            winning_number: vec![4, 15, 6, 4],
+            bonus_number: 7,
+        }
+    }
+
+    #[test]
+    fn draw_maps_the_randomness_hex() {
+        assert_eq!(draw(RANDOMNESS, &rules(9)).unwrap(), winning_draw());
+        // A zero digit is the highest ball and the lowest bonus
+        let zero_draw = draw(&[0u8; 32], &rules(9)).unwrap();
+        assert_eq!(zero_draw.winning_number, vec![16, 16, 16, 16]);
+        assert_eq!(zero_draw.bonus_number, 1);
+    }
+
+    #[test]
+    fn prize_tier_with_nine_tiers() {
+        let rules = rules(9);
+        let tier = |number, bonus| prize_tier(&ticket(number, bonus), &winning_draw(), &rules);
+        assert_eq!(tier([1, 1, 1, 1], 1), None);
+        assert_eq!(tier([1, 1, 1, 1], 7), Some(0));
+        assert_eq!(tier([4, 1, 1, 1], 1), Some(1));
+        assert_eq!(tier([4, 15, 6, 1], 1), Some(5));
+        assert_eq!(tier([4, 15, 6, 4], 1), Some(7));
+        assert_eq!(tier([4, 15, 6, 4], 7), Some(8));
+        // Balls only match at their position
+        assert_eq!(tier([15, 4, 4, 6], 1), None);
+    }
+
+    #[test]
+    fn prize_tier_with_eight_tiers() {
+        // The bonus alone does not win
+        let rules = rules(8);
+        let tier = |number, bonus| prize_tier(&ticket(number, bonus), &winning_draw(), &rules);
+        assert_eq!(tier([1, 1, 1, 1], 7), None);
+        assert_eq!(tier([4, 1, 1, 1], 1), Some(0));
+        assert_eq!(tier([4, 1, 1, 1], 7), Some(1));
+        assert_eq!(tier([4, 15, 6, 4], 7), Some(7));
+    }
+
+    #[test]
+    fn evaluate_fixed_prizes() {
+        let rules = rules(9);
+        assert_eq!(
+            evaluate(&ticket([4, 15, 6, 1], 7), &winning_draw(), &rules),
+            Outcome::Won {
+                tier: 6,
+                prize: Some(Uint128::from(14u128)),
+            }
+        );
+        assert_eq!(
+            evaluate(&ticket([1, 1, 1, 1], 1), &winning_draw(), &rules),
+            Outcome::Lost
+        );
+    }
+
+    #[test]
+    fn evaluate_pari_mutuel_prizes() {
+        // Every tier shares a pool, the prize is only known once the winners are counted
+        let rules = Rules {
+            prize_mode: PrizeMode::PariMutuel,
+            ..rules(9)
+        };
+        assert_eq!(
+            evaluate(&ticket([4, 15, 6, 1], 7), &winning_draw(), &rules),
+            Outcome::Won {
+                tier: 6,
+                prize: None,
+            }
+        );
+        assert_eq!(
+            evaluate(&ticket([1, 1, 1, 1], 1), &winning_draw(), &rules),
+            Outcome::Lost
+        );
+    }
+
+    #[test]
+    fn evaluate_progressive_jackpot() {
+        // Only the top tier shares the jackpot, the other tiers keep their fixed prize
+        let rules = Rules {
+            jackpot_share: Decimal::percent(10),
+            ..rules(9)
+        };
+        assert_eq!(
+            evaluate(&ticket([4, 15, 6, 4], 7), &winning_draw(), &rules),
+            Outcome::Won {
+                tier: 8,
+                prize: None,
+            }
+        );
+        assert_eq!(
+            evaluate(&ticket([4, 15, 6, 4], 1), &winning_draw(), &rules),
+            Outcome::Won {
+                tier: 7,
+                prize: Some(Uint128::from(16u128)),
+            }
+        );
+    }
+
+    #[test]
+    fn quick_pick_numbers_are_deterministic() {
+        let game_key = |game_id: u64| [&[0, 20][..], &[1; 20], &game_id.to_be_bytes()].concat();
+        let numbers = quick_pick_numbers(RANDOMNESS, &game_key(3), 4);
+        assert_eq!(numbers, quick_pick_numbers(RANDOMNESS, &game_key(3), 4));
+        assert_eq!(numbers.0.len(), 4);
+        assert!(numbers.0.iter().all(|n| (1..=16).contains(n)));
+        assert!((1..=8).contains(&numbers.1));
+        // Each game and each round randomness draws its own numbers
+        assert_ne!(numbers, quick_pick_numbers(RANDOMNESS, &game_key(4), 4));
+        assert_ne!(numbers, quick_pick_numbers(&[0u8; 32], &game_key(3), 4));
+    }
+}
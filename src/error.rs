@@ -36,11 +36,125 @@ pub enum ContractError {
     #[error("Live round max life exceeded")]
     LiveRoundMaxLifeExceeded {},
 
+    #[error("Live round max must be at least 1")]
+    LiveRoundMaxZero {},
+
     #[error("The amount you sent is not correct, sent {0}, expected {1}")]
     AmountSentError(Uint128, Uint128),
 
     #[error("No prize to collect")]
     NoPrizeToCollect {},
+
+    #[error("Tickets already collected")]
+    AlreadyCollected {},
+
+    #[error("Cancel fee can't be more than 100%")]
+    CancelFeeTooHigh {},
+
+    #[error("No round left to cancel")]
+    NothingToCancel {},
+
+    #[error("Memo can't be longer than {0} characters")]
+    MemoTooLong(usize),
+
+    #[error("Fee recipient shares must add up to the fee collector share")]
+    InvalidFeeRecipients {},
+
+    #[error("Referral share can't be more than the fee collector share")]
+    ReferralShareTooHigh {},
+
+    #[error("Referral rewards require the fees taken on the tickets sold")]
+    ReferralWithoutFeeOnSales {},
+
+    #[error("Players can't refer themselves")]
+    InvalidReferrer {},
+
+    #[error("No staking contract configured")]
+    StakingNotConfigured {},
+
+    #[error("The staking contract must receive the fee collector fees")]
+    StakingNotFeeRecipient {},
+
+    #[error("Tickets minted as NFTs can't be cancelled")]
+    TicketNftNotCancellable {},
+
+    #[error("Tickets collected together must have the same owner")]
+    TicketOwnersDiffer {},
+
+    #[error("The ticket NFT contract can't be changed once set")]
+    TicketNftLocked {},
+
+    #[error("No fees to withdraw")]
+    NoFeesToWithdraw {},
+
+    #[error("No referral rewards to claim")]
+    NoReferralRewards {},
+
+    #[error("Only the open round can be aborted")]
+    CannotAbortRound {},
+
+    #[error("The owner can only abort a round before its draw time")]
+    AbortAfterDrawTime {},
+
+    #[error("The randomness of the round is available, it must be drawn")]
+    RandomnessAvailable {},
+
+    #[error("Lottery was not aborted")]
+    LotteryNotCancelled {},
+
+    #[error("No ticket to refund")]
+    NothingToRefund {},
+
+    #[error("Refund already claimed")]
+    RefundAlreadyClaimed {},
+
+    #[error("Subscription must play at least one round")]
+    EmptySubscription {},
+
+    #[error("Subscription not found")]
+    SubscriptionNotFound {},
+
+    #[error("Numbers must be {0} balls in range followed by {1} bonus")]
+    InvalidNumbers(u8, u8),
+
+    #[error("Subscriptions must be played first with PlaySubscriptions")]
+    SubscriptionsNotPlayed {},
+
+    #[error("Subscriptions are already played for this round")]
+    SubscriptionsAlreadyPlayed {},
+
+    #[error("Lottery winners are not counted yet")]
+    LotteryNotResolved {},
+
+    #[error("Lottery winners are already counted")]
+    LotteryAlreadyResolved {},
+
+    #[error("Previous lottery winners must be counted first")]
+    PreviousLotteryNotResolved {},
+
+    #[error("Jackpot share can't be more than 100%")]
+    JackpotShareTooHigh {},
+
+    #[error(
+        "Prize share must have one share per tier and can't be more than 100% with the jackpot \
+         and the fees on sales"
+    )]
+    InvalidPrizeShare {},
+
+    #[error("Cannot migrate from a different contract: {0}")]
+    CannotMigrate(String),
+
+    #[error("Cannot migrate from version {0} to older version {1}")]
+    CannotMigrateVersion(String, String),
+
+    #[error("Semver parsing error: {0}")]
+    SemVer(String),
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
+}
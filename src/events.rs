@@ -0,0 +1,224 @@
+//! Events emitted for the indexers, with stable attribute keys. The chain prefixes their type
+//! with `wasm-`, `loterra_round_drawn` being indexed as `wasm-loterra_round_drawn`.
+
+use cosmwasm_std::{Binary, Decimal, Event, Uint128};
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub struct TicketRegistered<'a> {
+    pub round: u64,
+    /// Last round played, the round itself for a single round ticket
+    pub end_round: u64,
+    pub player: &'a str,
+    /// Sender who paid the ticket, the player for the tickets of its subscriptions
+    pub buyer: &'a str,
+    pub game_id: u64,
+    /// Numbers followed by the bonus, None for a quick-pick
+    pub numbers: Option<&'a [u8]>,
+    pub multiplier: Decimal,
+    /// Total paid for the ticket, the price per round times the rounds from `round` to
+    /// `end_round`, a subscription ticket playing a single round
+    pub amount: Uint128,
+}
+
+impl From<TicketRegistered<'_>> for Event {
+    fn from(ticket: TicketRegistered) -> Self {
+        let (numbers, bonus) = match ticket.numbers {
+            Some(numbers) => (
+                join(&numbers[..numbers.len() - 1]),
+                numbers[numbers.len() - 1].to_string(),
+            ),
+            None => (String::new(), String::new()),
+        };
+        Event::new("loterra_ticket_registered")
+            .add_attribute("round", ticket.round.to_string())
+            .add_attribute("end_round", ticket.end_round.to_string())
+            .add_attribute("player", ticket.player)
+            .add_attribute("buyer", ticket.buyer)
+            .add_attribute("game_id", ticket.game_id.to_string())
+            .add_attribute("numbers", numbers)
+            .add_attribute("bonus", bonus)
+            .add_attribute("quick_pick", ticket.numbers.is_none().to_string())
+            .add_attribute("multiplier", ticket.multiplier.to_string())
+            .add_attribute("amount", ticket.amount.to_string())
+    }
+}
+
+pub struct TicketCancelled<'a> {
+    pub round: u64,
+    pub player: &'a str,
+    pub game_id: u64,
+    /// Rounds not played anymore, from the first to the end round
+    pub first_round: u64,
+    pub end_round: u64,
+    /// Amount refunded before the cancel fee and tax
+    pub refund: Uint128,
+    pub cancel_fee: Uint128,
+}
+
+impl From<TicketCancelled<'_>> for Event {
+    fn from(cancel: TicketCancelled) -> Self {
+        Event::new("loterra_ticket_cancelled")
+            .add_attribute("round", cancel.round.to_string())
+            .add_attribute("player", cancel.player)
+            .add_attribute("game_id", cancel.game_id.to_string())
+            .add_attribute("first_round", cancel.first_round.to_string())
+            .add_attribute("end_round", cancel.end_round.to_string())
+            .add_attribute("refund", cancel.refund.to_string())
+            .add_attribute("cancel_fee", cancel.cancel_fee.to_string())
+    }
+}
+
+pub struct SubscriptionCancelled<'a> {
+    pub player: &'a str,
+    pub subscription_id: u64,
+    /// Rounds not played yet
+    pub rounds: u64,
+    /// Amount refunded before tax
+    pub refund: Uint128,
+}
+
+impl From<SubscriptionCancelled<'_>> for Event {
+    fn from(cancel: SubscriptionCancelled) -> Self {
+        Event::new("loterra_subscription_cancelled")
+            .add_attribute("player", cancel.player)
+            .add_attribute("subscription_id", cancel.subscription_id.to_string())
+            .add_attribute("rounds", cancel.rounds.to_string())
+            .add_attribute("refund", cancel.refund.to_string())
+    }
+}
+
+pub struct RoundDrawn<'a> {
+    pub round: u64,
+    pub winning_number: &'a [u8],
+    pub bonus_number: u8,
+    pub terrand_round: u64,
+    pub worker: &'a str,
+    pub randomness: &'a Binary,
+    pub total_ticket_sold: u64,
+    pub total_collected: Uint128,
+}
+
+impl From<RoundDrawn<'_>> for Event {
+    fn from(draw: RoundDrawn) -> Self {
+        Event::new("loterra_round_drawn")
+            .add_attribute("round", draw.round.to_string())
+            .add_attribute("winning_number", join(draw.winning_number))
+            .add_attribute("bonus_number", draw.bonus_number.to_string())
+            .add_attribute("terrand_round", draw.terrand_round.to_string())
+            .add_attribute("worker", draw.worker)
+            .add_attribute("randomness", hex::encode(draw.randomness.as_slice()))
+            .add_attribute("total_ticket_sold", draw.total_ticket_sold.to_string())
+            .add_attribute("total_collected", draw.total_collected.to_string())
+    }
+}
+
+pub struct PrizeCollected<'a> {
+    pub round: u64,
+    pub player: &'a str,
+    /// Paid the prize, the owner of the ticket NFTs when they are enabled
+    pub recipient: &'a str,
+    pub game_ids: &'a [u64],
+    /// Prize won before fees
+    pub prize: Uint128,
+    /// Amount sent to the player before tax
+    pub amount: Uint128,
+    pub collector_fee: Uint128,
+    pub terrand_fee: Uint128,
+}
+
+impl From<PrizeCollected<'_>> for Event {
+    fn from(collect: PrizeCollected) -> Self {
+        Event::new("loterra_prize_collected")
+            .add_attribute("round", collect.round.to_string())
+            .add_attribute("player", collect.player)
+            .add_attribute("recipient", collect.recipient)
+            .add_attribute("game_ids", join(collect.game_ids))
+            .add_attribute("prize", collect.prize.to_string())
+            .add_attribute("amount", collect.amount.to_string())
+            .add_attribute("collector_fee", collect.collector_fee.to_string())
+            .add_attribute("terrand_fee", collect.terrand_fee.to_string())
+    }
+}
+
+pub struct RoundAborted {
+    pub round: u64,
+    /// Aborted by anyone once terrand is late, otherwise by the owner
+    pub timed_out: bool,
+}
+
+impl From<RoundAborted> for Event {
+    fn from(abort: RoundAborted) -> Self {
+        Event::new("loterra_round_aborted")
+            .add_attribute("round", abort.round.to_string())
+            .add_attribute("timed_out", abort.timed_out.to_string())
+    }
+}
+
+pub struct RefundClaimed<'a> {
+    /// Aborted round refunded
+    pub round: u64,
+    pub player: &'a str,
+    /// Amount sent before tax
+    pub amount: Uint128,
+}
+
+impl From<RefundClaimed<'_>> for Event {
+    fn from(refund: RefundClaimed) -> Self {
+        Event::new("loterra_refund_claimed")
+            .add_attribute("round", refund.round.to_string())
+            .add_attribute("player", refund.player)
+            .add_attribute("amount", refund.amount.to_string())
+    }
+}
+
+pub struct ReferralRewardsClaimed<'a> {
+    pub referrer: &'a str,
+    /// Amount sent before tax
+    pub amount: Uint128,
+}
+
+impl From<ReferralRewardsClaimed<'_>> for Event {
+    fn from(claim: ReferralRewardsClaimed) -> Self {
+        Event::new("loterra_referral_rewards_claimed")
+            .add_attribute("referrer", claim.referrer)
+            .add_attribute("amount", claim.amount.to_string())
+    }
+}
+
+/// Why a fee left the contract
+pub enum FeeKind {
+    /// Fees accrued withdrawn by their recipient
+    Withdraw,
+    /// Fees accrued deposited to the staking contract
+    Staking,
+    /// Reward of the terrand worker for the randomness of a draw
+    Randomness,
+}
+
+pub struct FeePaid<'a> {
+    pub recipient: &'a str,
+    /// Amount sent before tax
+    pub amount: Uint128,
+    pub kind: FeeKind,
+}
+
+impl From<FeePaid<'_>> for Event {
+    fn from(fee: FeePaid) -> Self {
+        let kind = match fee.kind {
+            FeeKind::Withdraw => "withdraw",
+            FeeKind::Staking => "staking",
+            FeeKind::Randomness => "randomness",
+        };
+        Event::new("loterra_fee_paid")
+            .add_attribute("recipient", fee.recipient)
+            .add_attribute("amount", fee.amount.to_string())
+            .add_attribute("kind", kind)
+    }
+}
@@ -1,67 +1,17 @@
-use crate::state::{Game, GameStats, GAMES};
+use crate::engine::{evaluate, quick_pick_numbers, Draw, Outcome, Rules, Ticket};
+use crate::state::{
+    Config, Game, GameStats, LotteryState, LotteryStats, PendingReferral, PrizeMode, RoundDelta,
+    ACTIVE_TICKETS, FEE_BALANCES, GAMES, GAMES_STATS, GIFTS, LOTTERY_STATE, LOTTERY_STATS,
+    MULTI_ROUND_GAMES, PENDING_REFERRAL_REWARDS, ROUND_DELTAS, ROUND_PLAYERS,
+};
 use crate::ContractError;
-use cosmwasm_std::{CanonicalAddr, Decimal, Storage};
-
-pub fn bonus_number(number: &char) -> Result<u8, ContractError> {
-    let bonus_number = match number {
-        '0' => 1u8,
-        '1' => 1u8,
-        '2' => 2u8,
-        '3' => 2u8,
-        '4' => 3u8,
-        '5' => 3u8,
-        '6' => 4u8,
-        '7' => 4u8,
-        '8' => 5u8,
-        '9' => 5u8,
-        'a' => 6u8,
-        'b' => 6u8,
-        'c' => 7u8,
-        'd' => 7u8,
-        'e' => 8u8,
-        'f' => 8u8,
-        _ => return Err(ContractError::Unauthorized {}),
-    };
-
-    Ok(bonus_number)
-}
-
-pub fn winning_number(number: Vec<char>) -> Result<Vec<u8>, ContractError> {
-    let mut winning_number: Vec<u8> = vec![];
-    for n in number {
-        let number = match n {
-            '1' => 1u8,
-            '2' => 2u8,
-            '3' => 3u8,
-            '4' => 4u8,
-            '5' => 5u8,
-            '6' => 6u8,
-            '7' => 7u8,
-            '8' => 8u8,
-            '9' => 9u8,
-            'a' => 10u8,
-            'b' => 11u8,
-            'c' => 12u8,
-            'd' => 13u8,
-            'e' => 14u8,
-            'f' => 15u8,
-            '0' => 16u8,
-            _ => return Err(ContractError::Unauthorized {}),
-        };
-
-        if
-        /* !winning_number.contains(&number) && */
-        winning_number.len() != 4 {
-            winning_number.push(number);
-        }
-    }
+use cosmwasm_std::{
+    CanonicalAddr, Decimal, Empty, Fraction, Order, OverflowError, StdResult, Storage, Uint128,
+};
+use cw_storage_plus::{Bound, PrimaryKey};
+use std::convert::TryInto;
+use std::ops::Mul;
 
-    Ok(winning_number)
-}
-
-/*
-   Deprecated since non deterministic suspicion
-*/
 // pub fn random_number(randomness_hash: String, set_of_balls: u8, range_max: u8) -> Vec<u8>{
 //
 //     let mut winning_numbers: Vec<u8> = vec![];
@@ -89,46 +39,688 @@ pub fn winning_number(number: Vec<char>) -> Result<Vec<u8>, ContractError> {
 //
 //     winning_numbers
 // }
+/// Game played with the set of balls followed by the bonus, None for a quick-pick
+pub fn new_game(
+    numbers: Option<Vec<u8>>,
+    set_of_balls: u8,
+    multiplier: Decimal,
+    end_round: Option<u64>,
+) -> Game {
+    match numbers {
+        Some(numbers) => Game {
+            number: numbers[..usize::from(set_of_balls)].to_vec(),
+            bonus: numbers
+                .get(usize::from(set_of_balls))
+                .copied()
+                .unwrap_or_default(),
+            multiplier,
+            resolved: false,
+            quick_pick: false,
+            end_round,
+            collected_round: None,
+            price: Uint128::zero(),
+            buyer: None,
+            memo: None,
+            referrer: None,
+            referral_reward: Uint128::zero(),
+            minted: false,
+        },
+        None => Game {
+            number: vec![],
+            bonus: 0,
+            multiplier,
+            resolved: false,
+            quick_pick: true,
+            end_round,
+            collected_round: None,
+            price: Uint128::zero(),
+            buyer: None,
+            memo: None,
+            referrer: None,
+            referral_reward: Uint128::zero(),
+            minted: false,
+        },
+    }
+}
+
+/// Save the game after the last game of the player in the round and return its id
 pub fn save_game(
     storage: &mut dyn Storage,
     round: u64,
     address_raw: &CanonicalAddr,
-    numbers: Vec<u8>,
-    multiplier: Decimal,
-    game: Option<GameStats>,
-) -> Result<(), ContractError> {
-    let stats = match game {
-        None => 0,
-        Some(game_stats) => game_stats.total_ticket,
-    };
+    game: &Game,
+) -> Result<u64, ContractError> {
+    // Cancelled games are removed, the amount of tickets can't be used as the next id
+    let game_id = GAMES
+        .prefix((&round.to_be_bytes(), address_raw.as_slice()))
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .map_or(0, |k| u64::from_be_bytes(k.try_into().unwrap()) + 1);
 
-    // for number in numbers {
     GAMES.save(
         storage,
         (
             &round.to_be_bytes(),
             address_raw.as_slice(),
-            &stats.to_be_bytes(),
+            &game_id.to_be_bytes(),
         ),
-        &Game {
-            number: vec![numbers[0], numbers[1], numbers[2], numbers[3]],
-            bonus: numbers[4],
-            multiplier,
-            resolved: false,
+        game,
+    )?;
+    Ok(game_id)
+}
+
+/// Save a game bought in a round and account it in the player and round stats, `amount` being
+/// the price paid per round. The tickets and sales of the next rounds of a multi-round game are
+/// accounted in the round deltas, applied once they open, its player in all of them. Returns
+/// the game id
+pub fn register_game(
+    storage: &mut dyn Storage,
+    round: u64,
+    address_raw: &CanonicalAddr,
+    mut game: Game,
+    amount: Uint128,
+    config: &Config,
+) -> Result<u64, ContractError> {
+    game.price = amount;
+    game.minted = config.ticket_nft.is_some();
+    let game_stats =
+        GAMES_STATS.may_load(storage, (address_raw.as_slice(), &round.to_be_bytes()))?;
+    let game_id = save_game(storage, round, address_raw, &game)?;
+
+    let update_game_stats = match game_stats {
+        None => GameStats {
+            total_ticket: 1,
+            total_spent: amount,
+            first_round: Some(round),
+        },
+        Some(game_stats) => GameStats {
+            total_ticket: game_stats.total_ticket.checked_add(1).unwrap(),
+            total_spent: game_stats.total_spent.checked_add(amount).unwrap(),
+            first_round: game_stats
+                .first_round
+                .map(|first_round| first_round.min(round)),
+        },
+    };
+    GAMES_STATS.save(
+        storage,
+        (address_raw.as_slice(), &round.to_be_bytes()),
+        &update_game_stats,
+    )?;
+
+    // Count the player only once per round, whatever the amount of tickets
+    let new_player = add_round_player(storage, round, address_raw)?;
+    LOTTERY_STATS.update(
+        storage,
+        &round.to_be_bytes(),
+        |lottery_stats| -> Result<_, ContractError> {
+            let mut update_lottery_stats = lottery_stats.unwrap_or_default();
+            if new_player {
+                update_lottery_stats.counter_player += 1;
+            }
+            update_lottery_stats.total_ticket_sold += 1;
+            add_sales(&mut update_lottery_stats, amount, config);
+            Ok(update_lottery_stats)
+        },
+    )?;
+
+    if let Some(end_round) = game.end_round {
+        MULTI_ROUND_GAMES.save(
+            storage,
+            (
+                &end_round.to_be_bytes(),
+                &[
+                    &round.to_be_bytes()[..],
+                    &game_key(address_raw.as_slice(), game_id),
+                ]
+                .concat(),
+            ),
+            &Empty {},
+        )?;
+        add_ticket_range(storage, round, end_round, amount)?;
+        for next_round in round.checked_add(1).unwrap()..=end_round {
+            add_next_round_player(storage, round, next_round, address_raw, amount)?;
+        }
+    }
+    if let Some(buyer) = &game.buyer {
+        GIFTS.save(
+            storage,
+            (
+                buyer.as_slice(),
+                &[
+                    &round.to_be_bytes()[..],
+                    &game_key(address_raw.as_slice(), game_id),
+                ]
+                .concat(),
+            ),
+            &Empty {},
+        )?;
+    }
+
+    Ok(game_id)
+}
+
+/// Add a ticket of the player to the round, returns true when it is its first ticket
+fn add_round_player(
+    storage: &mut dyn Storage,
+    round: u64,
+    address_raw: &CanonicalAddr,
+) -> StdResult<bool> {
+    let key = (&round.to_be_bytes()[..], address_raw.as_slice());
+    let mut round_player = ROUND_PLAYERS.may_load(storage, key)?.unwrap_or_default();
+    round_player.tickets += 1;
+    ROUND_PLAYERS.save(storage, key, &round_player)?;
+    Ok(round_player.tickets == 1)
+}
+
+/// Index and count the player of a multi-round ticket bought in `start_round` in a round after
+/// it and add its price to the player stats of the round
+fn add_next_round_player(
+    storage: &mut dyn Storage,
+    start_round: u64,
+    round: u64,
+    address_raw: &CanonicalAddr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    GAMES_STATS.update(
+        storage,
+        (address_raw.as_slice(), &round.to_be_bytes()),
+        |game_stats| -> StdResult<_> {
+            let mut update_game_stats = game_stats.unwrap_or(GameStats {
+                first_round: Some(start_round),
+                ..GameStats::default()
+            });
+            update_game_stats.total_spent = update_game_stats.total_spent.checked_add(amount)?;
+            update_game_stats.first_round = update_game_stats
+                .first_round
+                .map(|first_round| first_round.min(start_round));
+            Ok(update_game_stats)
+        },
+    )?;
+    if add_round_player(storage, round, address_raw)? {
+        update_counter_player(storage, round, |counter| counter + 1)?;
+    }
+    Ok(())
+}
+
+/// Remove a cancelled ticket from the players and the player stats of a round it doesn't play
+/// anymore
+pub fn remove_round_ticket(
+    storage: &mut dyn Storage,
+    round: u64,
+    start_round: u64,
+    address_raw: &CanonicalAddr,
+    price: Uint128,
+) -> Result<(), ContractError> {
+    if remove_round_player(storage, round, address_raw)? {
+        update_counter_player(storage, round, |counter| counter - 1)?;
+    }
+    let stats_key = (address_raw.as_slice(), &round.to_be_bytes()[..]);
+    let mut game_stats = GAMES_STATS.load(storage, stats_key)?;
+    game_stats.total_spent = game_stats.total_spent.checked_sub(price).unwrap();
+    // The ticket is removed from the round it was bought in
+    if round == start_round {
+        game_stats.total_ticket -= 1;
+    }
+    if game_stats.total_ticket == 0 && game_stats.total_spent.is_zero() {
+        GAMES_STATS.remove(storage, stats_key);
+    } else {
+        GAMES_STATS.save(storage, stats_key, &game_stats)?;
+    }
+    Ok(())
+}
+
+/// Remove a ticket of the player from the round, returns true when it was its last ticket
+fn remove_round_player(
+    storage: &mut dyn Storage,
+    round: u64,
+    address_raw: &CanonicalAddr,
+) -> StdResult<bool> {
+    let key = (&round.to_be_bytes()[..], address_raw.as_slice());
+    let mut round_player = match ROUND_PLAYERS.may_load(storage, key)? {
+        None => return Ok(false),
+        Some(round_player) => round_player,
+    };
+    round_player.tickets -= 1;
+    if round_player.tickets > 0 {
+        ROUND_PLAYERS.save(storage, key, &round_player)?;
+        return Ok(false);
+    }
+    ROUND_PLAYERS.remove(storage, key);
+    Ok(true)
+}
+
+/// Update the amount of players of the round
+fn update_counter_player(
+    storage: &mut dyn Storage,
+    round: u64,
+    action: impl FnOnce(u64) -> u64,
+) -> StdResult<()> {
+    LOTTERY_STATS.update(
+        storage,
+        &round.to_be_bytes(),
+        |lottery_stats| -> StdResult<_> {
+            let mut update_lottery_stats = lottery_stats.unwrap_or_default();
+            update_lottery_stats.counter_player = action(update_lottery_stats.counter_player);
+            Ok(update_lottery_stats)
         },
     )?;
-    //stats += 1;
-    // }
     Ok(())
 }
 
-pub fn count_match(game: &[u8], lottery: &[u8], set_of_balls: u8) -> u8 {
-    let mut count = 0;
-    for i in 0..set_of_balls as usize {
-        if game[i] == lottery[i] {
-            count += 1
+/// Account a multi-round ticket in the deltas of the rounds after `round` up to `end_round`
+fn add_ticket_range(
+    storage: &mut dyn Storage,
+    round: u64,
+    end_round: u64,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let start = round.checked_add(1).unwrap();
+    let end = end_round.checked_add(1).unwrap();
+    update_round_delta(storage, start, |delta| {
+        delta.started_tickets += 1;
+        delta.started_amount = delta.started_amount.checked_add(amount).unwrap();
+    })?;
+    update_round_delta(storage, end, |delta| {
+        delta.ended_tickets += 1;
+        delta.ended_amount = delta.ended_amount.checked_add(amount).unwrap();
+    })?;
+
+    Ok(())
+}
+
+/// Remove the rounds from `first_round` on of a multi-round ticket from the round deltas, or
+/// from the tickets still playing when the open round already counts it
+pub fn remove_ticket_range(
+    storage: &mut dyn Storage,
+    open_round: u64,
+    start_round: u64,
+    end_round: u64,
+    first_round: u64,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    // The deltas account the rounds after the one the ticket was bought in
+    let first_round = first_round.max(start_round.checked_add(1).unwrap());
+    if first_round > end_round {
+        return Ok(());
+    }
+
+    update_round_delta(storage, end_round.checked_add(1).unwrap(), |delta| {
+        delta.ended_tickets -= 1;
+        delta.ended_amount = delta.ended_amount.checked_sub(amount).unwrap();
+    })?;
+    if first_round > open_round {
+        update_round_delta(storage, first_round, |delta| {
+            delta.ended_tickets += 1;
+            delta.ended_amount = delta.ended_amount.checked_add(amount).unwrap();
+        })?;
+    } else {
+        ACTIVE_TICKETS.update(storage, |mut active| -> StdResult<_> {
+            active.tickets -= 1;
+            active.amount = active.amount.checked_sub(amount).unwrap();
+            Ok(active)
+        })?;
+    }
+
+    Ok(())
+}
+
+fn update_round_delta(
+    storage: &mut dyn Storage,
+    round: u64,
+    action: impl FnOnce(&mut RoundDelta),
+) -> Result<(), ContractError> {
+    let mut delta = ROUND_DELTAS
+        .may_load(storage, &round.to_be_bytes())?
+        .unwrap_or_default();
+    action(&mut delta);
+    ROUND_DELTAS.save(storage, &round.to_be_bytes(), &delta)?;
+    Ok(())
+}
+
+/// Account an amount sold in the round stats, with the jackpot and the fees it funds
+pub fn add_sales(lottery_stats: &mut LotteryStats, amount: Uint128, config: &Config) {
+    lottery_stats.total_collected = lottery_stats.total_collected.checked_add(amount).unwrap();
+    lottery_stats.jackpot = lottery_stats
+        .jackpot
+        .checked_add(amount.mul(config.jackpot_share))
+        .unwrap();
+    if config.fee_model.on_sales() {
+        lottery_stats.collector_fee = lottery_stats
+            .collector_fee
+            .checked_add(amount.mul(config.fee_collector))
+            .unwrap();
+        lottery_stats.terrand_fee = lottery_stats
+            .terrand_fee
+            .checked_add(amount.mul(config.fee_collector_terrand))
+            .unwrap();
+    }
+}
+
+/// Remove an amount sold from the round stats, the config may have changed since the sale
+pub fn remove_sales(lottery_stats: &mut LotteryStats, amount: Uint128, config: &Config) {
+    lottery_stats.total_collected = lottery_stats.total_collected.checked_sub(amount).unwrap();
+    lottery_stats.jackpot = lottery_stats
+        .jackpot
+        .saturating_sub(amount.mul(config.jackpot_share));
+    if config.fee_model.on_sales() {
+        lottery_stats.collector_fee = lottery_stats
+            .collector_fee
+            .saturating_sub(amount.mul(config.fee_collector));
+        lottery_stats.terrand_fee = lottery_stats
+            .terrand_fee
+            .saturating_sub(amount.mul(config.fee_collector_terrand));
+    }
+}
+
+/// Apply the round delta when a round opens and account the multi-round tickets still playing
+/// in its stats, their players are counted when bought
+pub fn open_round_stats(
+    storage: &mut dyn Storage,
+    round: u64,
+    config: &Config,
+) -> Result<(), ContractError> {
+    let mut active = ACTIVE_TICKETS.may_load(storage)?.unwrap_or_default();
+    if let Some(delta) = ROUND_DELTAS.may_load(storage, &round.to_be_bytes())? {
+        active.tickets = active.tickets + delta.started_tickets - delta.ended_tickets;
+        active.amount = active
+            .amount
+            .checked_add(delta.started_amount)
+            .unwrap()
+            .checked_sub(delta.ended_amount)
+            .unwrap();
+        ACTIVE_TICKETS.save(storage, &active)?;
+        ROUND_DELTAS.remove(storage, &round.to_be_bytes());
+    }
+    if active.tickets == 0 {
+        return Ok(());
+    }
+
+    LOTTERY_STATS.update(
+        storage,
+        &round.to_be_bytes(),
+        |lottery_stats| -> Result<_, ContractError> {
+            let mut update_lottery_stats = lottery_stats.unwrap_or_default();
+            update_lottery_stats.total_ticket_sold += active.tickets;
+            add_sales(&mut update_lottery_stats, active.amount, config);
+            Ok(update_lottery_stats)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Amount paid per round for a game, derived from the price of its round for the games bought
+/// before it was stored
+pub fn game_price(storage: &dyn Storage, start_round: u64, game: &Game) -> StdResult<Uint128> {
+    if !game.price.is_zero() {
+        return Ok(game.price);
+    }
+    let lottery = LOTTERY_STATE.load(storage, &start_round.to_be_bytes())?;
+    Ok(lottery.ticket_price[0].mul(game.multiplier))
+}
+
+/// Accrue a fee to the balance of its recipient
+pub fn add_fee(
+    storage: &mut dyn Storage,
+    recipient: &CanonicalAddr,
+    amount: Uint128,
+) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+    FEE_BALANCES.update(storage, recipient.as_slice(), |balance| -> StdResult<_> {
+        Ok(balance.unwrap_or_default().checked_add(amount)?)
+    })?;
+    Ok(())
+}
+
+/// Key of the pending referral reward of a game by its end round
+fn pending_referral_key(end_round: u64, start_round: u64, game_key: &[u8]) -> Vec<u8> {
+    [
+        &end_round.to_be_bytes()[..],
+        &start_round.to_be_bytes()[..],
+        game_key,
+    ]
+    .concat()
+}
+
+/// Add the referral reward of a game for every round it plays, from `start_round` to `end_round`
+pub fn add_pending_referral_rewards(
+    storage: &mut dyn Storage,
+    referrer: &CanonicalAddr,
+    start_round: u64,
+    end_round: u64,
+    game_key: &[u8],
+    reward: Uint128,
+) -> StdResult<()> {
+    PENDING_REFERRAL_REWARDS.save(
+        storage,
+        (
+            referrer.as_slice(),
+            &pending_referral_key(end_round, start_round, game_key),
+        ),
+        &PendingReferral {
+            first_round: start_round,
+            reward,
+        },
+    )?;
+    for round in start_round..=end_round {
+        update_referral_rewards(storage, round, |rewards| rewards.checked_add(reward))?;
+    }
+    Ok(())
+}
+
+/// Update the referral rewards withheld from the collector fee of the round
+fn update_referral_rewards(
+    storage: &mut dyn Storage,
+    round: u64,
+    action: impl FnOnce(Uint128) -> Result<Uint128, OverflowError>,
+) -> StdResult<()> {
+    LOTTERY_STATS.update(
+        storage,
+        &round.to_be_bytes(),
+        |lottery_stats| -> StdResult<_> {
+            let mut update_lottery_stats = lottery_stats.unwrap_or_default();
+            update_lottery_stats.referral_rewards = action(update_lottery_stats.referral_rewards)?;
+            Ok(update_lottery_stats)
+        },
+    )?;
+    Ok(())
+}
+
+/// Remove the referral reward of a game from its cancelled rounds, from `first_round` to
+/// `end_round`, none of them is drawn yet
+pub fn remove_pending_referral_rewards(
+    storage: &mut dyn Storage,
+    referrer: &CanonicalAddr,
+    start_round: u64,
+    end_round: u64,
+    game_key: &[u8],
+    first_round: u64,
+) -> StdResult<()> {
+    let key = pending_referral_key(end_round, start_round, game_key);
+    let pending = match PENDING_REFERRAL_REWARDS.may_load(storage, (referrer.as_slice(), &key))? {
+        None => return Ok(()),
+        Some(pending) => pending,
+    };
+    PENDING_REFERRAL_REWARDS.remove(storage, (referrer.as_slice(), &key));
+    // The rounds left are pending until the new end round
+    if first_round > pending.first_round {
+        let key = pending_referral_key(first_round - 1, start_round, game_key);
+        PENDING_REFERRAL_REWARDS.save(storage, (referrer.as_slice(), &key), &pending)?;
+    }
+    for round in first_round..=end_round {
+        update_referral_rewards(storage, round, |rewards| {
+            rewards.checked_sub(pending.reward)
+        })?;
+    }
+    Ok(())
+}
+
+/// Pending referral rewards by key with the rewards they earned in the rounds before `round`,
+/// which are all drawn or aborted, the next `limit` ones after the cursor. The aborted rounds
+/// earn nothing as their tickets are refunded
+pub fn drawn_referral_rewards(
+    storage: &dyn Storage,
+    referrer: &CanonicalAddr,
+    round: u64,
+    start_after: Option<Vec<u8>>,
+    limit: usize,
+) -> StdResult<Vec<(Vec<u8>, PendingReferral, Uint128)>> {
+    let pending = PENDING_REFERRAL_REWARDS
+        .prefix(referrer.as_slice())
+        .range(
+            storage,
+            start_after.map(Bound::Exclusive),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut drawn = vec![];
+    for (key, pending) in pending {
+        // Keys start with the end round
+        let end_round = u64::from_be_bytes(key[..8].try_into().unwrap());
+        let mut earned = Uint128::zero();
+        for drawn_round in pending.first_round..round.min(end_round.checked_add(1).unwrap()) {
+            let lottery = LOTTERY_STATE.load(storage, &drawn_round.to_be_bytes())?;
+            if !lottery.cancelled {
+                earned = earned.checked_add(pending.reward)?;
+            }
         }
+        drawn.push((key, pending, earned));
+    }
+    Ok(drawn)
+}
+
+/// Accrue a fee to the fee collector, split between the fee recipients
+pub fn add_collector_fee(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: Uint128,
+) -> StdResult<()> {
+    if config.fee_recipients.is_empty() {
+        return add_fee(storage, &config.fee_collector_address, amount);
+    }
+
+    // Split by share of fee_collector, the rounding dust goes to the last recipient
+    let mut remaining = amount;
+    for (i, recipient) in config.fee_recipients.iter().enumerate() {
+        let part = if i == config.fee_recipients.len() - 1 {
+            remaining
+        } else {
+            amount
+                .multiply_ratio(
+                    recipient.share.numerator(),
+                    config.fee_collector.numerator(),
+                )
+                .min(remaining)
+        };
+        remaining = remaining.checked_sub(part)?;
+        add_fee(storage, &recipient.address, part)?;
+    }
+    Ok(())
+}
+
+/// Prize multiplier of the ticket price paid
+pub fn ticket_multiplier(
+    lottery: &LotteryState,
+    multiplier: Uint128,
+) -> Result<Decimal, ContractError> {
+    match multiplier.u128() {
+        1_000_000 => Ok(lottery.multiplier[0]),
+        2_000_000 => Ok(lottery.multiplier[1]),
+        5_000_000 => Ok(lottery.multiplier[2]),
+        _ => Err(ContractError::ErrorTierDetermination {}),
+    }
+}
+
+/// Key of a game within its round, as returned by a range over `GAMES.sub_prefix(round)`
+pub fn game_key(address_raw: &[u8], game_id: u64) -> Vec<u8> {
+    (address_raw, &game_id.to_be_bytes()[..]).joined_key()
+}
+
+/// Split a game key back into the player and the game id
+pub fn split_game_key(game_key: &[u8]) -> (&[u8], &[u8]) {
+    // The player is length prefixed, the game id is the last 8 bytes
+    game_key[2..].split_at(game_key.len() - 10)
+}
+
+/// Game with its quick-pick numbers once the round is drawn, other games are left unchanged
+pub fn drawn_game(
+    mut game: Game,
+    game_key: &[u8],
+    lottery: &LotteryState,
+    set_of_balls: u8,
+) -> Game {
+    if game.quick_pick && game.number.is_empty() {
+        if let Some(randomness) = &lottery.randomness {
+            let (number, bonus) = quick_pick_numbers(randomness, game_key, set_of_balls);
+            game.number = number;
+            game.bonus = bonus;
+        }
+    }
+    game
+}
+
+/// Prize won by a game before fees. In pari-mutuel mode each tier shares its part of the round
+/// total collected equally between its winners, and the top tier shares the round jackpot
+/// equally between its winners when the jackpot is progressive. None while it can't be known,
+/// the lottery not being drawn or its winners not being counted yet
+pub fn ticket_prize(
+    game: &Game,
+    lottery: &LotteryState,
+    lottery_stats: &LotteryStats,
+    set_of_balls: u8,
+) -> Option<Uint128> {
+    // Tickets of an aborted round are refunded instead
+    if lottery.cancelled {
+        return Some(Uint128::zero());
+    }
+    let draw = Draw::of(lottery)?;
+    let rules = Rules::new(set_of_balls, lottery);
+    let tier = match evaluate(&Ticket::from(game), &draw, &rules) {
+        Outcome::Lost => return Some(Uint128::zero()),
+        Outcome::Won {
+            prize: Some(prize), ..
+        } => return Some(prize),
+        Outcome::Won { tier, prize: None } => tier,
+    };
+    let top_tier = lottery.prize_rank.len() - 1;
+    let progressive = tier == top_tier && !lottery.jackpot_share.is_zero();
+    if !lottery.resolved {
+        return None;
+    }
+
+    let mut pool = match lottery.prize_mode {
+        PrizeMode::Fixed => Uint128::zero(),
+        PrizeMode::PariMutuel => lottery_stats.total_collected.mul(lottery.prize_share[tier]),
+    };
+    if progressive {
+        pool = pool
+            .checked_add(lottery.jackpot)
+            .ok()?
+            .checked_add(lottery_stats.jackpot)
+            .ok()?;
+    }
+
+    pool.checked_div(Uint128::from(lottery.winners[tier])).ok()
+}
+
+/// Jackpot of a round, rolled over from the previous rounds plus funded by its tickets
+pub fn round_jackpot(
+    storage: &dyn Storage,
+    round: u64,
+    lottery: &LotteryState,
+) -> StdResult<Uint128> {
+    // The jackpot funded by the tickets of an aborted round is refunded with them
+    if lottery.cancelled {
+        return Ok(lottery.jackpot);
     }
+    let funded = LOTTERY_STATS
+        .may_load(storage, &round.to_be_bytes())?
+        .map(|stats| stats.jackpot)
+        .unwrap_or_default();
 
-    count
+    Ok(lottery.jackpot.checked_add(funded)?)
 }
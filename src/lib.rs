@@ -1,9 +1,15 @@
 pub mod contract;
+pub mod engine;
 mod error;
+pub mod events;
 mod helpers;
+mod migrations;
 #[cfg(test)]
+#[allow(dead_code, unused_imports, unused_variables)]
 mod mock_querier;
 pub mod msg;
+#[cfg(test)]
+mod multitest;
 pub mod state;
 mod taxation;
 
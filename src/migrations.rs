@@ -0,0 +1,95 @@
+use crate::state::{
+    LotteryStats, RoundPlayer, GAMES_STATS, LEGACY_LOTTERY_STATS, LOTTERY_STATE, LOTTERY_STATS,
+    ROUND_PLAYERS, STATE,
+};
+use crate::ContractError;
+use cosmwasm_std::{Order, StdResult, Storage};
+use cw_storage_plus::Bound;
+use semver::Version;
+
+type MigrationStep = fn(&mut dyn Storage) -> StdResult<()>;
+
+/// Storage transforms ordered by the contract version introducing the new layout. A step runs
+/// when the stored version is lower than its version and the new version is at least it.
+const MIGRATIONS: &[(&str, MigrationStep)] = &[
+    ("0.2.0", migrate_lottery_stats),
+    ("0.3.0", migrate_lottery_resolution),
+];
+
+/// Run every storage transform between the stored contract version and the new one
+pub fn migrate_storage(
+    storage: &mut dyn Storage,
+    from_version: &Version,
+    to_version: &Version,
+) -> Result<Vec<String>, ContractError> {
+    let mut applied = vec![];
+    for (version, step) in MIGRATIONS {
+        let step_version = Version::parse(version)?;
+        if from_version < &step_version && &step_version <= to_version {
+            step(storage)?;
+            applied.push(version.to_string());
+        }
+    }
+
+    Ok(applied)
+}
+
+/// 0.2.0: Rebuild the unique players index from the games stats and store the lottery stats
+/// without the Option wrappers, counter_player being derived from the index
+fn migrate_lottery_stats(storage: &mut dyn Storage) -> StdResult<()> {
+    let players = GAMES_STATS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (key, game_stats) in players {
+        // Composite keys are length prefixed, split (player, round) back
+        let player_len = u16::from_be_bytes([key[0], key[1]]) as usize;
+        let (player, round) = key[2..].split_at(player_len);
+        // Tickets were bought for a single round before the multi-round tickets
+        let round_player = RoundPlayer {
+            tickets: game_stats.total_ticket as u32,
+        };
+        ROUND_PLAYERS.save(storage, (round, player), &round_player)?;
+    }
+
+    let legacy_stats = LEGACY_LOTTERY_STATS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (round, legacy) in legacy_stats {
+        let counter_player = ROUND_PLAYERS
+            .prefix(&round)
+            .keys(storage, None, None, Order::Ascending)
+            .count() as u64;
+        LOTTERY_STATS.save(
+            storage,
+            &round,
+            &LotteryStats {
+                counter_player,
+                total_ticket_sold: legacy.total_ticket_sold.unwrap_or_default(),
+                total_collected: legacy.total_collected.unwrap_or_default(),
+                ..LotteryStats::default()
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// 0.3.0: Rounds drawn before the progressive jackpot have fixed prizes only, mark them resolved
+/// so the winners counting of the next rounds can start
+fn migrate_lottery_resolution(storage: &mut dyn Storage) -> StdResult<()> {
+    let state = STATE.load(storage)?;
+    let lotteries = LOTTERY_STATE
+        .range(
+            storage,
+            None,
+            Some(Bound::Exclusive(state.round.to_be_bytes().to_vec())),
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<_>>>()?;
+    for (round, mut lottery) in lotteries {
+        lottery.resolved = true;
+        LOTTERY_STATE.save(storage, &round, &lottery)?;
+    }
+
+    Ok(())
+}
@@ -56,6 +56,7 @@ pub struct WasmMockQuerier {
     base: MockQuerier<TerraQueryWrapper>,
     token_owner: TokenOwnerResponse,
     tokens: TokensResponse,
+    randomness: Binary,
 }
 
 impl Querier for WasmMockQuerier {
@@ -81,13 +82,16 @@ impl WasmMockQuerier {
                 println!("{:?}", msg);
                 if contract_addr == &"TERRAND".to_string() {
                     let msg_terrand = terrand::msg::GetRandomResponse {
-                        randomness: Binary::from(
-                            "OdRl+j6PHnN84dy12n4Oq1BrGktD73FW4SKPihxfB9I=".as_bytes(),
-                        ),
+                        randomness: self.randomness.clone(),
                         worker: "worker".to_string(),
                     };
                     return SystemResult::Ok(ContractResult::Ok(to_binary(&msg_terrand).unwrap()));
                 }
+                if contract_addr == "TICKET_NFT" {
+                    return SystemResult::Err(SystemError::NoSuchContract {
+                        addr: contract_addr.clone(),
+                    });
+                }
                 panic!("DO NOT ENTER HERE")
             }
             QueryRequest::Custom(TerraQueryWrapper { route, query_data }) => match query_data {
@@ -115,6 +119,7 @@ impl WasmMockQuerier {
             base,
             token_owner: TokenOwnerResponse::default(),
             tokens: TokensResponse::default(),
+            randomness: Binary::from("OdRl+j6PHnN84dy12n4Oq1BrGktD73FW4SKPihxfB9I=".as_bytes()),
         }
     }
     // configure the mint whitelist mock querier
@@ -125,4 +130,8 @@ impl WasmMockQuerier {
     pub fn set_tokens(&mut self, tokens_asc: Vec<String>, tokens_desc: Vec<String>) {
         self.tokens = TokensResponse::new(tokens_asc, tokens_desc);
     }
+    // configure the randomness returned by terrand
+    pub fn set_randomness(&mut self, randomness: Binary) {
+        self.randomness = randomness;
+    }
 }
@@ -1,7 +1,23 @@
-use cosmwasm_std::{Decimal, Uint128};
+use crate::state::{FeeModel, PrizeMode, TaxMode};
+use cosmwasm_std::{Binary, Decimal, Order, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeRecipientMsg {
+    pub address: String,
+    /// Part of fee_collector paid to this recipient
+    pub share: Decimal,
+}
+
+/// Execute interface of the LoTerra staking contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingExecuteMsg {
+    /// Credit the funds sent to the stakers
+    DepositRewards {},
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub denom: String,
@@ -20,16 +36,41 @@ pub struct InstantiateMsg {
     pub ticket_price: Vec<Uint128>,
     pub multiplier: Vec<Decimal>,
     pub live_round_max: u16,
+    pub jackpot_share: Decimal,
+    pub cancel_fee: Decimal,
+    /// Share of each ticket price paid to the referrer, out of the fee collector share taken on
+    /// the tickets sold
+    pub referral_share: Decimal,
+    /// Take fee_collector and fee_collector_terrand on the tickets sold, the prizes or both
+    pub fee_model: FeeModel,
+    /// Split of the fee collector fees, the shares adding up to fee_collector, all paid to
+    /// fee_collector_address when empty
+    pub fee_recipients: Vec<FeeRecipientMsg>,
+    /// Staking contract receiving its fees through DepositRewards, set as fee_collector_address
+    /// or as one of the fee_recipients
+    pub staking_address: Option<String>,
+    pub tax_mode: TaxMode,
+    /// cw721 contract, with this contract as minter, minting a token per ticket
+    pub ticket_nft: Option<String>,
+    pub prize_mode: PrizeMode,
+    /// Share of the round total_collected per tier of prize_rank in pari-mutuel mode
+    pub prize_share: Vec<Decimal>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     Register {
-        numbers: Vec<u8>,
+        /// Numbers followed by the bonus, None for a quick-pick drawn with the round
+        numbers: Option<Vec<u8>>,
         multiplier: Uint128,
         live_round: u16,
+        /// Player of the ticket when bought for someone else, the sender by default
         address: Option<String>,
+        /// Short message recorded with the ticket
+        memo: Option<String>,
+        /// Earns the referral share of the ticket price
+        referrer: Option<String>,
     },
     Draw {},
     Collect {
@@ -37,6 +78,54 @@ pub enum ExecuteMsg {
         player: String,
         game_id: Vec<u64>,
     },
+    /// Play the same ticket for the next `rounds` rounds, without the live_round_max limit
+    Subscribe {
+        /// Numbers followed by the bonus, None for a quick-pick drawn with each round
+        numbers: Option<Vec<u8>>,
+        multiplier: Uint128,
+        rounds: u64,
+        address: Option<String>,
+    },
+    /// Cancel a ticket for the rounds not drawn yet, refunded minus the cancel fee
+    CancelTicket {
+        start_round: u64,
+        game_id: u64,
+    },
+    /// Stop a subscription and refund its rounds not drawn yet, the round closed to the
+    /// registration is still played
+    CancelSubscription {
+        subscription_id: u64,
+    },
+    /// Send the fees accrued to the sender, as fee collector or terrand worker
+    WithdrawFees {},
+    /// Deposit the fees accrued to the staking contract as rewards of its stakers
+    DistributeFees {},
+    /// Send the referral rewards of the sender, earned from the next `limit` tickets referred
+    /// after the previous claim, from the first ones again once the last ones are reached
+    ClaimReferralRewards {
+        limit: Option<u32>,
+    },
+    /// Abort the open round without a draw, by the owner before its draw time or by anyone once
+    /// terrand is late and the randomness of the round still unknown
+    AbortRound {
+        round: u64,
+    },
+    /// Refund the tickets of the player played in an aborted round, the minted ones to the owners
+    /// of their NFTs. Anyone can claim it
+    ClaimRefund {
+        round: u64,
+        player: String,
+    },
+    /// Count the winners of a drawn round, by batch of games, in round order
+    Resolve {
+        round: u64,
+        limit: Option<u32>,
+    },
+    /// Write the games of the subscriptions playing the open round once its registration is
+    /// closed, by batch, Draw playing the last batch
+    PlaySubscriptions {
+        limit: Option<u32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -48,8 +137,11 @@ pub enum QueryMsg {
     State {},
     /// Get all games from player at round x
     Games {
+        /// Exclusive cursor, the page starts strictly after this game id in the requested order
         start_after: Option<u64>,
         limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
         round: u64,
         player: String,
     },
@@ -57,21 +149,114 @@ pub enum QueryMsg {
     LotteryState { round: u64 },
     /// Query all lotteries
     LotteriesState {
+        /// Exclusive cursor, the page starts strictly after this round in the requested order
         start_after: Option<u64>,
         limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
     },
     /// Query game stats by address
     GameStats {
         player: String,
+        /// Exclusive cursor, the page starts strictly after this round in the requested order
         start_after: Option<u64>,
         limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
     },
     /// Query lottery stats by round
     LotteryStats { round: u64 },
+    /// Query tickets of a player across all rounds, ordered by (round, game_id)
+    PlayerTickets {
+        player: String,
+        /// Exclusive cursor, the page starts strictly after this (round, game_id) in the
+        /// requested order
+        start_after: Option<(u64, u64)>,
+        /// Bounds the tickets scanned, a page filtered by status can hold fewer tickets and
+        /// still have a next cursor
+        limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
+        status: Option<TicketStatus>,
+    },
+    /// Summary of the round currently open, used by frontends home page
+    CurrentRound {},
+    /// Query unique players of a round, including the players of multi-round tickets bought in
+    /// a previous round once the round is open
+    RoundPlayers {
+        round: u64,
+        /// Exclusive cursor, the page starts strictly after this player in the requested order
+        start_after: Option<String>,
+        limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
+    },
+    /// Query tickets bought by a user for other players, ordered by (round, player, game_id)
+    Gifts {
+        buyer: String,
+        /// Exclusive cursor, the page starts strictly after this (round, player, game_id) in the
+        /// requested order
+        start_after: Option<(u64, String, u64)>,
+        limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
+    },
+    /// Query fees accrued to a fee collector or a terrand worker, not withdrawn yet
+    FeeBalance { address: String },
+    /// Query rewards earned by a referrer
+    ReferralRewards { referrer: String },
+    /// Query players who registered with a referrer
+    ReferredPlayers {
+        referrer: String,
+        /// Exclusive cursor, the page starts strictly after this player in the requested order
+        start_after: Option<String>,
+        limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
+    },
+    /// Query active subscriptions of a player
+    Subscriptions {
+        player: String,
+        /// Exclusive cursor, the page starts strictly after this subscription id in the
+        /// requested order
+        start_after: Option<u64>,
+        limit: Option<u32>,
+        /// Ascending by default
+        order: Option<OrderBy>,
+    },
     // /// Get a game from player
     // GameStats {round: u64, player: String}
 }
 
+/// Order of the list queries, the next page is requested by passing `next_cursor` of the
+/// response as `start_after` with the same order
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBy {
+    Asc,
+    Desc,
+}
+
+impl From<OrderBy> for Order {
+    fn from(order: OrderBy) -> Self {
+        match order {
+            OrderBy::Asc => Order::Ascending,
+            OrderBy::Desc => Order::Descending,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketStatus {
+    /// The round is not drawn yet
+    Unresolved,
+    /// The round is drawn and the ticket won a prize
+    Won,
+    /// The round is drawn and the ticket won nothing
+    Lost,
+}
+
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ConfigResponse {
@@ -81,6 +266,15 @@ pub struct ConfigResponse {
     pub fee_collector_address: String,
     pub fee_collector_terrand: Decimal,
     pub fee_collector_terrand_address: String,
+    pub jackpot_share: Decimal,
+    pub cancel_fee: Decimal,
+    pub referral_share: Decimal,
+    pub fee_model: FeeModel,
+    pub fee_recipients: Vec<FeeRecipientMsg>,
+    pub staking_address: Option<String>,
+    pub tax_mode: TaxMode,
+    pub ticket_nft: Option<String>,
+    pub owner: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -95,6 +289,8 @@ pub struct StateResponse {
     pub prize_rank: Vec<Uint128>,
     pub ticket_price: Vec<Uint128>,
     pub multiplier: Vec<Decimal>,
+    pub prize_mode: PrizeMode,
+    pub prize_share: Vec<Decimal>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -103,10 +299,38 @@ pub struct GameResponse {
     pub bonus: u8,
     pub multiplier: Decimal,
     pub resolved: bool,
+    pub quick_pick: bool,
+    /// Last round played by a multi-round ticket
+    pub end_round: Option<u64>,
+    /// Sender who bought the ticket for the player
+    pub buyer: Option<String>,
+    pub memo: Option<String>,
     pub game_id: u64,
     pub lottery_id: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GamesResponse {
+    pub games: Vec<GameResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PlayerTicketResponse {
+    pub game: GameResponse,
+    pub status: TicketStatus,
+    /// Prize before fees, zero while unresolved
+    pub prize: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PlayerTicketsResponse {
+    pub tickets: Vec<PlayerTicketResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<(u64, u64)>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LotteryResponse {
     pub draw_time: u64,
@@ -117,9 +341,25 @@ pub struct LotteryResponse {
     pub multiplier: Vec<Decimal>,
     pub winning_number: Option<Vec<u8>>,
     pub bonus_number: Option<u8>,
+    pub randomness: Option<Binary>,
+    pub prize_mode: PrizeMode,
+    pub prize_share: Vec<Decimal>,
+    /// Jackpot of the top tier, rolled over plus funded by this round tickets
+    pub jackpot: Uint128,
+    pub jackpot_share: Decimal,
+    pub winners: Vec<u64>,
+    pub resolved: bool,
+    pub cancelled: bool,
     pub lottery_id: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LotteriesResponse {
+    pub lotteries: Vec<LotteryResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct GameStatsResponse {
     pub total_ticket: u64,
@@ -127,14 +367,101 @@ pub struct GameStatsResponse {
     pub game_stats_id: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GamesStatsResponse {
+    pub game_stats: Vec<GameStatsResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LotteryStatsResponse {
-    pub counter_player: Option<u64>,
-    pub total_ticket_sold: Option<u64>,
-    pub total_collected: Option<Uint128>,
+    pub counter_player: u64,
+    pub total_ticket_sold: u64,
+    pub total_collected: Uint128,
+    /// Fees taken on the tickets sold, paid when the round is drawn
+    pub collector_fee: Uint128,
+    pub terrand_fee: Uint128,
     pub lottery_stats_id: u64,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CurrentRoundResponse {
+    pub round: u64,
+    pub draw_time: u64,
+    /// Seconds until draw_time from the current block time, zero once passed
+    pub seconds_remaining: u64,
+    pub registration_open: bool,
+    pub terrand_round: u64,
+    pub total_ticket_sold: u64,
+    pub counter_player: u64,
+    pub total_collected: Uint128,
+    pub jackpot: Uint128,
+    pub ticket_price: Vec<Uint128>,
+    pub multiplier: Vec<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RoundPlayersResponse {
+    pub players: Vec<String>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeBalanceResponse {
+    pub balance: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralRewardsResponse {
+    /// Rewards not claimed yet
+    pub balance: Uint128,
+    pub total_earned: Uint128,
+    /// Rewards of the rounds not drawn yet, earned once they are drawn
+    pub pending: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferredPlayersResponse {
+    pub players: Vec<String>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GiftResponse {
+    pub player: String,
+    pub game: GameResponse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GiftsResponse {
+    pub gifts: Vec<GiftResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<(u64, String, u64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionResponse {
+    pub subscription_id: u64,
+    pub numbers: Option<Vec<u8>>,
+    pub multiplier: Decimal,
+    pub round_price: Uint128,
+    pub start_round: u64,
+    pub remaining_rounds: u64,
+    pub balance: Uint128,
+    /// Sender who subscribed for the player, None when the player subscribed
+    pub buyer: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionsResponse {
+    pub subscriptions: Vec<SubscriptionResponse>,
+    /// Cursor of the next page, None when this page is the last one
+    pub next_cursor: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct MigrateMsg {
     pub update_denom: String,
@@ -147,4 +474,16 @@ pub struct MigrateMsg {
     pub update_ticket_price: Vec<Uint128>,
     pub update_multiplier: Vec<Decimal>,
     pub update_live_round_max: u16,
+    pub update_jackpot_share: Decimal,
+    pub update_cancel_fee: Decimal,
+    pub update_referral_share: Decimal,
+    pub update_fee_model: FeeModel,
+    pub update_fee_recipients: Vec<FeeRecipientMsg>,
+    pub update_staking_address: Option<String>,
+    pub update_tax_mode: TaxMode,
+    /// Can't be changed or cleared once set, the tickets minted stay on it
+    pub update_ticket_nft: Option<String>,
+    pub update_owner: String,
+    pub update_prize_mode: PrizeMode,
+    pub update_prize_share: Vec<Decimal>,
 }
@@ -0,0 +1,662 @@
+use std::str::FromStr;
+
+use cosmwasm_std::testing::{mock_env, MockApi, MockStorage};
+use cosmwasm_std::{
+    coins, from_slice, to_binary, Addr, Binary, ContractResult, Decimal, Deps, DepsMut, Empty, Env,
+    MessageInfo, Querier, QuerierResult, QuerierWrapper, QueryRequest, Response, StdError,
+    StdResult, SystemError, SystemResult, Timestamp, Uint128,
+};
+use cw721::{Cw721QueryMsg, OwnerOfResponse};
+use cw_multi_test::{App, BankKeeper, ContractWrapper, Executor};
+use cw_storage_plus::Item;
+use serde::de::DeserializeOwned;
+use terra_cosmwasm::{TaxCapResponse, TaxRateResponse, TerraQuery, TerraQueryWrapper};
+
+use crate::contract::{execute, instantiate, query, ticket_token_id};
+use crate::msg::{
+    ExecuteMsg, FeeBalanceResponse, InstantiateMsg, LotteryStatsResponse, PlayerTicketsResponse,
+    QueryMsg, StakingExecuteMsg, TicketStatus,
+};
+use crate::state::{FeeModel, PrizeMode, TaxMode};
+use crate::ContractError;
+
+const DRAND_GENESIS_TIME: u64 = 1595431050;
+
+/// Answer the Terra treasury queries multi-test can't route, with the rates of the mock querier
+struct TaxQuerier<'a> {
+    base: QuerierWrapper<'a>,
+}
+
+impl Querier for TaxQuerier<'_> {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let query_data = match from_slice::<QueryRequest<TerraQueryWrapper>>(bin_request) {
+            Ok(QueryRequest::Custom(TerraQueryWrapper { query_data, .. })) => query_data,
+            _ => return self.base.raw_query(bin_request),
+        };
+        match query_data {
+            TerraQuery::TaxRate {} => {
+                SystemResult::Ok(ContractResult::from(to_binary(&TaxRateResponse {
+                    rate: Decimal::percent(1),
+                })))
+            }
+            TerraQuery::TaxCap { .. } => {
+                SystemResult::Ok(ContractResult::from(to_binary(&TaxCapResponse {
+                    cap: Uint128::from(1_000_000u128),
+                })))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "terra".to_string(),
+            }),
+        }
+    }
+}
+
+fn lottery_instantiate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    let DepsMut {
+        storage,
+        api,
+        querier,
+    } = deps;
+    let querier = TaxQuerier { base: querier };
+    let deps = DepsMut {
+        storage,
+        api,
+        querier: QuerierWrapper::new(&querier),
+    };
+    instantiate(deps, env, info, msg)
+}
+
+fn lottery_execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let DepsMut {
+        storage,
+        api,
+        querier,
+    } = deps;
+    let querier = TaxQuerier { base: querier };
+    let deps = DepsMut {
+        storage,
+        api,
+        querier: QuerierWrapper::new(&querier),
+    };
+    execute(deps, env, info, msg)
+}
+
+fn terrand_query(_deps: Deps, _env: Env, msg: terrand::msg::QueryMsg) -> StdResult<Binary> {
+    match msg {
+        terrand::msg::QueryMsg::GetRandomness { .. } => {
+            to_binary(&terrand::msg::GetRandomResponse {
+                randomness: Binary::from("OdRl+j6PHnN84dy12n4Oq1BrGktD73FW4SKPihxfB9I=".as_bytes()),
+                worker: "worker".to_string(),
+            })
+        }
+        _ => Err(StdError::generic_err("not supported by the mock terrand")),
+    }
+}
+
+const STAKING_REWARDS: Item<Uint128> = Item::new("rewards");
+
+/// Stand-in of the staking contract, accumulating the rewards deposited
+fn staking_execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: StakingExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        StakingExecuteMsg::DepositRewards {} => {
+            let amount = info
+                .funds
+                .iter()
+                .filter(|coin| coin.denom == "uusd")
+                .fold(Uint128::zero(), |total, coin| total + coin.amount);
+            STAKING_REWARDS.update(deps.storage, |rewards| -> StdResult<_> {
+                Ok(rewards + amount)
+            })?;
+            Ok(Response::new())
+        }
+    }
+}
+
+fn staking_query(deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+    to_binary(&STAKING_REWARDS.load(deps.storage)?)
+}
+
+fn empty_instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: Empty,
+) -> StdResult<Response> {
+    STAKING_REWARDS.save(deps.storage, &Uint128::zero())?;
+    Ok(Response::new())
+}
+
+fn empty_execute(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: Empty,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn instantiate_msg(terrand: &Addr, staking: &Addr, fee_model: FeeModel) -> InstantiateMsg {
+    InstantiateMsg {
+        denom: "uusd".to_string(),
+        frequency: 300,
+        fee_collector: Decimal::from_str("0.05").unwrap(),
+        fee_collector_address: staking.to_string(),
+        fee_collector_terrand: Decimal::from_str("0.01").unwrap(),
+        terrand_address: terrand.to_string(),
+        set_of_balls: 4,
+        range_min: 1,
+        range_max: 16,
+        bonus_set_of_balls: 1,
+        bonus_range_min: 1,
+        bonus_range_max: 8,
+        prize_rank: vec![
+            Uint128::from(1_000_000u128),
+            Uint128::from(2_000_000u128),
+            Uint128::from(5_000_000u128),
+            Uint128::from(10_000_000u128),
+            Uint128::from(30_000_000u128),
+            Uint128::from(50_000_000u128),
+            Uint128::from(150_000_000u128),
+            Uint128::from(1_000_000_000u128),
+            Uint128::from(10_000_000_000u128),
+        ],
+        ticket_price: vec![
+            Uint128::from(1_000_000u128),
+            Uint128::from(2_000_000u128),
+            Uint128::from(5_000_000u128),
+        ],
+        multiplier: vec![
+            Decimal::from_str("1").unwrap(),
+            Decimal::from_str("2").unwrap(),
+            Decimal::from_str("5").unwrap(),
+        ],
+        live_round_max: 5,
+        jackpot_share: Decimal::zero(),
+        cancel_fee: Decimal::zero(),
+        referral_share: Decimal::zero(),
+        fee_model,
+        fee_recipients: vec![],
+        staking_address: Some(staking.to_string()),
+        tax_mode: TaxMode::Terra,
+        ticket_nft: None,
+        prize_mode: PrizeMode::Fixed,
+        prize_share: vec![],
+    }
+}
+
+struct Suite {
+    app: App,
+    lottery: Addr,
+    staking: Addr,
+    ticket_nft: Option<Addr>,
+}
+
+impl Suite {
+    fn new(fee_model: FeeModel) -> Self {
+        Self::setup(fee_model, false)
+    }
+
+    /// Tickets minted on a cw721-base contract
+    fn with_ticket_nft(fee_model: FeeModel) -> Self {
+        Self::setup(fee_model, true)
+    }
+
+    fn setup(fee_model: FeeModel, with_ticket_nft: bool) -> Self {
+        // Start at the drand genesis, the rounds are scheduled from it
+        let mut block = mock_env().block;
+        block.time = Timestamp::from_seconds(DRAND_GENESIS_TIME);
+        let mut app = App::new(
+            MockApi::default(),
+            block,
+            BankKeeper::new(),
+            MockStorage::new(),
+        );
+        let owner = Addr::unchecked("creator");
+
+        let terrand_id = app.store_code(Box::new(ContractWrapper::new(
+            empty_execute,
+            empty_instantiate,
+            terrand_query,
+        )));
+        let staking_id = app.store_code(Box::new(ContractWrapper::new(
+            staking_execute,
+            empty_instantiate,
+            staking_query,
+        )));
+        let lottery_id = app.store_code(Box::new(ContractWrapper::new(
+            lottery_execute,
+            lottery_instantiate,
+            query,
+        )));
+
+        let terrand = app
+            .instantiate_contract(terrand_id, owner.clone(), &Empty {}, &[], "terrand", None)
+            .unwrap();
+        let staking = app
+            .instantiate_contract(staking_id, owner.clone(), &Empty {}, &[], "staking", None)
+            .unwrap();
+        // The lottery is the minter, instantiated next at the following contract address
+        let lottery_addr = Addr::unchecked("Contract #3");
+        let ticket_nft = with_ticket_nft.then(|| {
+            let nft_id = app.store_code(Box::new(ContractWrapper::new(
+                cw721_base::contract::execute,
+                cw721_base::contract::instantiate,
+                cw721_base::contract::query,
+            )));
+            let msg = cw721_base::msg::InstantiateMsg {
+                name: "LoTerra tickets".to_string(),
+                symbol: "TICKET".to_string(),
+                minter: lottery_addr.to_string(),
+            };
+            app.instantiate_contract(nft_id, owner.clone(), &msg, &[], "tickets", None)
+                .unwrap()
+        });
+        let mut msg = instantiate_msg(&terrand, &staking, fee_model);
+        msg.ticket_nft = ticket_nft.as_ref().map(|address| address.to_string());
+        let lottery = app
+            .instantiate_contract(lottery_id, owner, &msg, &[], "lottery", None)
+            .unwrap();
+        if ticket_nft.is_some() {
+            assert_eq!(lottery, lottery_addr);
+        }
+
+        Suite {
+            app,
+            lottery,
+            staking,
+            ticket_nft,
+        }
+    }
+
+    fn execute(&mut self, sender: &str, msg: &ExecuteMsg, funds: u128) -> Result<(), String> {
+        let funds = if funds == 0 {
+            vec![]
+        } else {
+            coins(funds, "uusd")
+        };
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.lottery.clone(), msg, &funds)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn register(&mut self, player: &str, numbers: Vec<u8>, multiplier: u128, live_round: u16) {
+        let msg = ExecuteMsg::Register {
+            numbers: Some(numbers),
+            multiplier: Uint128::from(multiplier),
+            live_round,
+            address: None,
+            memo: None,
+            referrer: None,
+        };
+        self.execute(player, &msg, multiplier * live_round as u128)
+            .unwrap();
+    }
+
+    fn collect(&mut self, round: u64, player: &str, game_id: Vec<u64>) {
+        let msg = ExecuteMsg::Collect {
+            round,
+            player: player.to_string(),
+            game_id,
+        };
+        self.execute(player, &msg, 0).unwrap();
+    }
+
+    /// Move to the next draw time, the blocks being produced every 5 seconds
+    fn advance(&mut self, seconds: u64) {
+        self.app.update_block(|block| {
+            block.time = block.time.plus_seconds(seconds);
+            block.height += seconds / 5;
+        });
+    }
+
+    fn balance(&self, address: &str) -> Uint128 {
+        self.app
+            .wrap()
+            .query_balance(address, "uusd")
+            .unwrap()
+            .amount
+    }
+
+    fn query<T: DeserializeOwned>(&self, msg: &QueryMsg) -> T {
+        self.app
+            .wrap()
+            .query_wasm_smart(&self.lottery, msg)
+            .unwrap()
+    }
+
+    fn ticket_owner(&self, round: u64, player: &str, game_id: u64) -> String {
+        let owner: OwnerOfResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                self.ticket_nft.as_ref().unwrap(),
+                &Cw721QueryMsg::OwnerOf {
+                    token_id: ticket_token_id(round, player, game_id),
+                    include_expired: None,
+                },
+            )
+            .unwrap();
+        owner.owner
+    }
+
+    fn fee_balance(&self, address: &str) -> Uint128 {
+        let fees: FeeBalanceResponse = self.query(&QueryMsg::FeeBalance {
+            address: address.to_string(),
+        });
+        fees.balance
+    }
+
+    /// The contract holds the funding and the sales less what it paid, and covers the fees
+    /// accrued but not withdrawn yet
+    fn assert_balance(&self, funding: Uint128, sales: Uint128, prizes: Uint128) {
+        let staking = self.staking.to_string();
+        let fees_paid = self.balance("worker") + self.balance(&staking);
+        let contract_balance = self.balance(self.lottery.as_str());
+        assert_eq!(contract_balance + prizes + fees_paid, funding + sales);
+        assert!(contract_balance >= self.fee_balance(&staking) + self.fee_balance("worker"));
+    }
+}
+
+/// Amount received once the Terra tax of the mock treasury is deducted
+fn after_tax(amount: Uint128) -> Uint128 {
+    let tax = amount - amount.multiply_ratio(100u128, 101u128);
+    amount - tax.min(Uint128::from(1_000_000u128))
+}
+
+#[test]
+fn distribute_fees_to_staking() {
+    let mut suite = Suite::new(FeeModel::OnSales);
+    let staking = suite.staking.to_string();
+    suite
+        .app
+        .init_bank_balance(&Addr::unchecked("alice"), coins(10_000_000, "uusd"))
+        .unwrap();
+
+    // Nothing is deposited before the fees of the round are earned
+    let err = suite
+        .execute("alice", &ExecuteMsg::DistributeFees {}, 0)
+        .unwrap_err();
+    assert_eq!(err, ContractError::NoFeesToWithdraw {}.to_string());
+
+    for _ in 0..2 {
+        suite.register("alice", vec![1, 2, 3, 4, 5], 1_000_000, 1);
+    }
+    suite.advance(300);
+    suite.execute("alice", &ExecuteMsg::Draw {}, 0).unwrap();
+    assert_eq!(suite.fee_balance(&staking), Uint128::from(100_000u128));
+
+    // Anyone can forward the fees, credited to the stakers in the same message
+    suite
+        .execute("alice", &ExecuteMsg::DistributeFees {}, 0)
+        .unwrap();
+    let rewards: Uint128 = suite
+        .app
+        .wrap()
+        .query_wasm_smart(&suite.staking, &Empty {})
+        .unwrap();
+    assert_eq!(rewards, Uint128::from(99_009u128));
+    assert_eq!(suite.balance(&staking), rewards);
+    assert_eq!(suite.fee_balance(&staking), Uint128::zero());
+}
+
+#[test]
+fn lifecycle_over_many_rounds() {
+    let mut suite = Suite::new(FeeModel::OnWinnings);
+    let staking = suite.staking.to_string();
+    let funding = Uint128::from(1_000_000_000u128);
+    suite
+        .app
+        .init_bank_balance(&suite.lottery, coins(funding.u128(), "uusd"))
+        .unwrap();
+    for &player in &["alice", "bob", "carol"] {
+        suite
+            .app
+            .init_bank_balance(&Addr::unchecked(player), coins(100_000_000, "uusd"))
+            .unwrap();
+    }
+
+    // The mock terrand always draws 4 15 6 4 and the bonus 7
+    let mut sales = Uint128::zero();
+    let mut collect_ids = vec![];
+    for round in 0..5u64 {
+        // One match and the bonus wins 5, no match and no bonus loses
+        suite.register("alice", vec![4, 1, 1, 1, 7], 1_000_000, 1);
+        suite.register("carol", vec![1, 1, 1, 1, 1], 2_000_000, 1);
+        sales += Uint128::from(3_000_000u128);
+        // Bob plays two matches, worth 10 per round, for three then two rounds
+        if round == 0 || round == 3 {
+            let live_round = 3 - (round / 3) as u16;
+            suite.register("bob", vec![4, 15, 1, 1, 1], 1_000_000, live_round);
+            sales += Uint128::from(1_000_000u128 * live_round as u128);
+        }
+
+        let err = suite.execute("carol", &ExecuteMsg::Draw {}, 0).unwrap_err();
+        assert_eq!(err, ContractError::LotteryInProgress {}.to_string());
+        suite.advance(300);
+        suite.execute("carol", &ExecuteMsg::Draw {}, 0).unwrap();
+
+        let won = |suite: &Suite, player: &str| {
+            let tickets: PlayerTicketsResponse = suite.query(&QueryMsg::PlayerTickets {
+                player: player.to_string(),
+                start_after: None,
+                limit: None,
+                order: None,
+                status: Some(TicketStatus::Won),
+            });
+            tickets
+                .tickets
+                .into_iter()
+                .filter(|ticket| ticket.game.lottery_id == round)
+                .collect::<Vec<_>>()
+        };
+        let alice_won = won(&suite, "alice");
+        assert_eq!(alice_won.len(), 1);
+        assert_eq!(alice_won[0].prize, Uint128::from(5_000_000u128));
+        collect_ids.push(alice_won[0].game.game_id);
+        assert!(won(&suite, "carol").is_empty());
+
+        suite.assert_balance(funding, sales, Uint128::zero());
+    }
+
+    // Every round sold what was registered for it, a multi-round ticket selling in each round
+    let collected = (0..5u64).fold(Uint128::zero(), |total, round| {
+        let stats: LotteryStatsResponse = suite.query(&QueryMsg::LotteryStats { round });
+        total + stats.total_collected
+    });
+    assert_eq!(collected, sales);
+    // The worker is paid for the randomness of each round drawn
+    assert_eq!(
+        suite.balance("worker"),
+        after_tax(Uint128::from(1_000_000u128)) * Uint128::from(5u128)
+    );
+
+    // The prizes are paid once, less the fees and the tax
+    let net = |prize: u128| {
+        let prize = Uint128::from(prize);
+        after_tax(prize - prize * Decimal::percent(5) - prize * Decimal::percent(1))
+    };
+    let collected = |suite: &mut Suite, round: u64, player: &str, game_id: u64| {
+        let msg = ExecuteMsg::Collect {
+            round,
+            player: player.to_string(),
+            game_id: vec![game_id],
+        };
+        let err = suite.execute(player, &msg, 0).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyCollected {}.to_string());
+    };
+    for (round, game_id) in collect_ids.into_iter().enumerate() {
+        let before = suite.balance("alice");
+        suite.collect(round as u64, "alice", vec![game_id]);
+        assert_eq!(suite.balance("alice"), before + net(5_000_000));
+        collected(&mut suite, round as u64, "alice", game_id);
+    }
+    let before = suite.balance("bob");
+    suite.collect(0, "bob", vec![0]);
+    suite.collect(3, "bob", vec![0]);
+    assert_eq!(
+        suite.balance("bob"),
+        before + net(30_000_000) + net(20_000_000)
+    );
+    collected(&mut suite, 0, "bob", 0);
+    assert_eq!(
+        suite.balance("bob"),
+        before + net(30_000_000) + net(20_000_000)
+    );
+    let before = suite.balance("carol");
+    suite.collect(4, "carol", vec![0]);
+    assert_eq!(suite.balance("carol"), before);
+
+    // 5% of the 75 won goes to the staking contract and 1% to the worker
+    assert_eq!(suite.fee_balance(&staking), Uint128::from(3_750_000u128));
+    assert_eq!(suite.fee_balance("worker"), Uint128::from(750_000u128));
+    suite
+        .execute("worker", &ExecuteMsg::WithdrawFees {}, 0)
+        .unwrap();
+    suite
+        .execute("carol", &ExecuteMsg::DistributeFees {}, 0)
+        .unwrap();
+    assert_eq!(suite.fee_balance(&staking), Uint128::zero());
+    assert_eq!(suite.fee_balance("worker"), Uint128::zero());
+    suite.assert_balance(
+        funding,
+        sales,
+        net(5_000_000) * Uint128::from(5u128) + net(30_000_000) + net(20_000_000),
+    );
+}
+
+#[test]
+fn prizes_follow_the_ticket_nft() {
+    let mut suite = Suite::with_ticket_nft(FeeModel::OnWinnings);
+    let ticket_nft = suite.ticket_nft.clone().unwrap();
+    suite
+        .app
+        .init_bank_balance(&suite.lottery, coins(1_000_000_000, "uusd"))
+        .unwrap();
+    suite
+        .app
+        .init_bank_balance(&Addr::unchecked("alice"), coins(10_000_000, "uusd"))
+        .unwrap();
+
+    // Two matches win 10 and one match with the bonus wins 5
+    suite.register("alice", vec![4, 15, 1, 1, 1], 1_000_000, 1);
+    suite.register("alice", vec![4, 1, 1, 1, 7], 1_000_000, 1);
+    assert_eq!(suite.ticket_owner(0, "alice", 0), "alice");
+    assert_eq!(suite.ticket_owner(0, "alice", 1), "alice");
+
+    // Minted tickets can't be cancelled
+    let err = suite
+        .execute(
+            "alice",
+            &ExecuteMsg::CancelTicket {
+                start_round: 0,
+                game_id: 0,
+            },
+            0,
+        )
+        .unwrap_err();
+    assert_eq!(err, ContractError::TicketNftNotCancellable {}.to_string());
+
+    // Bob buys the first ticket on a secondary market
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked("alice"),
+            ticket_nft,
+            &cw721_base::msg::ExecuteMsg::TransferNft {
+                recipient: "bob".to_string(),
+                token_id: ticket_token_id(0, "alice", 0),
+            },
+            &[],
+        )
+        .unwrap();
+    assert_eq!(suite.ticket_owner(0, "alice", 0), "bob");
+
+    suite.advance(300);
+    suite.execute("carol", &ExecuteMsg::Draw {}, 0).unwrap();
+
+    // Games collected together must have the same owner
+    let err = suite
+        .execute(
+            "carol",
+            &ExecuteMsg::Collect {
+                round: 0,
+                player: "alice".to_string(),
+                game_id: vec![0, 1],
+            },
+            0,
+        )
+        .unwrap_err();
+    assert_eq!(err, ContractError::TicketOwnersDiffer {}.to_string());
+
+    let net = |prize: u128| {
+        let prize = Uint128::from(prize);
+        after_tax(prize - prize * Decimal::percent(5) - prize * Decimal::percent(1))
+    };
+    let alice = suite.balance("alice");
+    suite.collect(0, "alice", vec![0]);
+    assert_eq!(suite.balance("bob"), net(10_000_000));
+    suite.collect(0, "alice", vec![1]);
+    assert_eq!(suite.balance("alice"), alice + net(5_000_000));
+}
+
+#[test]
+fn refunds_follow_the_ticket_nft() {
+    let mut suite = Suite::with_ticket_nft(FeeModel::OnWinnings);
+    let ticket_nft = suite.ticket_nft.clone().unwrap();
+    suite
+        .app
+        .init_bank_balance(&Addr::unchecked("alice"), coins(10_000_000, "uusd"))
+        .unwrap();
+
+    // Bob buys the first ticket, alice keeps the second
+    suite.register("alice", vec![4, 15, 1, 1, 1], 1_000_000, 1);
+    suite.register("alice", vec![4, 1, 1, 1, 7], 2_000_000, 1);
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked("alice"),
+            ticket_nft,
+            &cw721_base::msg::ExecuteMsg::TransferNft {
+                recipient: "bob".to_string(),
+                token_id: ticket_token_id(0, "alice", 0),
+            },
+            &[],
+        )
+        .unwrap();
+
+    suite
+        .execute("creator", &ExecuteMsg::AbortRound { round: 0 }, 0)
+        .unwrap();
+    // Bob claims the refunds of the tickets alice bought, without her
+    let alice = suite.balance("alice");
+    let bob = suite.balance("bob");
+    let msg = ExecuteMsg::ClaimRefund {
+        round: 0,
+        player: "alice".to_string(),
+    };
+    suite.execute("bob", &msg, 0).unwrap();
+    assert_eq!(
+        suite.balance("bob"),
+        bob + after_tax(Uint128::from(1_000_000u128))
+    );
+    assert_eq!(
+        suite.balance("alice"),
+        alice + after_tax(Uint128::from(2_000_000u128))
+    );
+}
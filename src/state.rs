@@ -1,9 +1,64 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{CanonicalAddr, Decimal, Uint128};
+use cosmwasm_std::{Binary, CanonicalAddr, Decimal, Empty, Uint128};
 use cw_storage_plus::{Item, Map};
 
+use crate::engine::{Draw, Rules, Ticket};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeModel {
+    /// fee_collector and fee_collector_terrand are taken from the prizes collected
+    OnWinnings,
+    /// fee_collector and fee_collector_terrand are taken from the tickets sold
+    OnSales,
+    Both,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for FeeModel {
+    fn default() -> Self {
+        FeeModel::OnWinnings
+    }
+}
+
+impl FeeModel {
+    pub fn on_sales(&self) -> bool {
+        matches!(self, FeeModel::OnSales | FeeModel::Both)
+    }
+
+    pub fn on_winnings(&self) -> bool {
+        matches!(self, FeeModel::OnWinnings | FeeModel::Both)
+    }
+}
+
+/// Tax deducted by the chain from the coins the contract sends
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxMode {
+    /// Terra stability tax, rate and cap queried from the treasury
+    Terra,
+    /// No tax, for the chains without the Terra custom querier
+    None,
+    /// Fixed rate without cap
+    Fixed { rate: Decimal },
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for TaxMode {
+    fn default() -> Self {
+        TaxMode::Terra
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeRecipient {
+    pub address: CanonicalAddr,
+    /// Part of fee_collector paid to this recipient
+    pub share: Decimal,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub denom: String,
@@ -13,6 +68,31 @@ pub struct Config {
     pub fee_collector_terrand: Decimal,
     pub terrand_address: CanonicalAddr,
     pub live_round_max: u16,
+    /// Share of each ticket sold funding the progressive jackpot
+    #[serde(default)]
+    pub jackpot_share: Decimal,
+    /// Share of the refund kept when a ticket is cancelled
+    #[serde(default)]
+    pub cancel_fee: Decimal,
+    /// Share of each ticket price paid to the referrer, out of the fee collector share
+    #[serde(default)]
+    pub referral_share: Decimal,
+    #[serde(default)]
+    pub fee_model: FeeModel,
+    /// Split of the fee collector fees, all paid to fee_collector_address when empty
+    #[serde(default)]
+    pub fee_recipients: Vec<FeeRecipient>,
+    /// Staking contract the fees accrued to it are deposited to as rewards
+    #[serde(default)]
+    pub staking_address: Option<CanonicalAddr>,
+    #[serde(default)]
+    pub tax_mode: TaxMode,
+    /// cw721 contract minting a token per ticket, the prizes are paid to the token owner
+    #[serde(default)]
+    pub ticket_nft: Option<CanonicalAddr>,
+    /// Can abort a round, None until set by a migration for the contracts instantiated before
+    #[serde(default)]
+    pub owner: Option<CanonicalAddr>,
 }
 pub const CONFIG: Item<Config> = Item::new("config");
 
@@ -21,6 +101,8 @@ pub struct BallsRange {
     pub min: u8,
     pub max: u8,
 }
+pub use crate::engine::PrizeMode;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
     pub round: u64,
@@ -31,6 +113,11 @@ pub struct State {
     pub prize_rank: Vec<Uint128>,
     pub ticket_price: Vec<Uint128>,
     pub multiplier: Vec<Decimal>,
+    #[serde(default)]
+    pub prize_mode: PrizeMode,
+    /// Share of the round total_collected per tier of prize_rank in pari-mutuel mode
+    #[serde(default)]
+    pub prize_share: Vec<Decimal>,
 }
 pub const STATE: Item<State> = Item::new("state");
 
@@ -44,16 +131,106 @@ pub struct LotteryState {
     pub multiplier: Vec<Decimal>,
     pub winning_number: Option<Vec<u8>>,
     pub bonus_number: Option<u8>,
+    /// Drand randomness of the draw, quick-pick numbers are derived from it
+    #[serde(default)]
+    pub randomness: Option<Binary>,
+    #[serde(default)]
+    pub prize_mode: PrizeMode,
+    #[serde(default)]
+    pub prize_share: Vec<Decimal>,
+    /// Jackpot rolled over from the previous rounds
+    #[serde(default)]
+    pub jackpot: Uint128,
+    /// The top tier is a progressive jackpot when the share is not zero
+    #[serde(default)]
+    pub jackpot_share: Decimal,
+    /// Winners counted per tier of prize_rank
+    #[serde(default)]
+    pub winners: Vec<u64>,
+    /// Last key counted while the winners are being counted, of `GAMES` then of
+    /// `MULTI_ROUND_GAMES`
+    #[serde(default)]
+    pub resolution_cursor: Option<Vec<u8>>,
+    /// Games bought for the round are counted, the multi-round games are counted next
+    #[serde(default)]
+    pub games_counted: bool,
+    /// All winners are counted
+    #[serde(default)]
+    pub resolved: bool,
+    /// The round was aborted without a draw, its tickets are refunded
+    #[serde(default)]
+    pub cancelled: bool,
+    /// Last key of `SUBSCRIPTIONS` played while the subscriptions are played by batch
+    #[serde(default)]
+    pub subscriptions_cursor: Option<Vec<u8>>,
+    /// All the subscriptions playing the round have their game written
+    #[serde(default)]
+    pub subscriptions_played: bool,
 }
 pub const LOTTERY_STATE: Map<&[u8], LotteryState> = Map::new("lottery_state");
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+impl Rules {
+    /// Rules of the round of a lottery
+    pub fn new(set_of_balls: u8, lottery: &LotteryState) -> Self {
+        Rules {
+            set_of_balls,
+            prize_rank: lottery.prize_rank.clone(),
+            prize_mode: lottery.prize_mode.clone(),
+            jackpot_share: lottery.jackpot_share,
+        }
+    }
+}
+
+impl Draw {
+    /// Draw of a lottery, None until it is drawn
+    pub fn of(lottery: &LotteryState) -> Option<Self> {
+        match (&lottery.winning_number, lottery.bonus_number) {
+            (Some(winning_number), Some(bonus_number)) => Some(Draw {
+                winning_number: winning_number.clone(),
+                bonus_number,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
 pub struct LotteryStats {
+    pub counter_player: u64,
+    pub total_ticket_sold: u64,
+    pub total_collected: Uint128,
+    /// Jackpot funded by the tickets sold for this round
+    #[serde(default)]
+    pub jackpot: Uint128,
+    /// Fees taken on the tickets sold for this round, paid when the round is drawn
+    #[serde(default)]
+    pub collector_fee: Uint128,
+    #[serde(default)]
+    pub terrand_fee: Uint128,
+    /// Referral rewards of the tickets playing this round, withheld from its collector fee when
+    /// the round is drawn
+    #[serde(default)]
+    pub referral_rewards: Uint128,
+}
+pub const LOTTERY_STATS: Map<&[u8], LotteryStats> = Map::new("lottery_stats");
+
+/// Layout of `LotteryStats` before the Option wrappers were removed, only used by `migrate`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyLotteryStats {
     pub counter_player: Option<u64>,
     pub total_ticket_sold: Option<u64>,
     pub total_collected: Option<Uint128>,
 }
-pub const LOTTERY_STATS: Map<&[u8], LotteryStats> = Map::new("lottery_stats");
+pub const LEGACY_LOTTERY_STATS: Map<&[u8], LegacyLotteryStats> = Map::new("lottery_stats");
+
+/// Tickets of a player playing a round
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct RoundPlayer {
+    pub tickets: u32,
+}
+/// Players of every round they play keyed by (round, player), the players of the multi-round
+/// tickets are added to all their rounds when bought. counter_player is derived from it
+pub const ROUND_PLAYERS: Map<(&[u8], &[u8]), RoundPlayer> = Map::new("round_players");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Game {
@@ -61,13 +238,134 @@ pub struct Game {
     pub bonus: u8,
     pub multiplier: Decimal,
     pub resolved: bool,
+    /// Numbers left to the draw, they are empty until the round randomness is known
+    #[serde(default)]
+    pub quick_pick: bool,
+    /// Last round played by a multi-round ticket, None when only its round is played
+    #[serde(default)]
+    pub end_round: Option<u64>,
+    /// Last round collected of a multi-round ticket
+    #[serde(default)]
+    pub collected_round: Option<u64>,
+    /// Amount paid per round, zero for the games bought before it was stored
+    #[serde(default)]
+    pub price: Uint128,
+    /// Sender who bought the ticket for the player, None when the player bought it
+    #[serde(default)]
+    pub buyer: Option<CanonicalAddr>,
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Referrer earning `referral_reward` for every round played
+    #[serde(default)]
+    pub referrer: Option<CanonicalAddr>,
+    #[serde(default)]
+    pub referral_reward: Uint128,
+    /// Ticket NFT minted, the games bought before the NFTs were enabled stay with the player
+    #[serde(default)]
+    pub minted: bool,
 }
 #[allow(clippy::type_complexity)]
 pub const GAMES: Map<(&[u8], &[u8], &[u8]), Game> = Map::new("games");
 
+impl From<&Game> for Ticket {
+    fn from(game: &Game) -> Self {
+        Ticket {
+            number: game.number.clone(),
+            bonus: game.bonus,
+            multiplier: game.multiplier,
+        }
+    }
+}
+
+/// Multi-round games by (end_round, start_round followed by the game key), the games still
+/// playing a round they were not bought in are found from its end round on. The key keeps the
+/// end round bought, the game end_round is earlier once its last rounds are cancelled
+pub const MULTI_ROUND_GAMES: Map<(&[u8], &[u8]), Empty> = Map::new("multi_round_games");
+
+/// Tickets bought for someone else by (buyer, round followed by the game key)
+pub const GIFTS: Map<(&[u8], &[u8]), Empty> = Map::new("gifts");
+
+/// Change of the multi-round tickets playing from a round on, applied to its stats when it opens
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct RoundDelta {
+    pub started_tickets: u64,
+    pub ended_tickets: u64,
+    pub started_amount: Uint128,
+    pub ended_amount: Uint128,
+}
+pub const ROUND_DELTAS: Map<&[u8], RoundDelta> = Map::new("round_deltas");
+
+/// Multi-round tickets playing the open round besides the ones bought in it
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ActiveTickets {
+    pub tickets: u64,
+    pub amount: Uint128,
+}
+pub const ACTIVE_TICKETS: Item<ActiveTickets> = Item::new("active_tickets");
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ReferralRewards {
+    /// Rewards not claimed yet
+    pub balance: Uint128,
+    pub total_earned: Uint128,
+    /// Pending rewards key the next claim goes on after, None to start over
+    #[serde(default)]
+    pub claim_cursor: Option<Vec<u8>>,
+}
+/// Rewards per referrer
+pub const REFERRAL_REWARDS: Map<&[u8], ReferralRewards> = Map::new("referral_rewards");
+/// Players who registered with a referrer, keyed by (referrer, player)
+pub const REFERRED_PLAYERS: Map<(&[u8], &[u8]), Empty> = Map::new("referred_players");
+/// Referral reward of a ticket for every round it plays from `first_round` on, the rounds are
+/// earned once drawn and earn nothing when aborted
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingReferral {
+    pub first_round: u64,
+    pub reward: Uint128,
+}
+/// Referral rewards of the tickets not earned yet, keyed by (referrer, end round followed by the
+/// start round and the game key)
+pub const PENDING_REFERRAL_REWARDS: Map<(&[u8], &[u8]), PendingReferral> =
+    Map::new("pending_referral_rewards");
+
+/// Fees accrued per recipient, the fee collector and the terrand workers, until withdrawn
+pub const FEE_BALANCES: Map<&[u8], Uint128> = Map::new("fee_balances");
+
+/// Players who claimed the refund of an aborted round, keyed by (round, player)
+pub const REFUND_CLAIMS: Map<(&[u8], &[u8]), Empty> = Map::new("refund_claims");
+
+/// Tickets bought by a player in a round, keyed by (player, round)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
 pub struct GameStats {
     pub total_ticket: u64,
+    /// Spent on the round by the tickets playing it, the multi-round tickets add their price to
+    /// all their rounds when bought
     pub total_spent: Uint128,
+    /// Earliest round the tickets playing the round were bought in, None for the stats written
+    /// before it was stored
+    #[serde(default)]
+    pub first_round: Option<u64>,
 }
 pub const GAMES_STATS: Map<(&[u8], &[u8]), GameStats> = Map::new("games_stats");
+
+/// Tickets played every round until the prepaid rounds run out, the game of a round is only
+/// written when that round is drawn
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Subscription {
+    /// Numbers followed by the bonus, None for quick-picks
+    pub numbers: Option<Vec<u8>>,
+    pub multiplier: Decimal,
+    /// Amount paid per round
+    pub round_price: Uint128,
+    /// First round played
+    pub start_round: u64,
+    pub remaining_rounds: u64,
+    /// Prepaid amount of the remaining rounds, refunded on cancel
+    pub balance: Uint128,
+    /// Sender who subscribed for the player, None when the player subscribed
+    #[serde(default)]
+    pub buyer: Option<CanonicalAddr>,
+}
+/// Subscriptions keyed by (player, subscription_id)
+pub const SUBSCRIPTIONS: Map<(&[u8], &[u8]), Subscription> = Map::new("subscriptions");
+pub const SUBSCRIPTION_COUNT: Item<u64> = Item::new("subscription_count");
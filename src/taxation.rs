@@ -2,12 +2,25 @@ use cosmwasm_std::{Coin, Decimal, QuerierWrapper, StdResult, Uint128};
 
 use terra_cosmwasm::TerraQuerier;
 
+use crate::state::TaxMode;
+
 static DECIMAL_FRACTION: Uint128 = Uint128::new(1_000_000_000_000_000_000u128);
 
-pub fn compute_tax(querier: &QuerierWrapper, coin: &Coin) -> StdResult<Uint128> {
-    let terra_querier = TerraQuerier::new(querier);
-    let tax_rate: Decimal = (terra_querier.query_tax_rate()?).rate;
-    let tax_cap: Uint128 = (terra_querier.query_tax_cap(coin.denom.to_string())?).cap;
+pub fn compute_tax(
+    querier: &QuerierWrapper,
+    tax_mode: &TaxMode,
+    coin: &Coin,
+) -> StdResult<Uint128> {
+    let (tax_rate, tax_cap) = match tax_mode {
+        TaxMode::None => return Ok(Uint128::zero()),
+        TaxMode::Fixed { rate } => (*rate, coin.amount),
+        TaxMode::Terra => {
+            let terra_querier = TerraQuerier::new(querier);
+            let tax_rate: Decimal = (terra_querier.query_tax_rate()?).rate;
+            let tax_cap: Uint128 = (terra_querier.query_tax_cap(coin.denom.to_string())?).cap;
+            (tax_rate, tax_cap)
+        }
+    };
     Ok(std::cmp::min(
         (coin.amount.checked_sub(coin.amount.multiply_ratio(
             DECIMAL_FRACTION,
@@ -17,8 +30,8 @@ pub fn compute_tax(querier: &QuerierWrapper, coin: &Coin) -> StdResult<Uint128>
     ))
 }
 
-pub fn deduct_tax(querier: &QuerierWrapper, coin: Coin) -> StdResult<Coin> {
-    let tax_amount = compute_tax(querier, &coin)?;
+pub fn deduct_tax(querier: &QuerierWrapper, tax_mode: &TaxMode, coin: Coin) -> StdResult<Coin> {
+    let tax_amount = compute_tax(querier, tax_mode, &coin)?;
     Ok(Coin {
         denom: coin.denom,
         amount: (coin.amount.checked_sub(tax_amount))?,
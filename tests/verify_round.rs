@@ -0,0 +1,64 @@
+use std::process::{Command, Output};
+
+const RANDOMNESS: &str =
+    "4f64526c2b6a3650486e4e3834647931326e344f71314272476b74443733465734534b50696878664239493d";
+const PLAYER: &str = "terra1qypqxpq9qcrsszg2pvxq6rs0zqg3yyc5exk7yu";
+
+fn verify_round(randomness: &str, player: &str) -> Output {
+    let fixture = |name| {
+        format!(
+            "{}/tests/fixtures/verify_round/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    };
+    Command::new(env!("CARGO_BIN_EXE_verify_round"))
+        .args(["--randomness", randomness, player].iter())
+        .args(
+            [
+                fixture("state.json"),
+                fixture("lottery.json"),
+                fixture("games.json"),
+            ]
+            .iter(),
+        )
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn verifies_the_round_with_its_rules() {
+    let output = verify_round(RANDOMNESS, PLAYER);
+    assert!(output.status.success());
+    // The round prize_rank has 9 tiers and a progressive jackpot, the state only 8 tiers
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "round 2 winning numbers [4, 15, 6, 4] bonus 7\n\
+         game 0 [4, 15, 6, 4] bonus 7 x1 tier 8 pool share\n\
+         game 1 [4, 15, 6, 1] bonus 7 x2 tier 6 prize 300000000\n\
+         game 2 [1, 2, 3, 5] bonus 1 x1 lost\n\
+         game 3 [10, 8, 12, 1] bonus 3 x1 lost\n\
+         game 4 doesn't play round 2\n"
+    );
+}
+
+#[test]
+fn rejects_a_beacon_the_contract_did_not_draw() {
+    let output = verify_round(&"00".repeat(32), PLAYER);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "the contract drew [4, 15, 6, 4] bonus 7\n"
+    );
+}
+
+#[test]
+fn rejects_an_invalid_player() {
+    let player = PLAYER.replace("yu", "uy");
+    let output = verify_round(RANDOMNESS, &player);
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("invalid address: {}\n", player)
+    );
+}